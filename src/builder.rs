@@ -0,0 +1,469 @@
+#[cfg(feature = "mock")]
+use crate::MemoryShmap;
+use crate::{
+    errors::ShmapError, format::MAX_DECODE_LIMIT, Clock, EvictionPolicy, Format, KeyHash, Shmap,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Builder for a [`Shmap`] with a custom configuration.
+// Options are independent flags
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Default)]
+pub struct ShmapBuilder {
+    pub(crate) encryption_key: Option<[u8; 32]>,
+    pub(crate) key_ring: Vec<[u8; 32]>,
+    pub(crate) namespace: Option<String>,
+    pub(crate) dir: Option<PathBuf>,
+    pub(crate) lock_dir: Option<PathBuf>,
+    pub(crate) durable_dir: Option<PathBuf>,
+    pub(crate) default_ttl: Option<Duration>,
+    pub(crate) mlock: bool,
+    pub(crate) access_stats: bool,
+    pub(crate) change_log: Option<usize>,
+    pub(crate) max_bytes: Option<usize>,
+    pub(crate) eviction_policy: EvictionPolicy,
+    pub(crate) fallocate: bool,
+    pub(crate) atomic_writes: bool,
+    pub(crate) chunk_size: Option<usize>,
+    pub(crate) quiet: bool,
+    pub(crate) config_check: bool,
+    pub(crate) empty_read_retries: Option<u32>,
+    pub(crate) max_read_size: Option<usize>,
+    pub(crate) decode_limit: Option<usize>,
+    pub(crate) key_hash: KeyHash,
+    pub(crate) raw_keys: bool,
+    pub(crate) empty_values: bool,
+    pub(crate) type_check: bool,
+    pub(crate) creator_pid: bool,
+    pub(crate) format: Format,
+    pub(crate) clock: Option<Arc<dyn Clock>>,
+    pub(crate) sweep_interval: Option<Duration>,
+    pub(crate) clean_on_drop: bool,
+}
+
+impl ShmapBuilder {
+    /// Initialize a builder with the default configuration, i.e. no encryption nor namespace.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Initialize a builder from the environment:
+    ///
+    /// - `SHMAP_DIR`: directory where items are stored, instead of `/dev/shm`.
+    /// - `SHMAP_DEFAULT_TTL_SECS`: TTL of the items inserted without an explicit one.
+    /// - `SHMAP_KEY`: AES256 encryption key, as 32 bytes encoded in base64.
+    ///
+    /// Unset variables keep the default configuration, while malformed ones are an error.
+    pub fn from_env() -> Result<Self, ShmapError> {
+        Self::from_vars(|name| std::env::var_os(name))
+    }
+
+    /// Initialize a builder like [`ShmapBuilder::from_env`], reading the variables with `var`.
+    pub(crate) fn from_vars<F>(var: F) -> Result<Self, ShmapError>
+    where
+        F: Fn(&str) -> Option<OsString>,
+    {
+        let mut builder = Self::new();
+
+        if let Some(dir) = var("SHMAP_DIR") {
+            builder = builder.with_dir(dir);
+        }
+
+        if let Some(ttl) = string_var(&var, "SHMAP_DEFAULT_TTL_SECS")? {
+            let secs = ttl
+                .parse()
+                .map_err(|_| ShmapError::InvalidEnvVar("SHMAP_DEFAULT_TTL_SECS"))?;
+            builder.default_ttl = Some(Duration::from_secs(secs));
+        }
+
+        if let Some(encryption_key) = string_var(&var, "SHMAP_KEY")? {
+            let encryption_key: [u8; 32] = STANDARD
+                .decode(encryption_key)
+                .ok()
+                .and_then(|encryption_key| encryption_key.try_into().ok())
+                .ok_or(ShmapError::InvalidEnvVar("SHMAP_KEY"))?;
+            builder = builder.with_encryption(&encryption_key);
+        }
+
+        Ok(builder)
+    }
+
+    /// Use AES256 encryption key (random bytes).
+    #[must_use]
+    pub const fn with_encryption(mut self, encryption_key: &[u8; 32]) -> Self {
+        self.encryption_key = Some(*encryption_key);
+        self
+    }
+
+    /// Use AES256 encryption key `primary`, like [`ShmapBuilder::with_encryption`], while still
+    /// reading the items encrypted with one of `ring`, e.g. during a key rotation until
+    /// [`Shmap::reencrypt`] migrated them. Keys of the ring are tried in order when an item does
+    /// not decrypt with `primary`, and are never used to write.
+    #[must_use]
+    pub fn with_key_ring(mut self, primary: &[u8; 32], ring: &[[u8; 32]]) -> Self {
+        self.encryption_key = Some(*primary);
+        self.key_ring = ring.to_vec();
+        self
+    }
+
+    /// Isolate items in a namespace: the namespace is part of the shm file names, and only the
+    /// items of the same namespace are listed.
+    ///
+    /// A namespace must be non-empty, and only contain ASCII alphanumeric characters, `-` or `_`.
+    #[must_use]
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_owned());
+        self
+    }
+
+    /// Apply a TTL to all the items inserted with [`Shmap::insert`], so that nothing lives
+    /// forever. [`Shmap::insert_with_ttl`] still overrides it, and [`Shmap::insert_no_expiry`]
+    /// opts out of it.
+    #[must_use]
+    pub const fn with_default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    /// Keep decrypted values out of swap: for encrypted items, the mapped memory is locked with
+    /// `mlock`, and the decrypted or serialized buffers that shmap owns are zeroized after use.
+    ///
+    /// Pages are only locked while they are mapped, i.e. during a get or an insert call, and the
+    /// amount of locked memory is bounded by `RLIMIT_MEMLOCK`: exceeding it makes the call fail
+    /// with [`ShmapError::MlockError`]. Values returned to the caller (e.g. by
+    /// [`Shmap::get_raw`]) are not zeroized, and this has no effect without encryption.
+    #[must_use]
+    pub const fn with_mlock(mut self, mlock: bool) -> Self {
+        self.mlock = mlock;
+        self
+    }
+
+    /// Record access statistics in the metadata of items, on each [`Shmap::get`], so that they can
+    /// be used to implement eviction policies, see [`Shmap::metadata`].
+    ///
+    /// This adds a metadata write on every read.
+    #[must_use]
+    pub const fn with_access_stats(mut self, access_stats: bool) -> Self {
+        self.access_stats = access_stats;
+        self
+    }
+
+    /// Record the keys of the inserted and removed items in a log shared by all the processes
+    /// using the store, so that they can learn which items changed with [`Shmap::changed_since`].
+    ///
+    /// The log only holds the last `capacity` changes, of all namespaces. This adds a write of the
+    /// log, under a lock shared by the whole store, on every insert and remove.
+    #[must_use]
+    pub const fn with_change_log(mut self, capacity: usize) -> Self {
+        self.change_log = Some(capacity);
+        self
+    }
+
+    /// Bound the total size of the item values of the namespace, in bytes: when an insert would
+    /// exceed it, other items are evicted first, following the eviction policy (see
+    /// [`ShmapBuilder::with_eviction_policy`]).
+    ///
+    /// Eviction is best-effort: the total size is computed by listing the directory, without
+    /// locking the whole store, so concurrent inserts from other threads or processes may briefly
+    /// exceed the limit. Metadata and lock files are not accounted.
+    #[must_use]
+    pub const fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Policy choosing which items are evicted first, [`EvictionPolicy::Lru`] by default. It only
+    /// applies with [`ShmapBuilder::with_max_bytes`].
+    #[must_use]
+    pub const fn with_eviction_policy(mut self, eviction_policy: EvictionPolicy) -> Self {
+        self.eviction_policy = eviction_policy;
+        self
+    }
+
+    /// Reserve the space of items with `posix_fallocate` when they are written: a full tmpfs then
+    /// makes the insert fail with [`ShmapError::OutOfSpace`], instead of crashing the process with
+    /// a `SIGBUS` when the value is copied.
+    #[must_use]
+    pub const fn with_fallocate(mut self, fallocate: bool) -> Self {
+        self.fallocate = fallocate;
+        self
+    }
+
+    /// Write each file of an item to a shadow file, then rename it over the previous one, so that
+    /// a process which crashes or fails mid-write never leaves a torn value behind: the file holds
+    /// either its previous or its new content. Shadow files left by a crash are removed by
+    /// [`Shmap::clean`].
+    ///
+    /// A raw item (see [`Shmap::insert_raw`]) whose write fails keeps its previous value, while
+    /// other items are still removed, since their value and metadata could otherwise disagree.
+    /// Mappings of the previous file, e.g. from [`Shmap::open_fd`], keep its content.
+    #[must_use]
+    pub const fn with_atomic_writes(mut self, atomic_writes: bool) -> Self {
+        self.atomic_writes = atomic_writes;
+        self
+    }
+
+    /// Split item values larger than `chunk_size` bytes across several shm files, so that a single
+    /// value does not need one huge mapping. Parts are reassembled transparently on read.
+    ///
+    /// Values inserted with [`Shmap::insert_raw`] have no metadata to record the parts, so they
+    /// are never split. A `chunk_size` of 0 disables splitting.
+    #[must_use]
+    pub const fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Log the normal life of items (expired items and orphan files being removed, eviction) at
+    /// `debug` level instead of `warn`. Genuine errors are still logged at `error` level.
+    #[must_use]
+    pub const fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Refuse to write to a store used with another configuration (e.g. without encryption), see
+    /// [`Shmap::config_fingerprint`]. All the users of the store should enable it, and it is
+    /// better used with a dedicated directory, since `/dev/shm` may be shared by unrelated
+    /// programs.
+    #[must_use]
+    pub const fn with_config_check(mut self, config_check: bool) -> Self {
+        self.config_check = config_check;
+        self
+    }
+
+    /// Number of times an item found empty is read again before it is considered corrupted and
+    /// removed, 3 by default, with a backoff doubling from 1ms between attempts.
+    ///
+    /// Writers truncate the shm file before writing the value to it: a process which does not
+    /// take the item lock (e.g. another program writing the file directly) can thus make it
+    /// appear empty for a short while.
+    #[must_use]
+    pub const fn with_empty_read_retries(mut self, retries: u32) -> Self {
+        self.empty_read_retries = Some(retries);
+        self
+    }
+
+    /// Refuse to read shm files larger than `max_read_size` bytes, failing with
+    /// [`ShmapError::ValueTooLarge`] before anything is allocated, so that a rogue writer can not
+    /// make the process run out of memory. Values split in parts (see
+    /// [`ShmapBuilder::with_chunk_size`]) are checked on their total length.
+    ///
+    /// Encrypted files are checked on their stored size, i.e. with the 28 bytes of the nonce and
+    /// of the tag.
+    #[must_use]
+    pub const fn with_max_read_size(mut self, max_read_size: usize) -> Self {
+        self.max_read_size = Some(max_read_size);
+        self
+    }
+
+    /// Fail to deserialize a `bincode` value with a [`SerializationError::Decode`] as soon as it
+    /// claims more than `decode_limit` bytes, so that a tampered length prefix can not make the
+    /// process allocate a huge buffer. Unlike [`ShmapBuilder::with_max_read_size`], this bounds
+    /// the decoded value, not the shm file. With `Format::GzipJson`, decompression stops past
+    /// `decode_limit` bytes with a `SerializationError::Gzip`, so that a small gzip bomb can not
+    /// exhaust the memory either.
+    ///
+    /// `bincode` only supports constant limits: `decode_limit` is rounded up to a power of two, of
+    /// at least 64 bytes. Limits larger than 2 GiB make [`ShmapBuilder::build`] fail with
+    /// [`ShmapError::InvalidDecodeLimit`].
+    ///
+    /// [`SerializationError::Decode`]: crate::SerializationError::Decode
+    #[must_use]
+    pub const fn with_decode_limit(mut self, decode_limit: usize) -> Self {
+        self.decode_limit = Some(decode_limit);
+        self
+    }
+
+    /// Accept empty values, e.g. inserted with [`Shmap::insert_raw`], instead of considering an
+    /// empty item as corrupted and removing it (after the retries of
+    /// [`ShmapBuilder::with_empty_read_retries`]), which is the default.
+    ///
+    /// Empty items are then returned as is, so an item being written by a process which does not
+    /// take the item lock may be seen empty. Without encryption only, since an encrypted value is
+    /// never empty.
+    #[must_use]
+    pub const fn with_empty_values(mut self, empty_values: bool) -> Self {
+        self.empty_values = empty_values;
+        self
+    }
+
+    /// Record the type of the serialized values in their metadata (its
+    /// [`std::any::type_name`]), and check it when they are deserialized: reading an item as
+    /// another type then fails with [`ShmapError::TypeMismatch`], instead of misinterpreting its
+    /// bytes or failing to decode them.
+    ///
+    /// The same type must be used on both sides, e.g. a value inserted as a `&str` with
+    /// [`Shmap::insert_ref`] is a `str`, not a `String`. Items inserted without type, e.g. raw
+    /// ones or by a Shmap without type checks, are read as any type.
+    #[must_use]
+    pub const fn with_type_check(mut self, type_check: bool) -> Self {
+        self.type_check = type_check;
+        self
+    }
+
+    /// Record the process id of the inserting process in the metadata of the items, so that
+    /// [`Shmap::reap_dead_owners`] removes them once it exited.
+    ///
+    /// Process ids are only meaningful to processes of the same pid namespace, and may be reused
+    /// by a new process, which then keeps the items alive.
+    #[must_use]
+    pub const fn with_creator_pid(mut self, creator_pid: bool) -> Self {
+        self.creator_pid = creator_pid;
+        self
+    }
+
+    /// Encoding of the serialized values, [`Format::Bincode`] by default. Raw values, and the
+    /// metadata, are not affected.
+    ///
+    /// The format is part of the fingerprint of the store (see
+    /// [`ShmapBuilder::with_config_check`]), since values of another format can not be read.
+    #[must_use]
+    pub const fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Hash algorithm turning keys into shm file names, [`KeyHash::Sha224`] by default.
+    ///
+    /// It is part of the file names, so Shmaps with different hashes never see each other's
+    /// items, even in the same directory: all the users of a store must use the same one.
+    ///
+    /// Only SHA-2 hashes are available for now: XXH3, which would be faster for hot keys, is not
+    /// supported yet.
+    #[must_use]
+    pub const fn with_key_hash(mut self, key_hash: KeyHash) -> Self {
+        self.key_hash = key_hash;
+        self
+    }
+
+    /// Use keys as shm file names directly (`shmap.<key>`, or `shmap.<namespace>.<key>`) instead
+    /// of hashing them, for keys which are already short and safe, e.g. fixed ids.
+    ///
+    /// Keys must then be non-empty, only contain ASCII alphanumeric characters, `-` or `_`, and
+    /// not be a name reserved by the store (`config`, `changes`, `metadata`, `lock`, `shadow`,
+    /// `eph`, `tmp` or `part<n>`), otherwise operations fail with
+    /// [`ShmapError::InvalidRawKey`]. A store must not mix raw and hashed keys, since a raw key may
+    /// look like a hash.
+    #[must_use]
+    pub const fn with_raw_keys(mut self, raw_keys: bool) -> Self {
+        self.raw_keys = raw_keys;
+        self
+    }
+
+    /// Use `clock` instead of the system clock for TTL and access statistics, e.g. to expire items
+    /// in tests without sleeping, see [`Clock`].
+    ///
+    /// File modification times, used to spare orphan files for a grace period and by
+    /// [`Shmap::age`], still come from the system. All the users of a store should share the same
+    /// time.
+    #[must_use]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Clean expired items every `interval` from a background thread, so that their memory is
+    /// released even if they are never read again.
+    ///
+    /// The thread is shared by all the clones of the Shmap: it is stopped, and joined, when the
+    /// last one is dropped.
+    #[must_use]
+    pub const fn with_sweep_interval(mut self, interval: Duration) -> Self {
+        self.sweep_interval = Some(interval);
+        self
+    }
+
+    /// Clean expired items once more when the last clone of the Shmap is dropped, after the
+    /// sweeper (see [`ShmapBuilder::with_sweep_interval`]) is stopped.
+    #[must_use]
+    pub const fn with_clean_on_drop(mut self, clean_on_drop: bool) -> Self {
+        self.clean_on_drop = clean_on_drop;
+        self
+    }
+
+    /// Store items in `dir` instead of `/dev/shm`. It should preferably be a tmpfs mount point too.
+    #[must_use]
+    pub fn with_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Create the lock files of the items in `dir` instead of the items directory, e.g. in a
+    /// regular tmpfs path, so that they do not add up to the shm objects. All the users of the
+    /// store must use the same lock directory.
+    #[must_use]
+    pub fn with_lock_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.lock_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Also write every item to `dir`, on a persistent filesystem, so that they survive a reboot:
+    /// the items missing from the Shmap directory (e.g. `/dev/shm` after a reboot) are restored
+    /// from it when the Shmap is built.
+    ///
+    /// Copies are exactly the shm files, so they are encrypted if an encryption key is set, and
+    /// keep their TTL: expired items are removed on restore, with their copies. TTLs are then
+    /// measured on the wall clock only, since the monotonic one restarts at boot. Each write is
+    /// synced to disk, which makes inserts (and reads with
+    /// [`ShmapBuilder::with_access_stats`]) much slower. All the users of the store must use the
+    /// same durable directory, and values written by other means (e.g. to the file returned by
+    /// [`Shmap::insert_raw_named`]) are not copied.
+    #[must_use]
+    pub fn with_durable_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.durable_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Build the Shmap.
+    pub fn build(self) -> Result<Shmap, ShmapError> {
+        self.check()?;
+        Ok(Shmap::_new(self))
+    }
+
+    /// Build an in-memory store instead of a Shmap, with the `mock` feature, see [`MemoryShmap`].
+    #[cfg(feature = "mock")]
+    pub fn build_in_memory(self) -> Result<MemoryShmap, ShmapError> {
+        self.check()?;
+        Ok(MemoryShmap::_new(self))
+    }
+
+    fn check(&self) -> Result<(), ShmapError> {
+        if let Some(namespace) = &self.namespace {
+            if namespace.is_empty()
+                || !namespace
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(ShmapError::InvalidNamespace);
+            }
+        }
+        if self
+            .decode_limit
+            .is_some_and(|decode_limit| decode_limit > MAX_DECODE_LIMIT)
+        {
+            return Err(ShmapError::InvalidDecodeLimit);
+        }
+        Ok(())
+    }
+}
+
+fn string_var<F>(var: &F, name: &'static str) -> Result<Option<String>, ShmapError>
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    var(name)
+        .map(|value| {
+            value
+                .into_string()
+                .map_err(|_| ShmapError::InvalidEnvVar(name))
+        })
+        .transpose()
+}
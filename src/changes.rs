@@ -0,0 +1,53 @@
+//! Log of the last changes of a store, see [`crate::ShmapBuilder::with_change_log`].
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Generation of a store, i.e. its number of changes, and the keys of its last changes.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ChangeLog {
+    pub generation: u64,
+    /// Oldest first.
+    changes: VecDeque<Change>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Change {
+    generation: u64,
+    namespace: Option<String>,
+    key: String,
+}
+
+impl ChangeLog {
+    /// Record a change of `key`, keeping only the last `capacity` changes.
+    pub fn record(&mut self, namespace: Option<&str>, key: &str, capacity: usize) {
+        self.generation += 1;
+        self.changes.push_back(Change {
+            generation: self.generation,
+            namespace: namespace.map(str::to_owned),
+            key: key.to_owned(),
+        });
+        while self.changes.len() > capacity {
+            self.changes.pop_front();
+        }
+    }
+
+    /// Keys of `namespace` changed after `generation`, each once, in the order of their last
+    /// change. A generation ahead of the log, which was then reset, gives all the changes.
+    pub fn changed_since(&self, namespace: Option<&str>, generation: u64) -> Vec<String> {
+        let since = if generation > self.generation {
+            0
+        } else {
+            generation
+        };
+        let mut keys: Vec<String> = Vec::new();
+        for change in &self.changes {
+            if change.generation <= since || change.namespace.as_deref() != namespace {
+                continue;
+            }
+            keys.retain(|key| key != &change.key);
+            keys.push(change.key.clone());
+        }
+        keys
+    }
+}
@@ -0,0 +1,57 @@
+use chrono::{DateTime, Utc};
+use std::{sync::OnceLock, time::Duration};
+
+/// Source of the current time for TTL and access statistics, see
+/// [`crate::ShmapBuilder::with_clock`].
+pub trait Clock: Send + Sync {
+    /// Current time.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Current time on the monotonic clock of the machine, as the time elapsed since boot, if
+    /// any. `None` by default.
+    ///
+    /// When available, TTLs are also tracked on it, so that wall-clock steps (e.g. NTP, manual
+    /// changes) neither expire items early nor keep them late. It must be shared by all the
+    /// processes of the machine, like the one of [`SystemClock`].
+    fn monotonic_now(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Clock of the system, the default one.
+///
+/// Its monotonic time is `CLOCK_BOOTTIME` on Linux, i.e. it keeps counting while the machine is
+/// suspended, like the wall clock. There is none on other platforms.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    #[cfg(target_os = "linux")]
+    fn monotonic_now(&self) -> Option<Duration> {
+        let mut time = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: time is a valid timespec to write to
+        if unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &raw mut time) } != 0 {
+            return None;
+        }
+        Some(Duration::new(
+            u64::try_from(time.tv_sec).ok()?,
+            u32::try_from(time.tv_nsec).ok()?,
+        ))
+    }
+}
+
+/// Identifier of the current boot of the machine, which monotonic times are relative to.
+pub fn boot_id() -> Option<u128> {
+    static BOOT_ID: OnceLock<Option<u128>> = OnceLock::new();
+    *BOOT_ID.get_or_init(|| {
+        let boot_id = std::fs::read_to_string("/proc/sys/kernel/random/boot_id").ok()?;
+        u128::from_str_radix(&boot_id.trim().replace('-', ""), 16).ok()
+    })
+}
@@ -0,0 +1,77 @@
+use crate::{errors::ShmapError, Shmap};
+use std::path::PathBuf;
+
+/// Handle on an `i64` counter item, returned by [`Shmap::counter`], which caches the names of
+/// its shm file and lock so that they are not computed again on each operation.
+///
+/// Each operation runs under the item lock, so that concurrent updates, from other threads or
+/// processes, are never lost. An absent or expired counter is 0, created with the default TTL if
+/// any on its first update, while an existing one keeps its metadata and thus its expiration.
+/// Arithmetic wraps around on overflow, like [`std::sync::atomic::AtomicI64`].
+pub struct Counter<'a> {
+    shmap: &'a Shmap,
+    key: String,
+    sanitized_key: String,
+    lock_path: PathBuf,
+}
+
+impl<'a> Counter<'a> {
+    pub(crate) fn new(
+        shmap: &'a Shmap,
+        key: &str,
+        sanitized_key: String,
+        lock_path: PathBuf,
+    ) -> Self {
+        Self {
+            shmap,
+            key: key.to_owned(),
+            sanitized_key,
+            lock_path,
+        }
+    }
+
+    /// Key of the counter item.
+    #[must_use]
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Current value of the counter.
+    pub fn get(&self) -> Result<i64, ShmapError> {
+        self.shmap
+            .read_counter(&self.sanitized_key, &self.lock_path)
+    }
+
+    /// Add 1 to the counter, and return its new value.
+    pub fn inc(&self) -> Result<i64, ShmapError> {
+        self.add(1)
+    }
+
+    /// Subtract 1 from the counter, and return its new value.
+    pub fn dec(&self) -> Result<i64, ShmapError> {
+        self.add(-1)
+    }
+
+    /// Add `delta` to the counter, and return its new value.
+    pub fn add(&self, delta: i64) -> Result<i64, ShmapError> {
+        self.update(|value| {
+            *value = value.wrapping_add(delta);
+            *value
+        })
+    }
+
+    /// Set the counter to `value`.
+    pub fn set(&self, value: i64) -> Result<(), ShmapError> {
+        self.update(|current| *current = value)
+    }
+
+    /// Set the counter to 0, and return its previous value, e.g. to collect a metric.
+    pub fn reset(&self) -> Result<i64, ShmapError> {
+        self.update(std::mem::take)
+    }
+
+    fn update<R>(&self, f: impl FnOnce(&mut i64) -> R) -> Result<R, ShmapError> {
+        self.shmap
+            .update_value(&self.key, &self.sanitized_key, &self.lock_path, true, f)
+    }
+}
@@ -5,6 +5,9 @@ pub enum ShmapError {
     #[error("shm file not found")]
     ShmFileNotFound,
 
+    #[error("shm file already exists")]
+    ShmFileExists,
+
     #[error("CStringNulError: {}", _0)]
     CStringNulError(#[from] std::ffi::NulError),
 
@@ -14,15 +17,147 @@ pub enum ShmapError {
     #[error("BincodeEncodeError: {}", _0)]
     BincodeEncodeError(#[from] bincode::error::EncodeError),
 
+    #[error("SerializationError for item <{key}>: {source}")]
+    Serialization {
+        key: String,
+        #[source]
+        source: SerializationError,
+    },
+
     #[error("IO Error: {}", _0)]
     IOError(#[from] std::io::Error),
 
-    #[error("NamedLockError: {}", _0)]
-    NamedLockError(#[from] named_lock::Error),
+    #[error("{syscall} failed: {}", describe_errno(source))]
+    SyscallError {
+        syscall: &'static str,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("MlockError: {}", _0)]
+    MlockError(std::io::Error),
 
     #[error("DurationOutOfRangeError")]
     DurationOutOfRangeError,
 
+    #[error("key name too long")]
+    KeyNameTooLong,
+
+    #[error("invalid raw key")]
+    InvalidRawKey,
+
+    #[error("invalid namespace")]
+    InvalidNamespace,
+
+    #[error("decode limit larger than {} bytes", crate::format::MAX_DECODE_LIMIT)]
+    InvalidDecodeLimit,
+
+    #[error("invalid environment variable {}", _0)]
+    InvalidEnvVar(&'static str),
+
+    #[error("value too large")]
+    ValueTooLarge,
+
+    #[error("associated data mismatch")]
+    AadMismatch,
+
+    #[error("type mismatch: expected {expected}, found {found}")]
+    TypeMismatch { expected: String, found: String },
+
+    #[error("no space left to allocate the item")]
+    OutOfSpace,
+
+    #[error("store configuration mismatch: fingerprint {found:#018x} instead of {expected:#018x}")]
+    ConfigMismatch { expected: u64, found: u64 },
+
+    #[error("transaction interrupted after committing {} items: {source}", committed.len())]
+    PartialTransaction {
+        /// Keys of the items written before the failure.
+        committed: Vec<String>,
+        #[source]
+        source: Box<Self>,
+    },
+
+    #[error("item is not encrypted")]
+    NotEncrypted,
+
+    #[error("item is encrypted with its own key")]
+    Encrypted,
+
+    #[error("too many open files")]
+    TooManyOpenFiles,
+
     #[error("AesGcmError: {}", _0)]
     AesGcmError(#[from] aes_gcm::Error),
 }
+
+impl ShmapError {
+    /// Raw `errno` of the underlying OS error, if any.
+    #[must_use]
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self {
+            Self::IOError(e) | Self::MlockError(e) | Self::SyscallError { source: e, .. } => {
+                e.raw_os_error()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Symbolic name of the errno of `err` if known (e.g. `ENOSPC`), followed by its description.
+fn describe_errno(err: &std::io::Error) -> String {
+    err.raw_os_error()
+        .and_then(errno_name)
+        .map_or_else(|| err.to_string(), |name| format!("{name}: {err}"))
+}
+
+const fn errno_name(errno: i32) -> Option<&'static str> {
+    Some(match errno {
+        libc::EPERM => "EPERM",
+        libc::ENOENT => "ENOENT",
+        libc::EINTR => "EINTR",
+        libc::EIO => "EIO",
+        libc::EBADF => "EBADF",
+        libc::EAGAIN => "EAGAIN",
+        libc::ENOMEM => "ENOMEM",
+        libc::EACCES => "EACCES",
+        libc::EEXIST => "EEXIST",
+        libc::ENOTDIR => "ENOTDIR",
+        libc::EISDIR => "EISDIR",
+        libc::EINVAL => "EINVAL",
+        libc::ENFILE => "ENFILE",
+        libc::EMFILE => "EMFILE",
+        libc::EFBIG => "EFBIG",
+        libc::ENOSPC => "ENOSPC",
+        libc::EROFS => "EROFS",
+        libc::ENAMETOOLONG => "ENAMETOOLONG",
+        libc::ENOLCK => "ENOLCK",
+        libc::ELOOP => "ELOOP",
+        libc::EDQUOT => "EDQUOT",
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum SerializationError {
+    #[error("BincodeDecodeError on {len} bytes: {source}")]
+    Decode {
+        len: usize,
+        #[source]
+        source: bincode::error::DecodeError,
+    },
+
+    #[error("BincodeEncodeError: {}", _0)]
+    Encode(bincode::error::EncodeError),
+
+    #[error("InvalidMetadata: {}", _0)]
+    InvalidMetadata(&'static str),
+
+    #[cfg(feature = "gzip")]
+    #[error("JsonError: {}", _0)]
+    Json(serde_json::Error),
+
+    #[cfg(feature = "gzip")]
+    #[error("GzipError: {}", _0)]
+    Gzip(std::io::Error),
+}
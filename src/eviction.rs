@@ -0,0 +1,54 @@
+use crate::metadata::Metadata;
+use chrono::{DateTime, Utc};
+
+/// Policy choosing which items are evicted first when the size limit set with
+/// [`crate::ShmapBuilder::with_max_bytes`] is reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Least recently used items first. Without access statistics, this is the least recently
+    /// inserted.
+    #[default]
+    Lru,
+    /// Least frequently used items first, then the least recently used. Needs access statistics,
+    /// see [`crate::ShmapBuilder::with_access_stats`], otherwise this behaves like [`Self::Lru`].
+    Lfu,
+    /// Items which expire the soonest first, then the ones without expiration, in least recently
+    /// used order.
+    Ttl,
+}
+
+/// An item which may be evicted.
+pub struct Candidate {
+    pub sanitized_key: String,
+    pub size: u64,
+    pub metadata: Metadata,
+    /// Last modification of the item file, used when there is no last access.
+    pub modified: DateTime<Utc>,
+}
+
+impl Candidate {
+    fn last_used(&self) -> DateTime<Utc> {
+        self.metadata.last_access.unwrap_or(self.modified)
+    }
+}
+
+impl EvictionPolicy {
+    /// Sort candidates so that the first ones are evicted first.
+    pub(crate) fn sort(self, candidates: &mut [Candidate]) {
+        match self {
+            Self::Lru => candidates.sort_by_key(Candidate::last_used),
+            Self::Lfu => {
+                candidates.sort_by_key(|candidate| {
+                    (candidate.metadata.access_count, candidate.last_used())
+                });
+            }
+            Self::Ttl => candidates.sort_by_key(|candidate| {
+                (
+                    candidate.metadata.expiration.is_none(),
+                    candidate.metadata.expiration,
+                    candidate.last_used(),
+                )
+            }),
+        }
+    }
+}
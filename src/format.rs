@@ -0,0 +1,120 @@
+use bincode::error::DecodeError;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::errors::SerializationError;
+
+/// Encoding of the values serialized by a Shmap, see [`crate::ShmapBuilder::with_format`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// `bincode`, with its standard configuration.
+    #[default]
+    Bincode,
+    /// JSON, in a gzip stream, with the `gzip` feature: without encryption, value files can be
+    /// read with `zcat` or any other gzip decoder.
+    #[cfg(feature = "gzip")]
+    GzipJson,
+}
+
+impl Format {
+    /// Name of the format in the fingerprint of the store, see
+    /// [`crate::Shmap::config_fingerprint`].
+    pub(crate) const fn name(self) -> &'static str {
+        match self {
+            Self::Bincode => "bincode-standard",
+            #[cfg(feature = "gzip")]
+            Self::GzipJson => "gzip-json",
+        }
+    }
+
+    pub(crate) fn encode<T>(self, value: &T) -> Result<Vec<u8>, SerializationError>
+    where
+        T: Serialize + ?Sized,
+    {
+        match self {
+            Self::Bincode => bincode::serde::encode_to_vec(value, bincode::config::standard())
+                .map_err(SerializationError::Encode),
+            #[cfg(feature = "gzip")]
+            Self::GzipJson => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                serde_json::to_writer(&mut encoder, value).map_err(SerializationError::Json)?;
+                encoder.finish().map_err(SerializationError::Gzip)
+            }
+        }
+    }
+
+    /// Decode a value. With `limit`, a `bincode` value which claims more bytes fails with
+    /// [`bincode::error::DecodeError::LimitExceeded`], and a gzip stream which decompresses to
+    /// more bytes with `SerializationError::Gzip`, see
+    /// [`crate::ShmapBuilder::with_decode_limit`].
+    pub(crate) fn decode<T>(
+        self,
+        bytes: &[u8],
+        limit: Option<usize>,
+    ) -> Result<T, SerializationError>
+    where
+        T: DeserializeOwned,
+    {
+        match self {
+            Self::Bincode => {
+                let (value, _) =
+                    decode_bincode(bytes, limit).map_err(|e| SerializationError::Decode {
+                        len: bytes.len(),
+                        source: e,
+                    })?;
+                Ok(value)
+            }
+            #[cfg(feature = "gzip")]
+            Self::GzipJson => {
+                use std::io::Read;
+
+                let limit =
+                    limit.map_or(u64::MAX, |limit| u64::try_from(limit).unwrap_or(u64::MAX));
+                // One more byte, to tell a value of `limit` bytes from a larger one
+                let mut decoder = flate2::read::GzDecoder::new(bytes).take(limit.saturating_add(1));
+                let ret = serde_json::from_reader(std::io::BufReader::new(&mut decoder));
+                if decoder.limit() == 0 {
+                    return Err(SerializationError::Gzip(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "decompressed value larger than the decode limit",
+                    )));
+                }
+                ret.map_err(|e| {
+                    // Failures to decompress are reported as I/O errors by the JSON reader
+                    if e.is_io() {
+                        SerializationError::Gzip(e.into())
+                    } else {
+                        SerializationError::Json(e)
+                    }
+                })
+            }
+        }
+    }
+}
+
+/// Largest decode limit, see [`crate::ShmapBuilder::with_decode_limit`].
+pub const MAX_DECODE_LIMIT: usize = 1 << 31;
+
+/// Decode a `bincode` value with the smallest of the limits below which is at least `limit`:
+/// `bincode` only takes its limit as a constant. Larger limits are rejected when the Shmap is
+/// built.
+fn decode_bincode<T>(bytes: &[u8], limit: Option<usize>) -> Result<(T, usize), DecodeError>
+where
+    T: DeserializeOwned,
+{
+    let config = bincode::config::standard();
+    macro_rules! decode_with_limits {
+        ($($bits:literal),*) => {
+            match limit {
+                $(Some(limit) if limit <= 1 << $bits => {
+                    bincode::serde::decode_from_slice(bytes, config.with_limit::<{ 1 << $bits }>())
+                })*
+                _ => bincode::serde::decode_from_slice(bytes, config),
+            }
+        };
+    }
+    decode_with_limits!(
+        6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29,
+        30, 31
+    )
+}
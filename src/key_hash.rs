@@ -0,0 +1,33 @@
+use sha2::{Digest, Sha224, Sha256};
+
+/// Hash algorithm turning keys into shm file names, see [`crate::ShmapBuilder::with_key_hash`].
+///
+/// More hashes may be added, e.g. a faster non-cryptographic XXH3.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum KeyHash {
+    /// SHA-224, as 56 hexadecimal characters.
+    #[default]
+    Sha224,
+    /// SHA-256, as 64 hexadecimal characters, for a larger margin against collisions.
+    Sha256,
+}
+
+impl KeyHash {
+    /// Name of the hash in the fingerprint of the store, see
+    /// [`crate::Shmap::config_fingerprint`].
+    pub(crate) const fn name(self) -> &'static str {
+        match self {
+            Self::Sha224 => "sha224",
+            Self::Sha256 => "sha256",
+        }
+    }
+
+    /// Hash of `key`, in lowercase hexadecimal.
+    pub(crate) fn hex_digest(self, key: &[u8]) -> String {
+        match self {
+            Self::Sha224 => format!("{:x}", Sha224::digest(key)),
+            Self::Sha256 => format!("{:x}", Sha256::digest(key)),
+        }
+    }
+}
@@ -6,14 +6,26 @@
 //!
 //! - Items are stored in the linux shared memory: it uses `shm_open` to create file in the ramdisk (/dev/shm), then they are mapped in memory with mmap.
 //!
-//! - Concurrent access to items it provided thanks to `named-lock` mutexes.
+//! - Concurrent access to items it provided thanks to `flock` locks on lock files. Readers of the same item share the lock, while writers get exclusive access.
 //!
 //! - Value serialization can be made transparently with serde (`bincode`), so don't forget to use [serde_bytes](https://crates.io/crates/serde_bytes) to enable optimized handling of `&[u8]` and `Vec<u8>` !
 //!
 //! - You can protect your data with AES256-GCM encryption.
 //!
+//! - All the options (encryption, namespace, directory, default TTL, size limit...) are set with `Shmap::builder()`.
+//!
+//! - You can isolate items in namespaces.
+//!
 //! - You can add a TTL so that your items won't be available anymore after this duration.
 //!
+//! - You can bound the total size of the items, evicting them with an LRU, LFU or TTL policy.
+//!
+//! ## Listing items
+//!
+//! The operations over many items ([`Shmap::keys`], [`Shmap::scan`], [`Shmap::for_each`],
+//! [`Shmap::retain`], the `*_prefix` ones...) read the shm directory as they go, without a snapshot
+//! semantic: items inserted or removed concurrently may or may not be seen.
+//!
 //! ## Example
 //!
 //! ```rust
@@ -38,12 +50,36 @@
 //! }
 //! ```
 
+mod builder;
+mod byte_buf;
+mod changes;
+mod clock;
+mod counter;
 mod errors;
+mod eviction;
+mod format;
+mod key_hash;
+mod lock;
 mod map;
 mod metadata;
+#[cfg(feature = "mock")]
+mod mock;
+mod scoped;
 mod shm;
+mod sweeper;
 #[cfg(test)]
 mod tests;
 
-pub use errors::ShmapError;
-pub use map::Shmap;
+pub use builder::ShmapBuilder;
+pub use clock::{Clock, SystemClock};
+pub use counter::Counter;
+pub use errors::{SerializationError, ShmapError};
+pub use eviction::EvictionPolicy;
+pub use format::Format;
+pub use key_hash::KeyHash;
+pub use map::{CleanReport, GetResult, GetStatus, ReencryptReport, Shmap, VerifyReport};
+pub use metadata::{Metadata, MonotonicDeadline};
+#[cfg(feature = "mock")]
+pub use mock::MemoryShmap;
+pub use scoped::ScopedKey;
+pub use shm::Fd;
@@ -0,0 +1,88 @@
+//! Locks on item lock files.
+//!
+//! Writers take an exclusive `flock` on the lock file, readers a shared one, so they can run
+//! concurrently while still excluding writers. Contenders sleep in the kernel until the lock is
+//! released, rather than polling it, so there is no retry loop to back off.
+//!
+//! A lock file may be unlinked while other threads or processes wait on it (once its item is
+//! removed, see [`crate::Shmap::clean`]), it is then only unlinked under an exclusive lock. A lock
+//! acquired on a file which is no longer the one at its path is thus stale: it is released, and
+//! acquired again on the current file.
+
+use crate::errors::ShmapError;
+use std::{
+    fs::{File, OpenOptions},
+    os::unix::{fs::MetadataExt, io::AsRawFd},
+    path::Path,
+};
+
+/// Shared lock on an item, released on Drop (when the underlying file is closed).
+#[derive(Debug)]
+pub struct ReadGuard {
+    _file: File,
+}
+
+impl ReadGuard {
+    /// Block until a shared lock is acquired on the lock file at `path`, creating it if needed.
+    pub fn lock(path: &Path) -> Result<Self, ShmapError> {
+        Ok(Self {
+            _file: flock(path, libc::LOCK_SH)?,
+        })
+    }
+}
+
+/// Exclusive lock on an item, released on Drop (when the underlying file is closed).
+#[derive(Debug)]
+pub struct WriteGuard {
+    _file: File,
+}
+
+impl WriteGuard {
+    /// Block until an exclusive lock is acquired on the lock file at `path`, creating it if
+    /// needed.
+    pub fn lock(path: &Path) -> Result<Self, ShmapError> {
+        Ok(Self {
+            _file: flock(path, libc::LOCK_EX)?,
+        })
+    }
+}
+
+/// Open the lock file at `path` and lock it with `operation`, until the locked file is the one
+/// at `path`.
+fn flock(path: &Path, operation: i32) -> Result<File, ShmapError> {
+    loop {
+        // Each guard opens its own file description, as flock locks are bound to it
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        loop {
+            // SAFETY: libc call is unsafe
+            let ret = unsafe { libc::flock(file.as_raw_fd(), operation) };
+            if ret == 0 {
+                break;
+            }
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                return Err(ShmapError::SyscallError {
+                    syscall: "flock",
+                    source: err,
+                });
+            }
+        }
+        if is_linked(&file, path)? {
+            return Ok(file);
+        }
+    }
+}
+
+/// Whether `file` is still the file at `path`.
+fn is_linked(file: &File, path: &Path) -> Result<bool, ShmapError> {
+    let locked = file.metadata()?;
+    match std::fs::metadata(path) {
+        Ok(current) => Ok(current.dev() == locked.dev() && current.ino() == locked.ino()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(ShmapError::IOError(e)),
+    }
+}
@@ -1,32 +1,105 @@
 use crate::{
-    errors::ShmapError,
-    metadata::Metadata,
+    builder::ShmapBuilder,
+    byte_buf::{ByteBuf, ByteSlice},
+    changes::ChangeLog,
+    counter::Counter,
+    errors::{SerializationError, ShmapError},
+    eviction::Candidate,
+    lock::{ReadGuard, WriteGuard},
+    metadata::{self, Metadata, FORMAT_VERSION},
+    scoped::ScopedKey,
     shm::{self, SHM_DIR},
+    sweeper::Teardown,
+    Clock, EvictionPolicy, Format, KeyHash, SystemClock,
 };
 use aes_gcm::{
-    aead::{generic_array::GenericArray, Aead},
+    aead::{generic_array::GenericArray, Aead, Payload},
     Aes256Gcm, KeyInit, Nonce,
 };
-use chrono::Utc;
-use log::{error, warn};
-use memmap2::{Mmap, MmapMut};
-use named_lock::NamedLock;
+use chrono::{DateTime, Utc};
+use log::{error, log, warn, Level};
+use memmap2::{Advice, Mmap, MmapMut, MmapOptions};
 use rand::{seq::SliceRandom, thread_rng};
 use serde::{de::DeserializeOwned, Serialize};
 use sha2::{Digest, Sha224};
 use std::{
+    collections::{BTreeMap, HashMap},
     fs,
-    path::PathBuf,
-    time::{Duration, SystemTime},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock, PoisonError},
+    time::{Duration, Instant, SystemTime},
 };
+use zeroize::Zeroize;
 
+/// Target of all the logs, which carry the sanitized key of the item (`key`) and the operation
+/// (`op`) as key-values, rather than in the message.
+pub const LOG_TARGET: &str = "shmap";
 const METADATA_SUFFIX: &str = "metadata";
 const SHMAP_PREFIX: &str = "shmap";
 const LOCK_SUFFIX: &str = "lock";
+const PART_SUFFIX: &str = "part";
+const SHADOW_SUFFIX: &str = "shadow";
+/// Suffix of the value file of an item without metadata, see [`Shmap::insert_ephemeral`].
+const EPHEMERAL_SUFFIX: &str = "eph";
+/// Suffix of a durable copy being written, before it is renamed.
+const DURABLE_TMP_SUFFIX: &str = "tmp";
+/// Reserved shm file, holding the configuration fingerprint of the store.
+const CONFIG_KEY: &str = "shmap.config";
+/// Reserved shm file, holding the change log of the store, see [`ShmapBuilder::with_change_log`].
+const CHANGES_KEY: &str = "shmap.changes";
+const WAIT_FOR_MAX_BACKOFF: Duration = Duration::from_millis(100);
+const DEFAULT_EMPTY_READ_RETRIES: u32 = 3;
+/// Age after which [`Shmap::clean`] considers a file without its counterpart as an orphan.
+const ORPHAN_GRACE_PERIOD: Duration = Duration::from_secs(5);
 
+/// Content of an item file read from shm.
+enum Content {
+    Missing,
+    /// The file is empty, which is not a valid item.
+    Empty,
+    Value(Vec<u8>),
+}
+
+// Options are independent flags
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Clone)]
 pub struct Shmap {
     cipher: Option<Aes256Gcm>,
+    /// Ciphers only used to decrypt, after `cipher`.
+    key_ring: Vec<Aes256Gcm>,
+    namespace: Option<String>,
+    dir: PathBuf,
+    lock_dir: PathBuf,
+    durable_dir: Option<PathBuf>,
+    default_ttl: Option<Duration>,
+    mlock: bool,
+    access_stats: bool,
+    change_log: Option<usize>,
+    max_bytes: Option<usize>,
+    eviction_policy: EvictionPolicy,
+    fallocate: bool,
+    atomic_writes: bool,
+    chunk_size: Option<usize>,
+    quiet: bool,
+    config_check: bool,
+    empty_read_retries: u32,
+    max_read_size: Option<usize>,
+    decode_limit: Option<usize>,
+    key_hash: KeyHash,
+    raw_keys: bool,
+    empty_values: bool,
+    type_check: bool,
+    creator_pid: bool,
+    format: Format,
+    clock: Arc<dyn Clock>,
+    /// Fingerprint found in the store, once checked.
+    stored_fingerprint: Arc<OnceLock<u64>>,
+    /// Last file processed by [`Shmap::clean_limited`], if it did not reach the end.
+    clean_cursor: Arc<Mutex<Option<String>>>,
+    /// Background resources, if any, released when the last clone is dropped.
+    teardown: Option<Arc<Teardown>>,
 }
 
 impl Default for Shmap {
@@ -40,366 +113,3729 @@ impl Shmap {
     /// Initialize Shmap with no TTL or encryption.
     #[must_use]
     pub fn new() -> Self {
-        Self::_new(None)
+        Self::_new(ShmapBuilder::new())
     }
 
     /// Initialize Shmap with AES256 encryption key (random bytes).
     #[must_use]
     pub fn new_with_encryption(encryption_key: &[u8; 32]) -> Self {
-        Self::_new(Some(encryption_key))
+        Self::_new(ShmapBuilder::new().with_encryption(encryption_key))
+    }
+
+    /// Start configuring a Shmap, with all the options of [`ShmapBuilder`].
+    #[must_use]
+    pub fn builder() -> ShmapBuilder {
+        ShmapBuilder::new()
+    }
+
+    /// Initialize Shmap from the environment, see [`ShmapBuilder::from_env`].
+    pub fn from_env() -> Result<Self, ShmapError> {
+        ShmapBuilder::from_env()?.build()
     }
 
-    fn _new(encryption_key: Option<&[u8; 32]>) -> Self {
+    pub(crate) fn _new(builder: ShmapBuilder) -> Self {
         if let Err(e) = fdlimit::raise_fd_limit() {
-            warn!("Could not set fd_limit : {e}");
+            warn!(target: LOG_TARGET, op = "init"; "Could not set fd_limit : {e}");
         }
 
         // If an encryption key was provided, create a `cipher` for AES256-GCM
-        let cipher = encryption_key.map(|key| {
-            let key = GenericArray::from_slice(key);
+        let cipher = builder.encryption_key.map(|key| {
+            let key = GenericArray::from_slice(&key);
             Aes256Gcm::new(key)
         });
+        let key_ring = builder
+            .key_ring
+            .iter()
+            .map(|key| Aes256Gcm::new(GenericArray::from_slice(key)))
+            .collect();
 
-        let shmap = Self { cipher };
+        let dir = builder.dir.unwrap_or_else(|| PathBuf::from(SHM_DIR));
+        let mut shmap = Self {
+            cipher,
+            key_ring,
+            namespace: builder.namespace,
+            dir: dir.clone(),
+            lock_dir: builder.lock_dir.unwrap_or(dir),
+            durable_dir: builder.durable_dir,
+            default_ttl: builder.default_ttl,
+            mlock: builder.mlock,
+            access_stats: builder.access_stats,
+            change_log: builder.change_log,
+            max_bytes: builder.max_bytes,
+            eviction_policy: builder.eviction_policy,
+            fallocate: builder.fallocate,
+            atomic_writes: builder.atomic_writes,
+            chunk_size: builder.chunk_size.filter(|&chunk_size| chunk_size > 0),
+            quiet: builder.quiet,
+            config_check: builder.config_check,
+            empty_read_retries: builder
+                .empty_read_retries
+                .unwrap_or(DEFAULT_EMPTY_READ_RETRIES),
+            max_read_size: builder.max_read_size,
+            decode_limit: builder.decode_limit,
+            key_hash: builder.key_hash,
+            raw_keys: builder.raw_keys,
+            empty_values: builder.empty_values,
+            type_check: builder.type_check,
+            creator_pid: builder.creator_pid,
+            format: builder.format,
+            clock: builder.clock.unwrap_or_else(|| Arc::new(SystemClock)),
+            stored_fingerprint: Arc::default(),
+            clean_cursor: Arc::default(),
+            teardown: None,
+        };
+        if let Err(e) = shmap.warm_up() {
+            warn!(target: LOG_TARGET, op = "init"; "Error while restoring durable items: {e}");
+        }
         if let Err(e) = shmap.clean() {
-            warn!("Error while cleaning shmap keys: {}", e);
+            warn!(target: LOG_TARGET, op = "init"; "Error while cleaning shmap keys: {e}");
+        }
+        if builder.sweep_interval.is_some() || builder.clean_on_drop {
+            let teardown = Teardown::new(&shmap, builder.sweep_interval, builder.clean_on_drop);
+            shmap.teardown = Some(Arc::new(teardown));
         }
         shmap
     }
 
+    /// Name of the shm file of an item.
+    ///
+    /// All the files of an item must fit in the file name limit, so that an insert never succeeds
+    /// for the value but fails for its metadata. The check is thus made against the longest
+    /// suffix, which is the metadata one.
+    fn sanitize_key(&self, key: &str) -> Result<String, ShmapError> {
+        self.sanitize_bytes_key(key.as_bytes())
+    }
+
+    /// Name of the shm file of an item with a binary key, see [`Shmap::sanitize_key`]. A UTF-8
+    /// key gives the same name as a string one.
+    fn sanitize_bytes_key(&self, key: &[u8]) -> Result<String, ShmapError> {
+        let sanitized_key = if self.raw_keys {
+            raw_key(self.namespace.as_deref(), key)?
+        } else {
+            sanitize_key_with(self.key_hash, self.namespace.as_deref(), key)
+        };
+        if metadata_key(&sanitized_key).len() > shm::NAME_MAX {
+            return Err(ShmapError::KeyNameTooLong);
+        }
+        Ok(sanitized_key)
+    }
+
     /// Get an item value by its key, and deserialize it (using `bincode`) to T.
     pub fn get<T>(&self, key: &str) -> Result<Option<T>, ShmapError>
     where
         T: DeserializeOwned,
     {
-        let sanitized_key = sanitize_key(key);
+        self.get_aad(key, None)
+    }
 
-        // Remove item if expired
-        let not_found = match self.get_metadata(key)? {
-            Some(metadata) => metadata.expiration.map_or(false, |expiration| {
-                let expired = Utc::now().gt(&expiration);
-                if expired {
-                    warn!("Key <{}> expired, removing", &key);
-                    let _ = self.remove(key);
-                }
-                expired
-            }),
-            None => true,
-        };
-        if not_found {
-            return Ok(None);
+    /// Get an item value by its key, like [`Shmap::get`], panicking if it is absent or can not be
+    /// read. This is meant for tests and trusted internal state, where a missing item is a bug.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the item is absent or expired, or if [`Shmap::get`] fails, with the key in the
+    /// message.
+    #[must_use]
+    #[track_caller]
+    pub fn expect_get<T>(&self, key: &str) -> T
+    where
+        T: DeserializeOwned,
+    {
+        match self.get(key) {
+            Ok(Some(value)) => value,
+            Ok(None) => panic!("shmap item <{key}> not found"),
+            Err(e) => panic!("shmap item <{key}> could not be read: {e}"),
         }
+    }
+
+    /// Get an item value by its key, like [`Shmap::get`], and deserialize it into `out`. Return
+    /// whether a value was read, `out` being left untouched otherwise.
+    ///
+    /// `bincode` cannot deserialize in place, so the value is still decoded into a new `T` and
+    /// moved into `out`: the previous allocations of `out` are not reused.
+    pub fn get_into<T>(&self, key: &str, out: &mut T) -> Result<bool, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        let Some(value) = self.get_aad(key, None)? else {
+            return Ok(false);
+        };
+        *out = value;
+        Ok(true)
+    }
 
-        self.get_deserialize(&sanitized_key)
+    /// Get an item inserted with [`Shmap::insert_with_aad`], which must be given the same
+    /// associated data.
+    pub fn get_with_aad<T>(&self, key: &str, aad: &[u8]) -> Result<Option<T>, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        self.get_aad(key, Some(aad))
     }
 
-    fn get_metadata(&self, key: &str) -> Result<Option<Metadata>, ShmapError> {
-        let sanitized_metadata_key = sanitize_metadata_key(key);
-        self.get_deserialize(&sanitized_metadata_key)
+    /// Get an item value by its key, like [`Shmap::get`], but tell whether a missing value expired.
+    ///
+    /// An item is only reported as [`GetStatus::Expired`] by the call which finds it expired and
+    /// removes it: once removed, by this call or by [`Shmap::clean`], it is [`GetStatus::Missing`].
+    pub fn get_status<T>(&self, key: &str) -> Result<GetStatus<T>, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        self.get_status_aad(key, None)
     }
 
-    fn get_deserialize<T>(&self, sanitized_key: &str) -> Result<Option<T>, ShmapError>
+    /// Get an item value by its key, like [`Shmap::get`], but report an item which can not be
+    /// read, because it is corrupted or of another type, as [`GetResult::Corrupt`] instead of an
+    /// error, and log it, e.g. to treat it as a miss. Other errors, e.g. I/O ones, are returned.
+    ///
+    /// The corrupted item is not removed.
+    pub fn get_result<T>(&self, key: &str) -> Result<GetResult<T>, ShmapError>
     where
         T: DeserializeOwned,
     {
-        match self._get(sanitized_key)? {
-            Some(bytes) => {
-                let (value, _): (T, usize) =
-                    bincode::serde::decode_from_slice(&bytes, bincode::config::standard())?;
-                Ok(Some(value))
+        match self.get(key) {
+            Ok(Some(value)) => Ok(GetResult::Found(value)),
+            Ok(None) => Ok(GetResult::NotFound),
+            Err(
+                e @ (ShmapError::Serialization { .. }
+                | ShmapError::TypeMismatch { .. }
+                | ShmapError::AesGcmError(_)),
+            ) => {
+                warn!(
+                    target: LOG_TARGET,
+                    key = key,
+                    op = "get";
+                    "Item can not be read, maybe corrupted: {e}"
+                );
+                Ok(GetResult::Corrupt(e))
             }
-            None => Ok(None),
+            Err(e) => Err(e),
         }
     }
 
-    /// Get an item by its key, without deserialization, as bytes.
-    pub fn get_raw(&self, key: &str) -> Result<Option<Vec<u8>>, ShmapError> {
-        let sanitized_key = sanitize_key(key);
-        self._get(&sanitized_key)
+    /// Get an item value by a binary key, like [`Shmap::get`]. A UTF-8 key is the same as the
+    /// equivalent string key.
+    pub fn get_bytes_key<T>(&self, key: &[u8]) -> Result<Option<T>, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        let sanitized_key = self.sanitize_bytes_key(key)?;
+        match self.get_status_sanitized(&sanitized_key, None)? {
+            GetStatus::Present(value) => Ok(Some(value)),
+            GetStatus::Expired | GetStatus::Missing => Ok(None),
+        }
     }
 
-    fn _get(&self, sanitized_key: &str) -> Result<Option<Vec<u8>>, ShmapError> {
-        let lock = NamedLock::with_path(
-            PathBuf::from(SHM_DIR).join(
-                sanitized_key
-                    .trim_end_matches(&format!(".{METADATA_SUFFIX}"))
-                    .to_string()
-                    + "."
-                    + LOCK_SUFFIX,
-            ),
-        )?;
-        let guard = lock.lock()?;
-
-        // Read the item from shm
-        let fd = match shm::open_read(sanitized_key) {
-            Ok(fd) => fd,
-            Err(e) => match e {
-                ShmapError::ShmFileNotFound => {
-                    // If the shm returns "file not found", return None
-                    //let _ = self._remove(sanitized_key); // useless
-                    return Ok(None);
-                }
-                e => return Err(e),
-            },
-        };
-        // SAFETY: Mmap call is unsafe
-        let mmap = unsafe { Mmap::map(fd) }?;
-        if mmap.len() == 0 {
-            // If the value is empty, remove it and return None
-            error!("mmap file for item <{}> is empty, removing", sanitized_key);
-            drop(guard);
-            let _ = self._remove(sanitized_key);
-            return Ok(None);
+    fn get_aad<T>(&self, key: &str, aad: Option<&[u8]>) -> Result<Option<T>, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        match self.get_status_aad(key, aad)? {
+            GetStatus::Present(value) => Ok(Some(value)),
+            GetStatus::Expired | GetStatus::Missing => Ok(None),
         }
+    }
 
-        // If an encryption key was provided, decrypt the value
-        let bytes = if let Some(cipher) = &self.cipher {
-            // Check length of data - must be at least 12 bytes for nonce
-            // otherwise it's not a valid nonce.
-            if mmap.len() < 12 {
-                warn!(
-                    "mmap len for item <{}> is lower than nonce size, maybe corrupted",
-                    sanitized_key
-                );
-                return Ok(None);
-            }
-            let nonce = Nonce::from_slice(&mmap[..12]);
-            cipher.decrypt(nonce, &mmap[12..])?
-        } else {
-            mmap.to_vec()
-        };
-        Ok(Some(bytes))
+    fn get_status_aad<T>(&self, key: &str, aad: Option<&[u8]>) -> Result<GetStatus<T>, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        self.get_status_sanitized(&sanitized_key, aad)
     }
 
-    /// Insert a new item, using `bincode` serialization.
-    pub fn insert<T>(&self, key: &str, value: T) -> Result<(), ShmapError>
+    fn get_status_sanitized<T>(
+        &self,
+        sanitized_key: &str,
+        aad: Option<&[u8]>,
+    ) -> Result<GetStatus<T>, ShmapError>
     where
-        T: Serialize,
+        T: DeserializeOwned,
     {
-        let sanitized_key = sanitize_key(key);
-        self.insert_serialize(&sanitized_key, value)?;
-        self.insert_metadata(Metadata::new(key, None, self.cipher.is_some())?)
+        let mut bytes = match self.get_bytes_status::<T>(sanitized_key, aad)? {
+            GetStatus::Present(bytes) => bytes,
+            GetStatus::Expired => return Ok(GetStatus::Expired),
+            GetStatus::Missing => return Ok(GetStatus::Missing),
+        };
+        let value = self.deserialize_zeroize(sanitized_key, &mut bytes)?;
+        self.record_access(sanitized_key)?;
+        Ok(GetStatus::Present(value))
     }
 
-    /// Insert a new item, using `bincode` serialization, with a TTL.
-    pub fn insert_with_ttl<T>(&self, key: &str, value: T, ttl: Duration) -> Result<(), ShmapError>
+    /// Get an item inserted with [`Shmap::insert_bytes_serde`], or as a `Vec<u8>`, by its key,
+    /// like [`Shmap::get`] with a `serde_bytes` wrapper.
+    pub fn get_bytes_serde(&self, key: &str) -> Result<Option<Vec<u8>>, ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        let GetStatus::Present(mut bytes) =
+            self.get_bytes_status::<Vec<u8>>(&sanitized_key, None)?
+        else {
+            return Ok(None);
+        };
+        let ByteBuf(value) = self.deserialize_zeroize(&sanitized_key, &mut bytes)?;
+        self.record_access(&sanitized_key)?;
+        Ok(Some(value))
+    }
+
+    /// Get an item value by its key, like [`Shmap::get`], along with its bytes, as serialized
+    /// (and decrypted if needed), e.g. to forward the exact payload. The item is read once.
+    ///
+    /// The bytes are returned to the caller, so they are not zeroized, even with
+    /// [`ShmapBuilder::with_mlock`].
+    #[allow(clippy::type_complexity)]
+    pub fn get_full<T>(&self, key: &str) -> Result<Option<(T, Vec<u8>)>, ShmapError>
     where
-        T: Serialize,
+        T: DeserializeOwned,
     {
-        let sanitized_key = sanitize_key(key);
-        self.insert_serialize(&sanitized_key, value)?;
-        self.insert_metadata(Metadata::new(key, Some(ttl), self.cipher.is_some())?)
+        let sanitized_key = self.sanitize_key(key)?;
+        let GetStatus::Present(bytes) = self.get_bytes_status::<T>(&sanitized_key, None)? else {
+            return Ok(None);
+        };
+        let value = deserialize(self.format, self.decode_limit, &sanitized_key, &bytes)?;
+        self.record_access(&sanitized_key)?;
+        Ok(Some((value, bytes)))
     }
 
-    /// Insert a new item, without serialization, with a TTL.
-    pub fn insert_raw_with_ttl(
+    /// Read the bytes of an item, to be deserialized to T, removing it if expired.
+    fn get_bytes_status<T>(
         &self,
-        key: &str,
-        value: &[u8],
-        ttl: Duration,
-    ) -> Result<(), ShmapError> {
-        let sanitized_key = sanitize_key(key);
-        self._insert(&sanitized_key, value)?;
-        self.insert_metadata(Metadata::new(key, Some(ttl), self.cipher.is_some())?)
+        sanitized_key: &str,
+        aad: Option<&[u8]>,
+    ) -> Result<GetStatus<Vec<u8>>, ShmapError> {
+        // Remove item if expired
+        let metadata = match self.get_metadata(sanitized_key)? {
+            Some(metadata) if self.cipher.is_some() && metadata.aad != aad.is_some() => {
+                return Err(ShmapError::AadMismatch);
+            }
+            Some(metadata) if metadata.encrypted && self.cipher.is_none() => {
+                return Err(ShmapError::Encrypted);
+            }
+            Some(metadata) if self.is_expired(&metadata) => {
+                log!(
+                    target: LOG_TARGET,
+                    self.routine_level(),
+                    key = sanitized_key,
+                    op = "get";
+                    "Item expired, removing"
+                );
+                let _ = self.remove_expired(sanitized_key);
+                return Ok(GetStatus::Expired);
+            }
+            Some(metadata) => metadata,
+            None => return Ok(GetStatus::Missing),
+        };
+        self.check_type::<T>(Some(&metadata))?;
+
+        Ok(self
+            .read_value(
+                sanitized_key,
+                Some(&metadata),
+                true,
+                aad.unwrap_or_default(),
+            )?
+            .map_or(GetStatus::Missing, GetStatus::Present))
     }
 
-    fn insert_metadata(&self, metadata: Metadata) -> Result<(), ShmapError> {
-        let sanitize_metadata_key = sanitize_metadata_key(&metadata.key);
-        self.insert_serialize(&sanitize_metadata_key, metadata)
+    /// Record a read of an item, if access statistics are enabled.
+    fn record_access(&self, sanitized_key: &str) -> Result<(), ShmapError> {
+        if self.access_stats {
+            let now = self.clock.now();
+            self.update_metadata(sanitized_key, |metadata| metadata.record_access(now))?;
+        }
+        Ok(())
     }
 
-    fn insert_serialize<T>(&self, sanitized_key: &str, value: T) -> Result<(), ShmapError>
+    /// Block until an item is available, and return its value, or `None` if `timeout` elapses
+    /// first.
+    ///
+    /// This is not event-driven: the item is polled, with a backoff growing up to
+    /// 100ms between attempts. A `timeout` too large to be represented, like [`Duration::MAX`],
+    /// waits forever.
+    pub fn wait_for<T>(&self, key: &str, timeout: Duration) -> Result<Option<T>, ShmapError>
     where
-        T: Serialize,
+        T: DeserializeOwned,
     {
-        let bytes = bincode::serde::encode_to_vec(&value, bincode::config::standard())?;
-        self._insert(sanitized_key, &bytes)
+        let deadline = Instant::now().checked_add(timeout);
+        let mut backoff = Duration::from_millis(1);
+        loop {
+            if let Some(value) = self.get(key)? {
+                return Ok(Some(value));
+            }
+            let now = Instant::now();
+            if deadline.is_some_and(|deadline| now >= deadline) {
+                return Ok(None);
+            }
+            std::thread::sleep(deadline.map_or(backoff, |deadline| backoff.min(deadline - now)));
+            backoff = (backoff * 2).min(WAIT_FOR_MAX_BACKOFF);
+        }
     }
 
-    /// Insert a new item, without serialization.
-    pub fn insert_raw(&self, key: &str, value: &[u8]) -> Result<(), ShmapError> {
-        let sanitized_key = sanitize_key(key);
-        self._insert(&sanitized_key, value)
+    /// Time elapsed since the value of an item was last written, from its shm file modification
+    /// time to the time of [`ShmapBuilder::with_clock`], or `None` if it does not exist. A
+    /// modification time in the future (clock skew) gives a zero age.
+    ///
+    /// Expiration is not checked.
+    pub fn age(&self, key: &str) -> Result<Option<Duration>, ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        let modified = match fs::metadata(self.dir.join(&sanitized_key)) {
+            Ok(metadata) => metadata.modified()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(ShmapError::IOError(e)),
+        };
+        Ok(Some(
+            SystemTime::from(self.clock.now())
+                .duration_since(modified)
+                .unwrap_or_default(),
+        ))
     }
 
-    fn _insert(&self, sanitized_key: &str, value: &[u8]) -> Result<(), ShmapError> {
-        // If an encryption key was provided, encrypt the value
-        let bytes = if let Some(cipher) = &self.cipher {
-            let mut nonce: Vec<u8> = (0..12).collect();
-            nonce.shuffle(&mut thread_rng());
-            let mut ciphertext = cipher.encrypt(Nonce::from_slice(nonce.as_slice()), value)?;
-            nonce.append(&mut ciphertext);
-            nonce
+    /// Get the metadata of an item by its key.
+    ///
+    /// Expiration is not checked, so this may return the metadata of a logically expired item.
+    pub fn metadata(&self, key: &str) -> Result<Option<Metadata>, ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        self.get_metadata(&sanitized_key)
+    }
+
+    fn get_metadata(&self, sanitized_key: &str) -> Result<Option<Metadata>, ShmapError> {
+        let metadata_key = metadata_key(sanitized_key);
+        match self._get(&metadata_key)? {
+            Some(bytes) => Ok(Some(decode_metadata(&metadata_key, &bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether values are encrypted, see [`ShmapBuilder::with_encryption`].
+    #[must_use]
+    pub const fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// TTL of the items inserted without an explicit one, see
+    /// [`ShmapBuilder::with_default_ttl`].
+    #[must_use]
+    pub const fn default_ttl(&self) -> Option<Duration> {
+        self.default_ttl
+    }
+
+    /// Directory where items are stored, [`SHM_DIR`] by default, see [`ShmapBuilder::with_dir`].
+    #[must_use]
+    pub fn shm_dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Name of the shm file in which the value of the item `key` is stored, in
+    /// [`Shmap::shm_dir`]: `shmap.<hash>`, or `shmap.<namespace>.<hash>` in a namespace, with the
+    /// hash of [`ShmapBuilder::with_key_hash`], or the key itself with
+    /// [`ShmapBuilder::with_raw_keys`].
+    pub fn shm_filename(&self, key: &str) -> Result<String, ShmapError> {
+        self.sanitize_key(key)
+    }
+
+    /// Name of the shm file in which the metadata of the item `key` is stored, see
+    /// [`Shmap::shm_filename`].
+    pub fn shm_metadata_filename(&self, key: &str) -> Result<String, ShmapError> {
+        Ok(metadata_key(&self.sanitize_key(key)?))
+    }
+
+    /// Name of the lock file shared by the item `key` and its metadata, in
+    /// [`ShmapBuilder::with_lock_dir`] if set, see [`Shmap::shm_filename`].
+    pub fn shm_lock_filename(&self, key: &str) -> Result<String, ShmapError> {
+        Ok(lock_key(&self.sanitize_key(key)?))
+    }
+
+    /// Prefix of the names of all the files of the Shmap.
+    #[must_use]
+    pub const fn prefix(&self) -> &'static str {
+        SHMAP_PREFIX
+    }
+
+    /// Namespace of the items, see [`ShmapBuilder::with_namespace`].
+    #[must_use]
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Bound of the total size of the item values, see [`ShmapBuilder::with_max_bytes`].
+    #[must_use]
+    pub const fn max_bytes(&self) -> Option<usize> {
+        self.max_bytes
+    }
+
+    /// Free space left in the Shmap directory, in bytes, e.g. to check before a bulk load that it
+    /// will not fail with [`ShmapError::OutOfSpace`] (or worse, see
+    /// [`ShmapBuilder::with_fallocate`]).
+    ///
+    /// This is the space of the whole filesystem, shared with other users, and items take a
+    /// little more than their value, rounded up to pages and with their metadata.
+    pub fn available_bytes(&self) -> Result<u64, ShmapError> {
+        shm::available_bytes(&self.dir)
+    }
+
+    /// Fingerprint of the configuration which must be shared by all the users of a store to read
+    /// each other's items: encryption algorithm, serialization codec, metadata format, file prefix,
+    /// and key naming (hash or raw keys). The encryption key itself is not part of it.
+    ///
+    /// With [`ShmapBuilder::with_config_check`], it is stored in the store on the first write, and
+    /// a Shmap with another fingerprint then refuses to write with [`ShmapError::ConfigMismatch`].
+    #[must_use]
+    pub fn config_fingerprint(&self) -> u64 {
+        let encryption = if self.cipher.is_some() {
+            "aes256-gcm"
+        } else {
+            "none"
+        };
+        let keys = if self.raw_keys {
+            "raw"
         } else {
-            value.to_vec()
+            self.key_hash.name()
         };
+        let codec = self.format.name();
+        let config = format!(
+            "prefix={SHMAP_PREFIX};encryption={encryption};codec={codec};metadata={FORMAT_VERSION};\
+             keys={keys}"
+        );
+        let hash = Sha224::digest(config.as_bytes());
+        let mut fingerprint = [0u8; 8];
+        fingerprint.copy_from_slice(&hash[..8]);
+        u64::from_le_bytes(fingerprint)
+    }
 
-        let lock = NamedLock::with_path(
-            PathBuf::from(SHM_DIR).join(
-                sanitized_key
-                    .trim_end_matches(&format!(".{METADATA_SUFFIX}"))
-                    .to_string()
-                    + "."
-                    + LOCK_SUFFIX,
-            ),
-        )?;
-        let guard = lock.lock()?;
-
-        // Insert the item to shm
-        let write_result = || -> Result<(), ShmapError> {
-            let fd = shm::open_write(sanitized_key, bytes.len())?;
-            // SAFETY: libc call is unsafe
-            let mut mmap = unsafe { MmapMut::map_mut(fd) }?;
-            mmap.copy_from_slice(bytes.as_slice());
-            Ok(())
-        }();
-
-        match write_result {
-            Ok(()) => Ok(()),
-            Err(e) => {
-                drop(guard);
-                let _ = self._remove(sanitized_key);
-                Err(e)
+    /// Check that the fingerprint of the store is the one of this Shmap, storing it if there is
+    /// none yet. Only the first call reads the store.
+    fn check_config(&self) -> Result<(), ShmapError> {
+        if !self.config_check {
+            return Ok(());
+        }
+        let found = if let Some(found) = self.stored_fingerprint.get() {
+            *found
+        } else {
+            let found = self.load_or_store_fingerprint()?;
+            *self.stored_fingerprint.get_or_init(|| found)
+        };
+        let expected = self.config_fingerprint();
+        if found != expected {
+            return Err(ShmapError::ConfigMismatch { expected, found });
+        }
+        Ok(())
+    }
+
+    fn load_or_store_fingerprint(&self) -> Result<u64, ShmapError> {
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, CONFIG_KEY))?;
+
+        match shm::open_read(&self.dir, CONFIG_KEY) {
+            Ok(fd) => {
+                // SAFETY: Mmap call is unsafe
+                let mmap = unsafe { Mmap::map(fd) }?;
+                let fingerprint = mmap[..].try_into().map_err(|_| ShmapError::Serialization {
+                    key: CONFIG_KEY.to_owned(),
+                    source: SerializationError::InvalidMetadata("invalid config fingerprint"),
+                })?;
+                Ok(u64::from_le_bytes(fingerprint))
+            }
+            Err(ShmapError::ShmFileNotFound) => {
+                let fingerprint = self.config_fingerprint().to_le_bytes();
+                let fd = shm::open_write(&self.dir, CONFIG_KEY, fingerprint.len(), false)?;
+                // SAFETY: libc call is unsafe
+                let mut mmap = unsafe { MmapMut::map_mut(fd) }?;
+                mmap.copy_from_slice(&fingerprint);
+                Ok(self.config_fingerprint())
             }
+            Err(e) => Err(e),
         }
     }
 
-    /// Remove an item by its key.
-    pub fn remove(&self, key: &str) -> Result<(), ShmapError> {
-        let sanitized_key = sanitize_key(key);
-        self._remove(&sanitized_key)?;
-        self.remove_metadata(key)
+    /// Level of the messages about the normal life of items (expiration, orphans, eviction), see
+    /// [`ShmapBuilder::with_quiet`].
+    const fn routine_level(&self) -> Level {
+        if self.quiet {
+            Level::Debug
+        } else {
+            Level::Warn
+        }
     }
 
-    fn remove_metadata(&self, key: &str) -> Result<(), ShmapError> {
-        let sanitize_metadata_key = sanitize_metadata_key(key);
-        self._remove(&sanitize_metadata_key)
+    /// Whether decrypted values must be kept out of swap, see [`ShmapBuilder::with_mlock`].
+    const fn protect_plaintext(&self) -> bool {
+        self.mlock && self.cipher.is_some()
     }
 
-    #[allow(clippy::unused_self)]
-    fn _remove(&self, sanitized_key: &str) -> Result<(), ShmapError> {
-        if !sanitized_key.ends_with(LOCK_SUFFIX) {
-            let lock = NamedLock::with_path(
-                PathBuf::from(SHM_DIR).join(
-                    sanitized_key
-                        .trim_end_matches(&format!(".{METADATA_SUFFIX}"))
-                        .to_string()
-                        + "."
-                        + LOCK_SUFFIX,
-                ),
-            )?;
-            let _guard = lock.lock()?;
+    /// Deserialize `bytes`, then zeroize them if they hold a decrypted value to protect.
+    fn deserialize_zeroize<T>(&self, sanitized_key: &str, bytes: &mut [u8]) -> Result<T, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        let value = deserialize(self.format, self.decode_limit, sanitized_key, bytes);
+        if self.protect_plaintext() {
+            bytes.zeroize();
         }
+        value
+    }
 
-        shm::unlink(sanitized_key)?;
+    /// Record `T` as the type of an item, see [`ShmapBuilder::with_type_check`].
+    fn tag_type<T: ?Sized>(&self, metadata: &mut Metadata) {
+        if self.type_check {
+            metadata.type_name = Some(std::any::type_name::<T>().to_owned());
+        }
+    }
 
-        Ok(())
+    /// Check that an item is a `T`, if both the Shmap and the item have type checks.
+    fn check_type<T: ?Sized>(&self, metadata: Option<&Metadata>) -> Result<(), ShmapError> {
+        let expected = std::any::type_name::<T>();
+        match metadata.and_then(|metadata| metadata.type_name.as_deref()) {
+            Some(found) if self.type_check && found != expected => Err(ShmapError::TypeMismatch {
+                expected: expected.to_owned(),
+                found: found.to_owned(),
+            }),
+            _ => Ok(()),
+        }
     }
 
-    /// List available keys.
-    pub fn keys(&self) -> Result<Vec<String>, ShmapError> {
-        self.clean()
+    /// Get an item value by its key, and deserialize it (using `bincode`) to T, without any side
+    /// effect on the store.
+    ///
+    /// Unlike [`Shmap::get`], expiration is not checked, so this may return a logically expired
+    /// item which has not been removed yet.
+    pub fn peek<T>(&self, key: &str) -> Result<Option<T>, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        let metadata = self.get_metadata(&sanitized_key)?;
+        self.check_type::<T>(metadata.as_ref())?;
+        match self.read_value(&sanitized_key, metadata.as_ref(), false, &[])? {
+            Some(mut bytes) => Ok(Some(self.deserialize_zeroize(&sanitized_key, &mut bytes)?)),
+            None => Ok(None),
+        }
     }
 
-    /// Clean expired items.
-    pub fn clean(&self) -> Result<Vec<String>, ShmapError> {
-        let mut keys = Vec::<String>::new();
-        for dir_entry in (std::fs::read_dir(PathBuf::from(SHM_DIR))?).flatten() {
-            let filename = dir_entry.file_name().to_string_lossy().to_string();
-            let Ok(metadata) = fs::metadata(format!("{SHM_DIR}/{filename}")) else {
+    /// Open the shm file of an item in readonly, for example to map it with another library or to
+    /// send it over a unix socket. Returns `None` if the item is absent or expired.
+    ///
+    /// The file holds the raw stored bytes: encrypted if an encryption key is set, and only the
+    /// first part of a value split with [`ShmapBuilder::with_chunk_size`]. Otherwise, a value
+    /// inserted with [`Shmap::insert_raw`] starts at the beginning of the file, without any
+    /// prefix, so that a mapping of it is page-aligned, e.g. to overlay a `#[repr(C)]` struct.
+    pub fn open_fd(&self, key: &str) -> Result<Option<shm::Fd>, ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        if self
+            .get_metadata(&sanitized_key)?
+            .is_some_and(|metadata| self.is_expired(&metadata))
+        {
+            return Ok(None);
+        }
+
+        let _guard = ReadGuard::lock(&lock_path(&self.lock_dir, &sanitized_key))?;
+        match shm::open_read(&self.dir, &sanitized_key) {
+            Ok(fd) => Ok(Some(fd)),
+            Err(ShmapError::ShmFileNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the nonce and the ciphertext of an encrypted item, for example to decrypt it with
+    /// external tooling. Returns `None` if the item is absent or expired, and
+    /// [`ShmapError::NotEncrypted`] if it is stored in plaintext.
+    ///
+    /// An encrypted shm file is laid out as a 12-byte AES256-GCM nonce followed by the ciphertext
+    /// and its 16-byte tag. The associated data is the file name (the sanitized key), followed by
+    /// the one given to [`Shmap::insert_with_aad`] if any. The decrypted bytes are the value
+    /// serialized with bincode (standard config, through serde), or as is for
+    /// [`Shmap::insert_raw`].
+    ///
+    /// Only the first part of a value split with [`ShmapBuilder::with_chunk_size`] is returned.
+    #[allow(clippy::type_complexity)]
+    pub fn raw_encryption_parts(
+        &self,
+        key: &str,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>, ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        let metadata = self.get_metadata(&sanitized_key)?;
+        if metadata
+            .as_ref()
+            .is_some_and(|metadata| self.is_expired(metadata))
+        {
+            return Ok(None);
+        }
+        // Without metadata (raw insert), the item was written with the same config as ours
+        if !metadata.map_or_else(|| self.cipher.is_some(), |metadata| metadata.encrypted) {
+            return Err(ShmapError::NotEncrypted);
+        }
+
+        let _guard = ReadGuard::lock(&lock_path(&self.lock_dir, &sanitized_key))?;
+        let fd = match shm::open_read(&self.dir, &sanitized_key) {
+            Ok(fd) => fd,
+            Err(ShmapError::ShmFileNotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        // SAFETY: Mmap call is unsafe
+        let mmap = unsafe { Mmap::map(fd) }?;
+        if mmap.len() < 12 {
+            warn!(
+                target: LOG_TARGET,
+                key = sanitized_key.as_str(),
+                op = "get",
+                size = mmap.len();
+                "mmap len is lower than nonce size, maybe corrupted"
+            );
+            return Ok(None);
+        }
+        Ok(Some((mmap[..12].to_vec(), mmap[12..].to_vec())))
+    }
+
+    /// Get an item by its key, without deserialization, as bytes.
+    pub fn get_raw(&self, key: &str) -> Result<Option<Vec<u8>>, ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        let metadata = self.get_metadata(&sanitized_key)?;
+        self.read_value(&sanitized_key, metadata.as_ref(), true, &[])
+    }
+
+    /// Get an item by its key, without deserialization, as [`bytes::Bytes`], with the `bytes`
+    /// feature. The buffer read from shm is moved into the `Bytes`, without a copy.
+    ///
+    /// The `Bytes` is never backed by the mapping itself, even without encryption: writers
+    /// truncate the shm file in place, which would make a later access to the mapping crash the
+    /// process with a `SIGBUS`.
+    #[cfg(feature = "bytes")]
+    pub fn get_bytes_shared(&self, key: &str) -> Result<Option<bytes::Bytes>, ShmapError> {
+        Ok(self.get_raw(key)?.map(bytes::Bytes::from))
+    }
+
+    /// Get an item by its key, without deserialization, as an `Arc<[u8]>`, e.g. to share it with an
+    /// in-process cache. See [`Shmap::insert_arc`].
+    ///
+    /// The buffer read from shm is copied once more into the `Arc`, which, unlike
+    /// [`bytes::Bytes`], can not take it over.
+    pub fn get_arc(&self, key: &str) -> Result<Option<Arc<[u8]>>, ShmapError> {
+        Ok(self.get_raw(key)?.map(Arc::from))
+    }
+
+    /// Get the raw bytes and metadata of several items, for example to replicate them elsewhere.
+    ///
+    /// Absent or expired items are skipped.
+    pub fn dump_entries(
+        &self,
+        keys: &[&str],
+    ) -> Result<Vec<(String, Vec<u8>, Metadata)>, ShmapError> {
+        let mut entries = Vec::with_capacity(keys.len());
+        for key in keys {
+            let sanitized_key = self.sanitize_key(key)?;
+            let Some(metadata) = self.get_metadata(&sanitized_key)? else {
                 continue;
             };
-            let Ok(modified_time) = metadata.modified() else {
+            if self.is_expired(&metadata) {
                 continue;
-            };
-            let Ok(duration_since_modified_time) = SystemTime::now().duration_since(modified_time)
-            else {
+            }
+            if let Some(bytes) = self.read_value(&sanitized_key, Some(&metadata), true, &[])? {
+                entries.push(((*key).to_owned(), bytes, metadata));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Get the raw bytes of all the live items whose key starts with `prefix`, with their keys.
+    ///
+    /// Items are found through their metadata, so values inserted with [`Shmap::insert_raw`],
+    /// which have none, are not returned, nor are items bound to an associated data (see
+    /// [`Shmap::insert_with_aad`]).
+    pub fn get_raw_prefix(&self, prefix: &str) -> Result<Vec<(String, Vec<u8>)>, ShmapError> {
+        let mut entries = Vec::new();
+        for (filename, metadata) in self.live_items()? {
+            if metadata.aad || !metadata.key.starts_with(prefix) {
                 continue;
-            };
-            if filename.starts_with(SHMAP_PREFIX)
-                && !filename.ends_with(METADATA_SUFFIX)
-                && !filename.ends_with(LOCK_SUFFIX)
-            {
-                let metadata_filename = format!("{filename}.{METADATA_SUFFIX}");
-                match self.get_deserialize::<Metadata>(&metadata_filename) {
-                    Ok(Some(metadata)) => match metadata.expiration {
-                        Some(expiration) => {
-                            if Utc::now().gt(&expiration) {
-                                // Expired, remove item and metadata
-                                warn!("[clean] Item <{}> expired, removing", &filename);
-                                let _ = self._remove(&filename);
-                                let _ = self._remove(&metadata_filename);
-                            } else {
-                                // Not expired, add to list
-                                keys.push(metadata.key);
-                            }
-                        }
-                        None => {
-                            // Not expiration, add to list
-                            keys.push(metadata.key);
-                        }
-                    },
-                    Ok(None) => {
-                        if duration_since_modified_time > Duration::from_secs(5) {
-                            // Item exists, but metadata not found, remove item
-                            warn!("[clean] Item <{}> metadata not found, removing", &filename);
-                            let _ = self._remove(&filename);
-                        }
-                    }
-                    Err(e) => {
-                        // Can't deserialized metadata or something else happens
-                        error!(
-                            "[clean] Could not get metadata for item <{}> : {}",
-                            &filename, e
-                        );
-                    }
-                }
-            } else if filename.starts_with(SHMAP_PREFIX) && filename.ends_with(METADATA_SUFFIX) {
-                let filename_path = dir_entry.path().to_string_lossy().to_string();
-                let item_filename = filename_path.trim_end_matches(&format!(".{METADATA_SUFFIX}"));
-                if !PathBuf::from(item_filename).exists()
-                    && duration_since_modified_time > Duration::from_secs(5)
-                {
-                    warn!(
-                        "[clean] Metadata <{}> exists, but item not found, removing metadata",
-                        &filename
-                    );
-                    let _ = self._remove(&filename);
-                }
-            } else if filename.starts_with(SHMAP_PREFIX) && filename.ends_with(LOCK_SUFFIX) {
-                let filename_path = dir_entry.path().to_string_lossy().to_string();
-                let item_filename = filename_path.trim_end_matches(&format!(".{LOCK_SUFFIX}"));
-                if !PathBuf::from(item_filename).exists()
-                    && !PathBuf::from(format!("{item_filename}.{METADATA_SUFFIX}")).exists()
-                    && duration_since_modified_time > Duration::from_secs(5)
-                {
-                    warn!(
-                        "[clean] Lock <{}> exists, but item not found, removing",
-                        &filename
-                    );
-                    let _ = self._remove(&filename);
-                }
+            }
+            if let Some(bytes) = self.read_value(&filename, Some(&metadata), true, &[])? {
+                entries.push((metadata.key, bytes));
             }
         }
-        Ok(keys)
+        Ok(entries)
     }
-}
 
-pub fn sanitize_key(key: &str) -> String {
-    let mut hasher = Sha224::new();
-    hasher.update(key);
-    format!("{}.{:x}", SHMAP_PREFIX, hasher.finalize())
-}
+    /// Copy all the live items of type T into a [`HashMap`], by key, e.g. to inspect the store in
+    /// tests or while debugging.
+    ///
+    /// Items which do not deserialize to T (or do not match it, see
+    /// [`ShmapBuilder::with_type_check`]) are skipped, as are items without metadata, encrypted
+    /// with their own key (see [`Shmap::insert_encrypted_with_key`]) or bound to an associated
+    /// data, like with [`Shmap::get_raw_prefix`].
+    pub fn to_hashmap<T>(&self) -> Result<HashMap<String, T>, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        let mut map = HashMap::new();
+        for (filename, metadata) in self.live_items()? {
+            if metadata.aad {
+                continue;
+            }
+            match self.get_status_sanitized(&filename, None) {
+                Ok(GetStatus::Present(value)) => {
+                    map.insert(metadata.key, value);
+                }
+                Ok(GetStatus::Expired | GetStatus::Missing)
+                | Err(
+                    ShmapError::Serialization { .. }
+                    | ShmapError::TypeMismatch { .. }
+                    | ShmapError::Encrypted,
+                ) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(map)
+    }
+
+    /// Fault in the pages of the files of `keys` (value parts and metadata), e.g. at startup, so
+    /// that the first reads of hot items do not wait for pages swapped out of the tmpfs.
+    ///
+    /// Items are not kept mapped or open afterwards. Absent or expired items are skipped.
+    pub fn warm(&self, keys: &[&str]) -> Result<(), ShmapError> {
+        for key in keys {
+            let sanitized_key = self.sanitize_key(key)?;
+            let metadata = self.get_metadata(&sanitized_key)?;
+            if metadata
+                .as_ref()
+                .is_some_and(|metadata| self.is_expired(metadata))
+            {
+                continue;
+            }
+            let parts = metadata.map_or(0, |metadata| metadata.parts);
+
+            let _guard = ReadGuard::lock(&lock_path(&self.lock_dir, &sanitized_key))?;
+            let filenames = (0..parts.max(1) as usize)
+                .map(|part| part_key(&sanitized_key, part))
+                .chain([metadata_key(&sanitized_key)]);
+            for filename in filenames {
+                let fd = match shm::open_read(&self.dir, &filename) {
+                    Ok(fd) => fd,
+                    Err(ShmapError::ShmFileNotFound) => continue,
+                    Err(e) => return Err(e),
+                };
+                // SAFETY: Mmap call is unsafe
+                let mmap = unsafe { MmapOptions::new().populate().map(fd) }?;
+                if !mmap.is_empty() {
+                    mmap.advise(Advice::WillNeed)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the value of an item, reassembling it if its `metadata` tells it is split in parts.
+    fn read_value(
+        &self,
+        sanitized_key: &str,
+        metadata: Option<&Metadata>,
+        remove_empty: bool,
+        aad: &[u8],
+    ) -> Result<Option<Vec<u8>>, ShmapError> {
+        let Some(metadata) = metadata.filter(|metadata| metadata.parts > 0) else {
+            return self._read(sanitized_key, remove_empty, aad);
+        };
+
+        let _guard = ReadGuard::lock(&lock_path(&self.lock_dir, sanitized_key))?;
+        self.read_parts_unlocked(sanitized_key, metadata, aad)
+    }
+
+    /// Read the value of an item, like [`Shmap::read_value`], the caller holding its lock. Empty
+    /// items are not removed.
+    fn read_value_unlocked(
+        &self,
+        sanitized_key: &str,
+        metadata: Option<&Metadata>,
+        aad: &[u8],
+    ) -> Result<Option<Vec<u8>>, ShmapError> {
+        match metadata.filter(|metadata| metadata.parts > 0) {
+            Some(metadata) => self.read_parts_unlocked(sanitized_key, metadata, aad),
+            None => match self.read_unlocked(sanitized_key, aad)? {
+                Content::Value(bytes) => Ok(Some(bytes)),
+                Content::Missing | Content::Empty => Ok(None),
+            },
+        }
+    }
+
+    /// Reassemble the parts of an item, the caller holding its lock.
+    fn read_parts_unlocked(
+        &self,
+        sanitized_key: &str,
+        metadata: &Metadata,
+        aad: &[u8],
+    ) -> Result<Option<Vec<u8>>, ShmapError> {
+        if self
+            .max_read_size
+            .is_some_and(|max_read_size| metadata.len > max_read_size as u64)
+        {
+            return Err(ShmapError::ValueTooLarge);
+        }
+        // Sized up front, so that no partial copy of a decrypted value is left behind. Parts are
+        // stored at least as large as their value, which bounds a corrupted length.
+        let stored = (0..metadata.parts as usize)
+            .map_while(|part| fs::metadata(self.dir.join(part_key(sanitized_key, part))).ok())
+            .map(|part_metadata| part_metadata.len())
+            .sum::<u64>();
+        let mut value =
+            Vec::with_capacity(usize::try_from(metadata.len.min(stored)).unwrap_or_default());
+        for part in 0..metadata.parts as usize {
+            let Content::Value(mut bytes) =
+                self.read_unlocked(&part_key(sanitized_key, part), aad)?
+            else {
+                warn!(
+                    target: LOG_TARGET,
+                    key = sanitized_key,
+                    op = "get",
+                    part = part;
+                    "Part not found, maybe corrupted"
+                );
+                return Ok(None);
+            };
+            value.append(&mut bytes);
+        }
+        if value.len() as u64 != metadata.len {
+            warn!(
+                target: LOG_TARGET,
+                key = sanitized_key,
+                op = "get",
+                size = value.len();
+                "Length does not match the metadata, maybe corrupted"
+            );
+            if self.protect_plaintext() {
+                value.zeroize();
+            }
+            return Ok(None);
+        }
+        Ok(Some(value))
+    }
+
+    fn _get(&self, sanitized_key: &str) -> Result<Option<Vec<u8>>, ShmapError> {
+        self._read(sanitized_key, true, &[])
+    }
+
+    /// Read an item from shm. If `remove_empty` is set, an empty item is considered corrupted and
+    /// removed, once it is still empty after the retries (see
+    /// [`ShmapBuilder::with_empty_read_retries`]).
+    fn _read(
+        &self,
+        sanitized_key: &str,
+        remove_empty: bool,
+        aad: &[u8],
+    ) -> Result<Option<Vec<u8>>, ShmapError> {
+        let guard = ReadGuard::lock(&lock_path(&self.lock_dir, sanitized_key))?;
+
+        let mut content = self.read_unlocked(sanitized_key, aad)?;
+        if remove_empty {
+            let mut backoff = Duration::from_millis(1);
+            for _ in 0..self.empty_read_retries {
+                if !matches!(content, Content::Empty) {
+                    break;
+                }
+                // Maybe being written by a process which does not take the lock
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                content = self.read_unlocked(sanitized_key, aad)?;
+            }
+        }
+
+        match content {
+            Content::Missing => Ok(None),
+            Content::Empty => {
+                // If the value is empty, remove it and return None
+                if remove_empty {
+                    error!(
+                        target: LOG_TARGET,
+                        key = sanitized_key,
+                        op = "get";
+                        "mmap file is empty, removing"
+                    );
+                    drop(guard);
+                    let _ = self._remove(sanitized_key);
+                }
+                Ok(None)
+            }
+            Content::Value(bytes) => Ok(Some(bytes)),
+        }
+    }
+
+    /// Read an item from shm, the caller holding its lock.
+    ///
+    /// Encrypted items are authenticated with their file name, followed by `aad`, as associated
+    /// data, so that a value moved to another file fails to decrypt.
+    fn read_unlocked(&self, sanitized_key: &str, aad: &[u8]) -> Result<Content, ShmapError> {
+        let fd = match shm::open_read(&self.dir, sanitized_key) {
+            Ok(fd) => fd,
+            Err(e) => match e {
+                ShmapError::ShmFileNotFound => {
+                    // If the shm returns "file not found", return None
+                    //let _ = self._remove(sanitized_key); // useless
+                    return Ok(Content::Missing);
+                }
+                e => return Err(e),
+            },
+        };
+        // SAFETY: Mmap call is unsafe
+        let mmap = unsafe { Mmap::map(fd) }?;
+        // Mapping does not allocate, copying or decrypting does
+        if self
+            .max_read_size
+            .is_some_and(|max_read_size| mmap.len() > max_read_size)
+        {
+            return Err(ShmapError::ValueTooLarge);
+        }
+        if mmap.is_empty() {
+            // An encrypted value always has a nonce
+            if self.empty_values && self.cipher.is_none() && is_item_filename(sanitized_key) {
+                return Ok(Content::Value(Vec::new()));
+            }
+            return Ok(Content::Empty);
+        }
+        if self.protect_plaintext() {
+            mmap.lock().map_err(ShmapError::MlockError)?;
+        }
+
+        // If an encryption key was provided, decrypt the value
+        let bytes = if let Some(cipher) = &self.cipher {
+            // Check length of data - must be at least 12 bytes for nonce
+            // otherwise it's not a valid nonce.
+            if mmap.len() < 12 {
+                warn!(
+                    target: LOG_TARGET,
+                    key = sanitized_key,
+                    op = "get",
+                    size = mmap.len();
+                    "mmap len is lower than nonce size, maybe corrupted"
+                );
+                return Ok(Content::Missing);
+            }
+            let nonce = Nonce::from_slice(&mmap[..12]);
+            let aad = [sanitized_key.as_bytes(), aad].concat();
+            let decrypt = |cipher: &Aes256Gcm| {
+                let payload = Payload {
+                    msg: &mmap[12..],
+                    aad: &aad,
+                };
+                cipher.decrypt(nonce, payload)
+            };
+            // Then fall back through the key ring, in order
+            self.key_ring
+                .iter()
+                .fold(decrypt(cipher), |bytes, cipher| {
+                    bytes.or_else(|_| decrypt(cipher))
+                })?
+        } else {
+            mmap.to_vec()
+        };
+        Ok(Content::Value(bytes))
+    }
+
+    /// Insert a new item, using `bincode` serialization, with the default TTL if any.
+    pub fn insert<T>(&self, key: &str, value: T) -> Result<(), ShmapError>
+    where
+        T: Serialize,
+    {
+        self.insert_ref(key, &value)
+    }
+
+    /// Insert a timestamp, with the default TTL if any, as milliseconds since the UNIX epoch (an
+    /// `i64`, negative before it), rather than with the serde representation of [`SystemTime`],
+    /// so that it can be read by other programs or versions. Sub-millisecond precision is lost.
+    pub fn insert_time(&self, key: &str, time: SystemTime) -> Result<(), ShmapError> {
+        let millis = match time.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(since) => i64::try_from(since.as_millis()),
+            Err(e) => i64::try_from(e.duration().as_millis()).map(|millis| -millis),
+        }
+        .map_err(|_| ShmapError::DurationOutOfRangeError)?;
+        self.insert(key, millis)
+    }
+
+    /// Get a timestamp inserted with [`Shmap::insert_time`].
+    pub fn get_time(&self, key: &str) -> Result<Option<SystemTime>, ShmapError> {
+        let Some(millis) = self.get::<i64>(key)? else {
+            return Ok(None);
+        };
+        let since = Duration::from_millis(millis.unsigned_abs());
+        let time = if millis < 0 {
+            SystemTime::UNIX_EPOCH.checked_sub(since)
+        } else {
+            SystemTime::UNIX_EPOCH.checked_add(since)
+        };
+        time.map(Some).ok_or(ShmapError::DurationOutOfRangeError)
+    }
+
+    /// Insert a new item holding `value` as a byte string, using `bincode` serialization, with the
+    /// default TTL if any, like [`Shmap::insert`] with a `serde_bytes` wrapper: the slice is
+    /// written at once after its length, rather than byte by byte.
+    ///
+    /// Read it with [`Shmap::get_bytes_serde`], or with [`Shmap::get`] as a `Vec<u8>`, which has
+    /// the same encoding.
+    pub fn insert_bytes_serde(&self, key: &str, value: &[u8]) -> Result<(), ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        let mut metadata = self.new_metadata(key, self.default_ttl)?;
+        self.tag_type::<Vec<u8>>(&mut metadata);
+        let mut bytes = serialize(self.format, &sanitized_key, &ByteSlice(value))?;
+        let ret = self.insert_bytes(&sanitized_key, &bytes, &[], metadata);
+        if self.protect_plaintext() {
+            bytes.zeroize();
+        }
+        ret
+    }
+
+    /// Insert a new item under a binary key, like [`Shmap::insert`]. A UTF-8 key is the same as
+    /// the equivalent string key, while other keys are listed by [`Shmap::keys`] hex-encoded (see
+    /// [`Metadata::binary_key`]).
+    pub fn insert_bytes_key<T>(&self, key: &[u8], value: T) -> Result<(), ShmapError>
+    where
+        T: Serialize,
+    {
+        let sanitized_key = self.sanitize_bytes_key(key)?;
+        let metadata = if let Ok(key) = std::str::from_utf8(key) {
+            self.new_metadata(key, self.default_ttl)?
+        } else {
+            let mut metadata = self.new_metadata(&hex_encode(key), self.default_ttl)?;
+            metadata.binary_key = true;
+            metadata
+        };
+        self.insert_serialize(&sanitized_key, &value, metadata)
+    }
+
+    /// Insert a new item from a reference, using `bincode` serialization, with the default TTL if
+    /// any. Unlike [`Shmap::insert`], the value can still be used afterwards.
+    pub fn insert_ref<T>(&self, key: &str, value: &T) -> Result<(), ShmapError>
+    where
+        T: Serialize + ?Sized,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        self.insert_serialize(
+            &sanitized_key,
+            value,
+            self.new_metadata(key, self.default_ttl)?,
+        )
+    }
+
+    /// Insert a new item, using `bincode` serialization, with the default TTL if any, and return a
+    /// guard which removes it when dropped (unless [`ScopedKey::forget`] is called).
+    pub fn insert_scoped<T>(&self, key: &str, value: T) -> Result<ScopedKey<'_>, ShmapError>
+    where
+        T: Serialize,
+    {
+        self.insert_ref(key, &value)?;
+        Ok(ScopedKey::new(self, key))
+    }
+
+    /// Insert a new item, using `bincode` serialization, with the default TTL if any, and bind it
+    /// to `aad` (e.g. a tenant id) as associated data: it can then only be read with
+    /// [`Shmap::get_with_aad`] and the same `aad`.
+    ///
+    /// This has no effect without encryption.
+    pub fn insert_with_aad<T>(&self, key: &str, value: T, aad: &[u8]) -> Result<(), ShmapError>
+    where
+        T: Serialize,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        let mut metadata = self.new_metadata(key, self.default_ttl)?;
+        metadata.aad = self.cipher.is_some();
+        self.tag_type::<T>(&mut metadata);
+        let mut bytes = serialize(self.format, &sanitized_key, &value)?;
+        let ret = self.insert_bytes(&sanitized_key, &bytes, aad, metadata);
+        if self.protect_plaintext() {
+            bytes.zeroize();
+        }
+        ret
+    }
+
+    /// Insert a new item, like [`Shmap::insert`], its value being encrypted with `encryption_key`
+    /// (AES256-GCM) instead of the key of the Shmap, if any. Read it with
+    /// [`Shmap::get_encrypted_with_key`].
+    ///
+    /// This mixes selectively encrypted items into a plaintext store: the metadata is still
+    /// written like the one of the other items, so that the item is listed, expired and cleaned
+    /// as usual, and only records that the value is encrypted.
+    // Taken by value like `Shmap::insert`
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn insert_encrypted_with_key<T>(
+        &self,
+        key: &str,
+        value: T,
+        encryption_key: &[u8; 32],
+    ) -> Result<(), ShmapError>
+    where
+        T: Serialize,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        let mut metadata = self.new_metadata(key, self.default_ttl)?;
+        metadata.encrypted = true;
+        self.tag_type::<T>(&mut metadata);
+        let writer = self.with_cipher(encryption_key);
+        let mut bytes = serialize(self.format, &sanitized_key, &value)?;
+        let ret = self.insert_bytes_with(&writer, &sanitized_key, &bytes, &[], metadata);
+        if writer.protect_plaintext() {
+            bytes.zeroize();
+        }
+        ret
+    }
+
+    /// Get an item inserted with [`Shmap::insert_encrypted_with_key`], which must be given the
+    /// same encryption key. Fails with [`ShmapError::NotEncrypted`] if the item value is not
+    /// encrypted.
+    pub fn get_encrypted_with_key<T>(
+        &self,
+        key: &str,
+        encryption_key: &[u8; 32],
+    ) -> Result<Option<T>, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        let Some(metadata) = self.get_metadata(&sanitized_key)? else {
+            return Ok(None);
+        };
+        if self.is_expired(&metadata) {
+            // Removed by the next clean
+            return Ok(None);
+        }
+        if !metadata.encrypted {
+            return Err(ShmapError::NotEncrypted);
+        }
+        self.check_type::<T>(Some(&metadata))?;
+
+        let reader = self.with_cipher(encryption_key);
+        match reader.read_value(&sanitized_key, Some(&metadata), true, &[])? {
+            Some(mut bytes) => Ok(Some(
+                reader.deserialize_zeroize(&sanitized_key, &mut bytes)?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// This Shmap, encrypting and decrypting values with `encryption_key` only.
+    fn with_cipher(&self, encryption_key: &[u8; 32]) -> Self {
+        Self {
+            cipher: Some(Aes256Gcm::new(GenericArray::from_slice(encryption_key))),
+            key_ring: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Insert a new item, like [`Shmap::insert`], unless the current value is the same, in which
+    /// case nothing is written and its TTL is not renewed. Return whether the item was written.
+    ///
+    /// Values are compared once serialized, under the item lock. Values larger than the chunk
+    /// size (see [`ShmapBuilder::with_chunk_size`]) are always written.
+    pub fn insert_if_changed<T>(&self, key: &str, value: T) -> Result<bool, ShmapError>
+    where
+        T: Serialize,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        let mut bytes = serialize(self.format, &sanitized_key, &value)?;
+        if self
+            .chunk_size
+            .is_some_and(|chunk_size| bytes.len() > chunk_size)
+        {
+            let mut metadata = self.new_metadata(key, self.default_ttl)?;
+            self.tag_type::<T>(&mut metadata);
+            let ret = self.insert_bytes(&sanitized_key, &bytes, &[], metadata);
+            if self.protect_plaintext() {
+                bytes.zeroize();
+            }
+            return ret.map(|()| true);
+        }
+        let mut metadata = self.new_metadata(key, self.default_ttl)?;
+        self.tag_type::<T>(&mut metadata);
+        let ret = self.write_if_changed(&sanitized_key, &bytes, metadata);
+        if self.protect_plaintext() {
+            bytes.zeroize();
+        }
+        ret
+    }
+
+    /// Insert a new item, like [`Shmap::insert`], only if it is absent or expired. Return whether
+    /// the item was written.
+    ///
+    /// The value file is created with `O_EXCL`, so that the kernel atomically refuses to create
+    /// it if it exists, even if written by a process which does not take the item lock (e.g. with
+    /// [`Shmap::insert_raw`] from another program): exactly one of concurrent inserts succeeds.
+    /// The value is never split in parts (see [`ShmapBuilder::with_chunk_size`]).
+    // Taken by value like `Shmap::insert`
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn insert_if_absent<T>(&self, key: &str, value: T) -> Result<bool, ShmapError>
+    where
+        T: Serialize,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        let mut metadata = self.new_metadata(key, self.default_ttl)?;
+        self.tag_type::<T>(&mut metadata);
+        let mut bytes = serialize(self.format, &sanitized_key, &value)?;
+        let sealed = self.seal(&sanitized_key, &bytes, &[]);
+        if self.protect_plaintext() {
+            bytes.zeroize();
+        }
+        let sealed = sealed?;
+        self.check_config()?;
+        if let Some(max_bytes) = self.max_bytes {
+            self.evict(&sanitized_key, sealed.len(), max_bytes)?;
+        }
+
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, &sanitized_key))?;
+
+        match self.get_metadata_unlocked(&sanitized_key)? {
+            Some(current) if !self.is_expired(&current) => return Ok(false),
+            Some(_) => self.unlink_item_unlocked(&sanitized_key)?,
+            None => {}
+        }
+        let fd = match shm::open_create_exclusive(
+            &self.dir,
+            &sanitized_key,
+            sealed.len(),
+            self.fallocate,
+        ) {
+            Ok(fd) => fd,
+            Err(ShmapError::ShmFileExists) => return Ok(false),
+            // Nothing was created, or it was unlinked already
+            Err(e) => return Err(e),
+        };
+        // Parts of a previous value
+        self.unlink_parts(&sanitized_key, 1);
+        let written = self
+            .copy_to_shm(fd, &sealed)
+            .and_then(|()| self.write_durable(&sanitized_key, &sealed))
+            .and_then(|()| {
+                let metadata_key = metadata_key(&sanitized_key);
+                let bytes = encode_metadata(&metadata_key, &metadata)?;
+                self.write_unlocked(&metadata_key, &bytes, &[])
+            });
+        if let Err(e) = written {
+            // Under the lock, not to remove the item of a concurrent insert
+            let _ = self.unlink_item_unlocked(&sanitized_key);
+            return Err(e);
+        }
+        self.record_changes([key])?;
+        Ok(true)
+    }
+
+    /// Write an item with its `metadata` unless it is live and its current value is `value`. Both
+    /// are written under the same lock, like with [`Shmap::insert_bytes`].
+    fn write_if_changed(
+        &self,
+        sanitized_key: &str,
+        value: &[u8],
+        mut metadata: Metadata,
+    ) -> Result<bool, ShmapError> {
+        self.check_config()?;
+        if let Some(max_bytes) = self.max_bytes {
+            self.evict(sanitized_key, value.len(), max_bytes)?;
+        }
+
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, sanitized_key))?;
+
+        // Metadata read under the lock, so that it matches the value compared below
+        let live = self
+            .get_metadata_unlocked(sanitized_key)?
+            .is_some_and(|metadata| {
+                !self.is_expired(&metadata) && !metadata.aad && metadata.parts == 0
+            });
+        if live {
+            if let Content::Value(mut current) = self.read_unlocked(sanitized_key, &[])? {
+                let unchanged = current == value;
+                if self.protect_plaintext() {
+                    current.zeroize();
+                }
+                if unchanged {
+                    return Ok(false);
+                }
+            }
+        }
+
+        match self.write_item_unlocked(sanitized_key, value, &[], &mut metadata) {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                let _ = self.unlink_item_unlocked(sanitized_key);
+                Err(e)
+            }
+        }
+    }
+
+    /// Migrate the value of an item from `OldT` to `NewT` with `f`, under the item lock, keeping
+    /// its metadata and thus its expiration. Return `false` if the item is absent or expired.
+    ///
+    /// The migrated value is not subject to eviction (see [`ShmapBuilder::with_max_bytes`]).
+    /// Items inserted with [`Shmap::insert_with_aad`] can not be migrated, and fail with
+    /// [`ShmapError::AadMismatch`].
+    pub fn migrate<OldT, NewT, F>(&self, key: &str, f: F) -> Result<bool, ShmapError>
+    where
+        OldT: DeserializeOwned,
+        NewT: Serialize,
+        F: FnOnce(OldT) -> NewT,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        self.check_config()?;
+        let metadata_key = metadata_key(&sanitized_key);
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, &sanitized_key))?;
+
+        let mut metadata = match self.read_unlocked(&metadata_key, &[])? {
+            Content::Value(bytes) => Some(decode_metadata(&metadata_key, &bytes)?),
+            Content::Missing | Content::Empty => None,
+        };
+        match &metadata {
+            Some(metadata) if self.cipher.is_some() && metadata.aad => {
+                return Err(ShmapError::AadMismatch);
+            }
+            Some(metadata) if self.is_expired(metadata) => return Ok(false),
+            _ => {}
+        }
+        self.check_type::<OldT>(metadata.as_ref())?;
+        let Some(mut bytes) = self.read_value_unlocked(&sanitized_key, metadata.as_ref(), &[])?
+        else {
+            return Ok(false);
+        };
+        let old: OldT = self.deserialize_zeroize(&sanitized_key, &mut bytes)?;
+        let mut bytes = serialize(self.format, &sanitized_key, &f(old))?;
+        if let Some(metadata) = &mut metadata {
+            self.tag_type::<NewT>(metadata);
+        }
+
+        let ret = self.write_migrated_unlocked(&sanitized_key, &bytes, metadata.as_mut());
+        if self.protect_plaintext() {
+            bytes.zeroize();
+        }
+        if ret.is_err() {
+            let _ = self.unlink_item_unlocked(&sanitized_key);
+        }
+        ret?;
+        self.record_changes([key])?;
+        Ok(true)
+    }
+
+    /// Push `item` at the end of the `Vec<T>` value of an item, under the item lock, so that
+    /// concurrent pushes are never lost. Return the new length.
+    ///
+    /// An absent or expired item is created, with the default TTL if any, while an existing one
+    /// keeps its metadata and thus its expiration. Like [`Shmap::migrate`], the value is not
+    /// subject to eviction, and items inserted with [`Shmap::insert_with_aad`] fail with
+    /// [`ShmapError::AadMismatch`].
+    pub fn push<T>(&self, key: &str, item: T) -> Result<usize, ShmapError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        self.update_list(key, true, |list: &mut Vec<T>| {
+            list.push(item);
+            list.len()
+        })
+    }
+
+    /// Remove the last item of the `Vec<T>` value of an item, under the item lock, see
+    /// [`Shmap::push`]. Return `None` if the item is absent, expired or empty.
+    pub fn pop_back<T>(&self, key: &str) -> Result<Option<T>, ShmapError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        self.update_list(key, false, Vec::pop)
+    }
+
+    /// Modify the `Vec<T>` value of an item with `f` under the item lock, an absent or expired
+    /// item being an empty list, which is only written if `create` is set.
+    fn update_list<T, R, F>(&self, key: &str, create: bool, f: F) -> Result<R, ShmapError>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce(&mut Vec<T>) -> R,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        let lock_path = lock_path(&self.lock_dir, &sanitized_key);
+        self.update_value(key, &sanitized_key, &lock_path, create, f)
+    }
+
+    /// Get a handle on the `i64` counter `key`, which caches its shm file and lock names, see
+    /// [`Counter`].
+    pub fn counter(&self, key: &str) -> Result<Counter<'_>, ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        let lock_path = lock_path(&self.lock_dir, &sanitized_key);
+        Ok(Counter::new(self, key, sanitized_key, lock_path))
+    }
+
+    /// Read the value of the counter `sanitized_key`, locked at `lock_path`, 0 if absent or
+    /// expired.
+    pub(crate) fn read_counter(
+        &self,
+        sanitized_key: &str,
+        lock_path: &Path,
+    ) -> Result<i64, ShmapError> {
+        let _guard = ReadGuard::lock(lock_path)?;
+
+        let metadata = self.get_metadata_unlocked(sanitized_key)?;
+        Ok(self
+            .read_live_unlocked(sanitized_key, metadata.as_ref())?
+            .unwrap_or_default())
+    }
+
+    /// Modify the value of an item with `f` under its lock at `lock_path`, an absent or expired
+    /// item being the default value, which is only written if `create` is set.
+    pub(crate) fn update_value<T, R, F>(
+        &self,
+        key: &str,
+        sanitized_key: &str,
+        lock_path: &Path,
+        create: bool,
+        f: F,
+    ) -> Result<R, ShmapError>
+    where
+        T: Serialize + DeserializeOwned + Default,
+        F: FnOnce(&mut T) -> R,
+    {
+        self.check_config()?;
+        let metadata_key = metadata_key(sanitized_key);
+        let _guard = WriteGuard::lock(lock_path)?;
+
+        let mut metadata = match self.read_unlocked(&metadata_key, &[])? {
+            Content::Value(bytes) => Some(decode_metadata(&metadata_key, &bytes)?),
+            Content::Missing | Content::Empty => None,
+        };
+        let bytes = match &metadata {
+            Some(metadata) if self.cipher.is_some() && metadata.aad => {
+                return Err(ShmapError::AadMismatch);
+            }
+            Some(metadata) if self.is_expired(metadata) => None,
+            _ => {
+                self.check_type::<T>(metadata.as_ref())?;
+                self.read_value_unlocked(sanitized_key, metadata.as_ref(), &[])?
+            }
+        };
+        let mut value = match bytes {
+            Some(mut bytes) => self.deserialize_zeroize(sanitized_key, &mut bytes)?,
+            None if create => {
+                let mut created = self.new_metadata(key, self.default_ttl)?;
+                self.tag_type::<T>(&mut created);
+                metadata = Some(created);
+                T::default()
+            }
+            None => return Ok(f(&mut T::default())),
+        };
+        let ret = f(&mut value);
+        let mut bytes = serialize(self.format, sanitized_key, &value)?;
+
+        let written = self.write_migrated_unlocked(sanitized_key, &bytes, metadata.as_mut());
+        if self.protect_plaintext() {
+            bytes.zeroize();
+        }
+        if let Err(e) = written {
+            let _ = self.unlink_item_unlocked(sanitized_key);
+            return Err(e);
+        }
+        self.record_changes([key])?;
+        Ok(ret)
+    }
+
+    /// Write the migrated value of an item, then its `metadata` if any, the caller holding its
+    /// lock.
+    fn write_migrated_unlocked(
+        &self,
+        sanitized_key: &str,
+        value: &[u8],
+        metadata: Option<&mut Metadata>,
+    ) -> Result<(), ShmapError> {
+        let Some(metadata) = metadata else {
+            // Without metadata to record parts, like a raw insert
+            self.write_unlocked(sanitized_key, value, &[])?;
+            self.unlink_parts(sanitized_key, 1);
+            return Ok(());
+        };
+        let parts = self.write_value_unlocked(sanitized_key, value, &[])?;
+        metadata.parts = parts;
+        metadata.len = if parts > 0 { value.len() as u64 } else { 0 };
+        let metadata_key = metadata_key(sanitized_key);
+        let bytes = encode_metadata(&metadata_key, metadata)?;
+        self.write_unlocked(&metadata_key, &bytes, &[])
+    }
+
+    /// Insert several items, using `bincode` serialization, with the default TTL if any, so that
+    /// either all of them or none are written. If a key is given several times, its last value is
+    /// written.
+    ///
+    /// The locks of all the items are held, while their values and metadata are written to shadow
+    /// files, which are then renamed over the items: a failure before the renames (e.g. a
+    /// serialization error or a full tmpfs) leaves all the items untouched. Each rename is atomic,
+    /// but not the whole set: a crash during the renames leaves only some items updated. Readers,
+    /// which take the locks of several items one by one, may also see them at different times.
+    /// A failure during the renames returns [`ShmapError::PartialTransaction`], with the keys of
+    /// the items already written: the item being renamed may have its new value with its previous
+    /// metadata, and the others are untouched.
+    ///
+    /// Values are never split in parts (see [`ShmapBuilder::with_chunk_size`]).
+    pub fn transaction<T>(&self, entries: &[(&str, T)]) -> Result<(), ShmapError>
+    where
+        T: Serialize,
+    {
+        let mut items = BTreeMap::<String, (Vec<u8>, Metadata)>::new();
+        let mut ret = Ok(());
+        for (key, value) in entries {
+            let entry = self.sanitize_key(key).and_then(|sanitized_key| {
+                let bytes = serialize(self.format, &sanitized_key, value)?;
+                let mut metadata = self.new_metadata(key, self.default_ttl)?;
+                self.tag_type::<T>(&mut metadata);
+                Ok((sanitized_key, bytes, metadata))
+            });
+            match entry {
+                Ok((sanitized_key, bytes, metadata)) => {
+                    if let Some((mut previous, _)) = items.insert(sanitized_key, (bytes, metadata))
+                    {
+                        if self.protect_plaintext() {
+                            previous.zeroize();
+                        }
+                    }
+                }
+                Err(e) => {
+                    ret = Err(e);
+                    break;
+                }
+            }
+        }
+        if ret.is_ok() {
+            ret = self.commit_transaction(&items);
+        }
+        if self.protect_plaintext() {
+            for (bytes, _) in items.values_mut() {
+                bytes.zeroize();
+            }
+        }
+        ret
+    }
+
+    /// Write items to shadow files under their locks, taken in the order of their names so that
+    /// concurrent transactions do not deadlock, then rename them over the items.
+    fn commit_transaction(
+        &self,
+        items: &BTreeMap<String, (Vec<u8>, Metadata)>,
+    ) -> Result<(), ShmapError> {
+        self.check_config()?;
+        if let (Some(max_bytes), Some(first_key)) = (self.max_bytes, items.keys().next()) {
+            // Other items of the transaction may be evicted, but they are written again anyway
+            let incoming = items.values().map(|(bytes, _)| bytes.len()).sum();
+            self.evict(first_key, incoming, max_bytes)?;
+        }
+
+        let _guards = items
+            .keys()
+            .map(|sanitized_key| WriteGuard::lock(&lock_path(&self.lock_dir, sanitized_key)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut shadows = Vec::new();
+        let ret = items
+            .iter()
+            .try_for_each(|(sanitized_key, (bytes, metadata))| {
+                let shadow = shadow_key(sanitized_key);
+                shadows.push(shadow.clone());
+                self.write_file_unlocked(&shadow, sanitized_key, bytes, &[])?;
+
+                let metadata_key = metadata_key(sanitized_key);
+                let metadata_shadow = shadow_key(&metadata_key);
+                shadows.push(metadata_shadow.clone());
+                let bytes = encode_metadata(&metadata_key, metadata)?;
+                self.write_file_unlocked(&metadata_shadow, &metadata_key, &bytes, &[])
+            });
+        if let Err(e) = ret {
+            for shadow in &shadows {
+                let _ = self.unlink(shadow);
+            }
+            return Err(e);
+        }
+
+        let mut committed = Vec::new();
+        for (sanitized_key, (_, metadata)) in items {
+            let metadata_key = metadata_key(sanitized_key);
+            let renamed = self
+                .rename(&shadow_key(sanitized_key), sanitized_key)
+                .and_then(|()| self.rename(&shadow_key(&metadata_key), &metadata_key));
+            if let Err(e) = renamed {
+                // Shadows left to rename, the others are already gone
+                for shadow in &shadows {
+                    let _ = self.unlink(shadow);
+                }
+                let _ = self.record_changes(committed.iter().map(String::as_str));
+                return Err(ShmapError::PartialTransaction {
+                    committed,
+                    source: Box::new(e),
+                });
+            }
+            // Parts of a previous value
+            self.unlink_parts(sanitized_key, 1);
+            committed.push(metadata.key.clone());
+        }
+        self.record_changes(committed.iter().map(String::as_str))
+    }
+
+    /// Insert a new item, using `bincode` serialization, which never expires even if a default TTL
+    /// is set.
+    pub fn insert_no_expiry<T>(&self, key: &str, value: T) -> Result<(), ShmapError>
+    where
+        T: Serialize,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        self.insert_serialize(&sanitized_key, &value, self.new_metadata(key, None)?)
+    }
+
+    /// Insert a new item, using `bincode` serialization, with the default TTL if any, owned by the
+    /// current process: its process id is recorded in the metadata (see
+    /// [`ShmapBuilder::with_creator_pid`]), and [`Shmap::reap_dead_owned`] removes it once the
+    /// process exited, even if it did not remove it itself.
+    pub fn insert_owned<T>(&self, key: &str, value: T) -> Result<(), ShmapError>
+    where
+        T: Serialize,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        let mut metadata = self.new_metadata(key, self.default_ttl)?;
+        metadata.creator_pid = Some(std::process::id());
+        metadata.owned = true;
+        self.insert_serialize(&sanitized_key, &value, metadata)
+    }
+
+    /// Insert a new item, using `bincode` serialization, with a TTL.
+    pub fn insert_with_ttl<T>(&self, key: &str, value: T, ttl: Duration) -> Result<(), ShmapError>
+    where
+        T: Serialize,
+    {
+        self.insert_ref_with_ttl(key, &value, ttl)
+    }
+
+    /// Insert a new item from a reference, using `bincode` serialization, with a TTL.
+    pub fn insert_ref_with_ttl<T>(
+        &self,
+        key: &str,
+        value: &T,
+        ttl: Duration,
+    ) -> Result<(), ShmapError>
+    where
+        T: Serialize + ?Sized,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        self.insert_serialize(&sanitized_key, value, self.new_metadata(key, Some(ttl))?)
+    }
+
+    /// Insert a new item, using `bincode` serialization, with a TTL, like
+    /// [`Shmap::insert_with_ttl`], and return the previous value if any, deserialized to T.
+    ///
+    /// The previous value is read and replaced under the item lock, so that when several threads
+    /// or processes race to populate the same item, each one learns whether it overwrote another.
+    /// The item is kept if the previous value can not be deserialized.
+    // Taken by value like `Shmap::insert_with_ttl`
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn insert_with_ttl_prev<T>(
+        &self,
+        key: &str,
+        value: T,
+        ttl: Duration,
+    ) -> Result<Option<T>, ShmapError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        self.insert_serialize_prev(
+            &sanitized_key,
+            &value,
+            self.new_metadata(key, Some(ttl))?,
+            false,
+        )
+    }
+
+    /// Set the value of an item and return its previous one, deserialized (using `bincode`) to T,
+    /// in the manner of Redis `GETSET`.
+    ///
+    /// Like [`Shmap::insert_with_ttl_prev`], both happen under the item lock, so that concurrent
+    /// swaps each get the value written by another one. The TTL of the previous value is kept,
+    /// while an absent or expired item is inserted with the default TTL if any, like with
+    /// [`Shmap::insert`].
+    // Taken by value like `Shmap::insert`
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn get_set<T>(&self, key: &str, value: T) -> Result<Option<T>, ShmapError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        self.insert_serialize_prev(
+            &sanitized_key,
+            &value,
+            self.new_metadata(key, self.default_ttl)?,
+            true,
+        )
+    }
+
+    /// Insert an item with `metadata` and return its previous value, keeping the TTL of the
+    /// previous value if `keep_ttl` is set.
+    fn insert_serialize_prev<T>(
+        &self,
+        sanitized_key: &str,
+        value: &T,
+        mut metadata: Metadata,
+        keep_ttl: bool,
+    ) -> Result<Option<T>, ShmapError>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        self.tag_type::<T>(&mut metadata);
+        let mut bytes = serialize(self.format, sanitized_key, value)?;
+        self.check_config()?;
+        if let Some(max_bytes) = self.max_bytes {
+            self.evict(sanitized_key, bytes.len(), max_bytes)?;
+        }
+
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, sanitized_key))?;
+
+        let previous_metadata = self.get_metadata_unlocked(sanitized_key)?;
+        let previous = self.read_live_unlocked(sanitized_key, previous_metadata.as_ref())?;
+        if let Some(previous_metadata) =
+            previous_metadata.filter(|_| keep_ttl && previous.is_some())
+        {
+            metadata.expiration = previous_metadata.expiration;
+            metadata.monotonic_expiration = previous_metadata.monotonic_expiration;
+        }
+
+        let written = self.write_item_unlocked(sanitized_key, &bytes, &[], &mut metadata);
+        if self.protect_plaintext() {
+            bytes.zeroize();
+        }
+        if let Err(e) = written {
+            let _ = self.unlink_item_unlocked(sanitized_key);
+            return Err(e);
+        }
+        Ok(previous)
+    }
+
+    /// Get an item value by its key, like [`Shmap::get`], or compute it with `f` and insert it
+    /// with `ttl` if it is absent or expired.
+    ///
+    /// Both happen under the item lock, so that when several threads or processes miss the same
+    /// item, `f` only runs once and the others get its value. `f` must thus not access the item
+    /// itself, which would deadlock. Like [`Shmap::migrate`], the inserted value is not subject to
+    /// eviction, and items inserted with [`Shmap::insert_with_aad`] fail with
+    /// [`ShmapError::AadMismatch`].
+    pub fn get_or_insert_with_ttl<T, F>(
+        &self,
+        key: &str,
+        ttl: Duration,
+        f: F,
+    ) -> Result<T, ShmapError>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> T,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        self.check_config()?;
+        let metadata_key = metadata_key(&sanitized_key);
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, &sanitized_key))?;
+
+        let current = match self.read_unlocked(&metadata_key, &[])? {
+            Content::Value(bytes) => Some(decode_metadata(&metadata_key, &bytes)?),
+            Content::Missing | Content::Empty => None,
+        };
+        let bytes = match &current {
+            Some(current) if self.cipher.is_some() && current.aad => {
+                return Err(ShmapError::AadMismatch);
+            }
+            Some(current) if self.is_expired(current) => None,
+            Some(current) => {
+                self.check_type::<T>(Some(current))?;
+                self.read_value_unlocked(&sanitized_key, Some(current), &[])?
+            }
+            // Raw items have no metadata, thus no TTL: they are replaced
+            None => None,
+        };
+        if let Some(mut bytes) = bytes {
+            return self.deserialize_zeroize(&sanitized_key, &mut bytes);
+        }
+
+        let value = f();
+        // The TTL starts once the value is computed
+        let mut metadata = self.new_metadata(key, Some(ttl))?;
+        self.tag_type::<T>(&mut metadata);
+        let mut bytes = serialize(self.format, &sanitized_key, &value)?;
+        let written = self.write_item_unlocked(&sanitized_key, &bytes, &[], &mut metadata);
+        if self.protect_plaintext() {
+            bytes.zeroize();
+        }
+        if let Err(e) = written {
+            let _ = self.unlink_item_unlocked(&sanitized_key);
+            return Err(e);
+        }
+        Ok(value)
+    }
+
+    /// Insert a new item, without serialization, with the default TTL if any, from an `Arc<[u8]>`,
+    /// e.g. shared with an in-process cache. Read it with [`Shmap::get_arc`] or
+    /// [`Shmap::get_raw`].
+    ///
+    /// Unlike with [`Shmap::insert_raw`], metadata is written, so the item is listed by
+    /// [`Shmap::keys`].
+    // Taken by value, as held by the caches
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn insert_arc(&self, key: &str, value: Arc<[u8]>) -> Result<(), ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        self.insert_bytes(
+            &sanitized_key,
+            &value,
+            &[],
+            self.new_metadata(key, self.default_ttl)?,
+        )
+    }
+
+    /// Insert a new item, without serialization, with a TTL.
+    pub fn insert_raw_with_ttl(
+        &self,
+        key: &str,
+        value: &[u8],
+        ttl: Duration,
+    ) -> Result<(), ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        self.insert_bytes(
+            &sanitized_key,
+            value,
+            &[],
+            self.new_metadata(key, Some(ttl))?,
+        )
+    }
+
+    /// Whether an item is expired, according to the clock of the Shmap.
+    fn is_expired(&self, metadata: &Metadata) -> bool {
+        metadata.is_expired_on(&*self.clock)
+    }
+
+    fn new_metadata(&self, key: &str, ttl: Option<Duration>) -> Result<Metadata, ShmapError> {
+        let mut metadata = Metadata::new(
+            key,
+            self.namespace.as_deref(),
+            ttl,
+            self.cipher.is_some(),
+            &*self.clock,
+        )?;
+        if self.creator_pid {
+            metadata.creator_pid = Some(std::process::id());
+        }
+        Ok(metadata)
+    }
+
+    fn insert_serialize<T>(
+        &self,
+        sanitized_key: &str,
+        value: &T,
+        mut metadata: Metadata,
+    ) -> Result<(), ShmapError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.tag_type::<T>(&mut metadata);
+        let mut bytes = serialize(self.format, sanitized_key, value)?;
+        let ret = self.insert_bytes(sanitized_key, &bytes, &[], metadata);
+        if self.protect_plaintext() {
+            bytes.zeroize();
+        }
+        ret
+    }
+
+    /// Insert the value of an item, then its metadata, which records whether the value was split
+    /// in parts. Both are written under the same lock, so that the item is never seen with the
+    /// metadata of a previous value.
+    fn insert_bytes(
+        &self,
+        sanitized_key: &str,
+        value: &[u8],
+        aad: &[u8],
+        metadata: Metadata,
+    ) -> Result<(), ShmapError> {
+        self.insert_bytes_with(self, sanitized_key, value, aad, metadata)
+    }
+
+    /// Insert an item like [`Shmap::insert_bytes`], its value being written, thus encrypted, by
+    /// `writer`.
+    fn insert_bytes_with(
+        &self,
+        writer: &Self,
+        sanitized_key: &str,
+        value: &[u8],
+        aad: &[u8],
+        mut metadata: Metadata,
+    ) -> Result<(), ShmapError> {
+        self.check_config()?;
+        if let Some(max_bytes) = self.max_bytes {
+            self.evict(sanitized_key, value.len(), max_bytes)?;
+        }
+
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, sanitized_key))?;
+
+        let ret = self.write_item_with_unlocked(writer, sanitized_key, value, aad, &mut metadata);
+        if ret.is_err() {
+            let _ = self.unlink_item_unlocked(sanitized_key);
+        }
+        ret
+    }
+
+    /// Write the value of an item, then its `metadata`, the caller holding its lock.
+    fn write_item_unlocked(
+        &self,
+        sanitized_key: &str,
+        value: &[u8],
+        aad: &[u8],
+        metadata: &mut Metadata,
+    ) -> Result<(), ShmapError> {
+        self.write_item_with_unlocked(self, sanitized_key, value, aad, metadata)
+    }
+
+    /// Write an item like [`Shmap::write_item_unlocked`], its value being written by `writer`.
+    fn write_item_with_unlocked(
+        &self,
+        writer: &Self,
+        sanitized_key: &str,
+        value: &[u8],
+        aad: &[u8],
+        metadata: &mut Metadata,
+    ) -> Result<(), ShmapError> {
+        let parts = writer.write_value_unlocked(sanitized_key, value, aad)?;
+        metadata.parts = parts;
+        if parts > 0 {
+            metadata.len = value.len() as u64;
+        }
+        let metadata_key = metadata_key(sanitized_key);
+        let bytes = encode_metadata(&metadata_key, metadata)?;
+        self.write_unlocked(&metadata_key, &bytes, &[])?;
+        self.record_changes([metadata.key.as_str()])
+    }
+
+    /// Insert a new item, without serialization.
+    ///
+    /// No metadata is written, so the value is never split in parts (see
+    /// [`ShmapBuilder::with_chunk_size`]).
+    pub fn insert_raw(&self, key: &str, value: &[u8]) -> Result<(), ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        self._insert(&sanitized_key, value)?;
+        self.record_changes([key])
+    }
+
+    /// Insert a short-lived item, using `bincode` serialization, without metadata: only the value
+    /// file is written, which roughly halves the cost of an insert. Read it with
+    /// [`Shmap::get_ephemeral`]. A previous item of the key, with metadata, is removed.
+    ///
+    /// Without metadata, the item has no TTL and is not listed by [`Shmap::keys`]: it is removed
+    /// by [`Shmap::clean`] once it has not been written for 5 seconds, and counted apart from the
+    /// orphans (see [`CleanReport::ephemeral`]). The value is still encrypted if an encryption
+    /// key is set, and never split in parts.
+    pub fn insert_ephemeral<T>(&self, key: &str, value: &T) -> Result<(), ShmapError>
+    where
+        T: Serialize + ?Sized,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        let mut bytes = serialize(self.format, &sanitized_key, value)?;
+        let ret = self.write_ephemeral(&sanitized_key, &bytes);
+        if self.protect_plaintext() {
+            bytes.zeroize();
+        }
+        ret?;
+        self.record_changes([key])
+    }
+
+    fn write_ephemeral(&self, sanitized_key: &str, value: &[u8]) -> Result<(), ShmapError> {
+        self.check_config()?;
+        if let Some(max_bytes) = self.max_bytes {
+            self.evict(sanitized_key, value.len(), max_bytes)?;
+        }
+
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, sanitized_key))?;
+        // A previous item with metadata would be read instead, see `Shmap::get_ephemeral`
+        self.unlink(sanitized_key)?;
+        self.unlink_parts(sanitized_key, 1);
+        self.unlink(&metadata_key(sanitized_key))?;
+        self.write_unlocked(&ephemeral_key(sanitized_key), value, &[])
+    }
+
+    /// Get an item inserted with [`Shmap::insert_ephemeral`], and deserialize it (using
+    /// `bincode`) to T. Items with metadata are read like with [`Shmap::get`].
+    pub fn get_ephemeral<T>(&self, key: &str) -> Result<Option<T>, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        if self.get_metadata(&sanitized_key)?.is_some() {
+            return match self.get_status_sanitized(&sanitized_key, None)? {
+                GetStatus::Present(value) => Ok(Some(value)),
+                GetStatus::Expired | GetStatus::Missing => Ok(None),
+            };
+        }
+        let ephemeral_key = ephemeral_key(&sanitized_key);
+        match self._read(&ephemeral_key, true, &[])? {
+            Some(mut bytes) => Ok(Some(self.deserialize_zeroize(&ephemeral_key, &mut bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Insert a new item, without serialization, like [`Shmap::insert_raw`], and return the name of
+    /// its shm file in the Shmap directory, so that another process can open and map it directly.
+    ///
+    /// The file holds the value as is, from its first byte, unless an encryption key is set (see
+    /// [`Shmap::open_fd`]).
+    pub fn insert_raw_named(&self, key: &str, value: &[u8]) -> Result<String, ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        self._insert(&sanitized_key, value)?;
+        self.record_changes([key])?;
+        Ok(sanitized_key)
+    }
+
+    fn _insert(&self, sanitized_key: &str, value: &[u8]) -> Result<(), ShmapError> {
+        self.check_config()?;
+        let is_item = is_item_filename(sanitized_key);
+        if let Some(max_bytes) = self.max_bytes {
+            if is_item {
+                self.evict(sanitized_key, value.len(), max_bytes)?;
+            }
+        }
+
+        let guard = WriteGuard::lock(&lock_path(&self.lock_dir, sanitized_key))?;
+
+        match self.write_unlocked(sanitized_key, value, &[]) {
+            Ok(()) => {
+                if is_item {
+                    // Parts of a previous value
+                    self.unlink_parts(sanitized_key, 1);
+                }
+                Ok(())
+            }
+            // The previous value is left intact
+            Err(e) if self.atomic_writes => Err(e),
+            Err(e) => {
+                drop(guard);
+                let _ = self._remove(sanitized_key);
+                Err(e)
+            }
+        }
+    }
+
+    /// Write an item value, split in parts if it is larger than the chunk size, the caller holding
+    /// its lock. Return the number of parts, 0 if not split.
+    fn write_value_unlocked(
+        &self,
+        sanitized_key: &str,
+        value: &[u8],
+        aad: &[u8],
+    ) -> Result<u32, ShmapError> {
+        let parts = match self.chunk_size {
+            Some(chunk_size) if value.len() > chunk_size => {
+                self.write_parts(sanitized_key, value, aad, chunk_size)?
+            }
+            _ => {
+                self.write_unlocked(sanitized_key, value, aad)?;
+                0
+            }
+        };
+        // Parts of a previous value
+        self.unlink_parts(sanitized_key, parts.max(1) as usize);
+        Ok(parts)
+    }
+
+    /// Write an item value split in parts of `chunk_size`, the caller holding its lock. The first
+    /// part is stored in the item file, and the following ones in `<item>.part<n>` files.
+    fn write_parts(
+        &self,
+        sanitized_key: &str,
+        value: &[u8],
+        aad: &[u8],
+        chunk_size: usize,
+    ) -> Result<u32, ShmapError> {
+        let parts = u32::try_from(value.len().div_ceil(chunk_size))
+            .map_err(|_| ShmapError::ValueTooLarge)?;
+        for (part, chunk) in value.chunks(chunk_size).enumerate() {
+            self.write_unlocked(&part_key(sanitized_key, part), chunk, aad)?;
+        }
+        Ok(parts)
+    }
+
+    /// Unlink the parts of an item value from `from`, the caller holding its lock.
+    fn unlink_parts(&self, sanitized_key: &str, from: usize) {
+        for part in from.. {
+            let part_key = part_key(sanitized_key, part);
+            if fs::symlink_metadata(self.dir.join(&part_key)).is_err() {
+                break;
+            }
+            let _ = self.unlink(&part_key);
+        }
+    }
+
+    /// Write an item to shm, the caller holding its lock. See [`Shmap::read_unlocked`] for `aad`.
+    ///
+    /// With [`ShmapBuilder::with_atomic_writes`], it is written to a shadow file renamed over the
+    /// previous one.
+    fn write_unlocked(
+        &self,
+        sanitized_key: &str,
+        value: &[u8],
+        aad: &[u8],
+    ) -> Result<(), ShmapError> {
+        if !self.atomic_writes {
+            return self.write_file_unlocked(sanitized_key, sanitized_key, value, aad);
+        }
+        let shadow = shadow_key(sanitized_key);
+        let written = self
+            .write_file_unlocked(&shadow, sanitized_key, value, aad)
+            .and_then(|()| self.rename(&shadow, sanitized_key));
+        if written.is_err() {
+            let _ = self.unlink(&shadow);
+        }
+        written
+    }
+
+    /// Write the value of `sanitized_key` to the `filename` shm file, which is only different
+    /// for a file to be renamed afterwards, the caller holding the item lock.
+    fn write_file_unlocked(
+        &self,
+        filename: &str,
+        sanitized_key: &str,
+        value: &[u8],
+        aad: &[u8],
+    ) -> Result<(), ShmapError> {
+        let bytes = self.seal(sanitized_key, value, aad)?;
+        self.write_shm_unlocked(filename, &bytes)?;
+        self.write_durable(filename, &bytes)
+    }
+
+    /// Bytes to store for `value`: encrypted if an encryption key was provided, see
+    /// [`Shmap::read_unlocked`] for `aad`.
+    fn seal(&self, sanitized_key: &str, value: &[u8], aad: &[u8]) -> Result<Vec<u8>, ShmapError> {
+        let Some(cipher) = &self.cipher else {
+            return Ok(value.to_vec());
+        };
+        let mut nonce: Vec<u8> = (0..12).collect();
+        nonce.shuffle(&mut thread_rng());
+        let payload = Payload {
+            msg: value,
+            aad: &[sanitized_key.as_bytes(), aad].concat(),
+        };
+        let mut ciphertext = cipher.encrypt(Nonce::from_slice(nonce.as_slice()), payload)?;
+        nonce.append(&mut ciphertext);
+        Ok(nonce)
+    }
+
+    /// Write `bytes`, already encrypted if needed, to the `filename` shm file, the caller holding
+    /// the item lock.
+    fn write_shm_unlocked(&self, filename: &str, bytes: &[u8]) -> Result<(), ShmapError> {
+        let fd = shm::open_write(&self.dir, filename, bytes.len(), self.fallocate)?;
+        self.copy_to_shm(fd, bytes)
+    }
+
+    /// Copy `bytes` to the shm file opened as `fd`, already sized for them.
+    fn copy_to_shm(&self, fd: shm::Fd, bytes: &[u8]) -> Result<(), ShmapError> {
+        // SAFETY: libc call is unsafe
+        let mut mmap = unsafe { MmapMut::map_mut(fd) }?;
+        if self.protect_plaintext() {
+            mmap.lock().map_err(ShmapError::MlockError)?;
+        }
+        mmap.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Write the copy of the `filename` shm file to the durable directory, if any, the caller
+    /// holding the item lock. It is synced, and atomically replaces the previous copy.
+    fn write_durable(&self, filename: &str, bytes: &[u8]) -> Result<(), ShmapError> {
+        let Some(durable_dir) = &self.durable_dir else {
+            return Ok(());
+        };
+        let tmp_path = durable_dir.join(format!("{filename}.{DURABLE_TMP_SUFFIX}"));
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(tmp_path, durable_dir.join(filename))?;
+        Ok(())
+    }
+
+    /// Restore the files of the durable directory which are missing from the items directory,
+    /// e.g. after a reboot cleared `/dev/shm`, see [`ShmapBuilder::with_durable_dir`].
+    fn warm_up(&self) -> Result<(), ShmapError> {
+        let Some(durable_dir) = &self.durable_dir else {
+            return Ok(());
+        };
+        fs::create_dir_all(durable_dir)?;
+        let mut filenames = fs::read_dir(durable_dir)?
+            .flatten()
+            .filter_map(|dir_entry| dir_entry.file_name().into_string().ok())
+            .filter(|filename| {
+                filename.starts_with(SHMAP_PREFIX)
+                    && !has_suffix(filename, SHADOW_SUFFIX)
+                    && !has_suffix(filename, DURABLE_TMP_SUFFIX)
+            })
+            .collect::<Vec<_>>();
+        // Metadata last, as when they are inserted, see `Shmap::clean_item`
+        filenames.sort_by_key(|filename| has_suffix(filename, METADATA_SUFFIX));
+        let mut restored = 0;
+        for filename in filenames {
+            // Parts are locked with their item
+            let item_key = part_item_key(&filename).unwrap_or(filename.as_str());
+            let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, item_key))?;
+            if fs::symlink_metadata(self.dir.join(&filename)).is_ok() {
+                continue;
+            }
+            let bytes = fs::read(durable_dir.join(&filename))?;
+            self.write_shm_unlocked(&filename, &bytes)?;
+            restored += 1;
+        }
+        if restored > 0 {
+            log!(
+                target: LOG_TARGET,
+                self.routine_level(),
+                op = "init",
+                files = restored;
+                "Restored durable files"
+            );
+        }
+        Ok(())
+    }
+
+    /// Unlink a shm file, and its durable copy if any.
+    fn unlink(&self, filename: &str) -> Result<(), ShmapError> {
+        if has_suffix(filename, LOCK_SUFFIX) {
+            return shm::unlink(&self.lock_dir, filename);
+        }
+        if let Some(durable_dir) = &self.durable_dir {
+            match fs::remove_file(durable_dir.join(filename)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        shm::unlink(&self.dir, filename)
+    }
+
+    /// Rename a shm file, and its durable copy if any, the caller holding the item lock.
+    fn rename(&self, from: &str, to: &str) -> Result<(), ShmapError> {
+        shm::rename(&self.dir, from, to)?;
+        if let Some(durable_dir) = &self.durable_dir {
+            fs::rename(durable_dir.join(from), durable_dir.join(to))?;
+        }
+        Ok(())
+    }
+
+    /// Read, modify and write back the metadata of an item, under its lock. Nothing is done if the
+    /// metadata is not found.
+    fn update_metadata<F>(&self, sanitized_key: &str, f: F) -> Result<(), ShmapError>
+    where
+        F: FnOnce(&mut Metadata),
+    {
+        self.check_config()?;
+        let metadata_key = metadata_key(sanitized_key);
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, sanitized_key))?;
+
+        let Content::Value(bytes) = self.read_unlocked(&metadata_key, &[])? else {
+            return Ok(());
+        };
+        let mut metadata = decode_metadata(&metadata_key, &bytes)?;
+        f(&mut metadata);
+        let bytes = encode_metadata(&metadata_key, &metadata)?;
+        self.write_unlocked(&metadata_key, &bytes, &[])
+            .inspect_err(|_| {
+                let _ = self.unlink(&metadata_key);
+            })
+    }
+
+    /// Evict items, other than the one being inserted, until `incoming` bytes fit in `max_bytes`.
+    fn evict(
+        &self,
+        sanitized_key: &str,
+        incoming: usize,
+        max_bytes: usize,
+    ) -> Result<(), ShmapError> {
+        let Some(read_dir) = self.read_dir()? else {
+            return Ok(());
+        };
+        let mut candidates = Vec::<Candidate>::new();
+        for dir_entry in read_dir.flatten() {
+            let Ok(filename) = dir_entry.file_name().into_string() else {
+                continue;
+            };
+            if filename == sanitized_key || !is_item_filename(&filename) {
+                continue;
+            }
+            let Ok(file_metadata) = dir_entry.metadata() else {
+                continue;
+            };
+            let Ok(Some(metadata)) = self.get_metadata(&filename) else {
+                continue;
+            };
+            if metadata.namespace != self.namespace {
+                continue;
+            }
+            let modified = file_metadata
+                .modified()
+                .map_or(DateTime::UNIX_EPOCH, DateTime::<Utc>::from);
+            candidates.push(Candidate {
+                sanitized_key: filename,
+                size: if metadata.parts > 0 {
+                    metadata.len
+                } else {
+                    file_metadata.len()
+                },
+                metadata,
+                modified,
+            });
+        }
+
+        let max_bytes = u64::try_from(max_bytes).unwrap_or(u64::MAX);
+        let incoming = u64::try_from(incoming).unwrap_or(u64::MAX);
+        let mut total: u64 = candidates.iter().map(|candidate| candidate.size).sum();
+        if total.saturating_add(incoming) <= max_bytes {
+            return Ok(());
+        }
+
+        self.eviction_policy.sort(&mut candidates);
+        for candidate in candidates {
+            if total.saturating_add(incoming) <= max_bytes {
+                break;
+            }
+            log!(
+                target: LOG_TARGET,
+                self.routine_level(),
+                key = candidate.sanitized_key.as_str(),
+                op = "evict",
+                size = candidate.size;
+                "Item evicted to fit in {max_bytes} bytes"
+            );
+            self.remove_item(&candidate.sanitized_key)?;
+            total -= candidate.size;
+        }
+        Ok(())
+    }
+
+    /// Set the TTL of all the live items whose key starts with `prefix`, without rewriting their
+    /// values. Return the number of items updated.
+    pub fn expire_prefix(&self, prefix: &str, ttl: Duration) -> Result<usize, ShmapError> {
+        // Fail early if the TTL is out of range
+        metadata::expiration_after(self.clock.now(), ttl)?;
+        let mut updated = 0;
+        for (filename, metadata) in self.live_items()? {
+            if !metadata.key.starts_with(prefix) {
+                continue;
+            }
+            let mut live = false;
+            self.update_metadata(&filename, |metadata| {
+                // It may have expired meanwhile
+                if !self.is_expired(metadata) {
+                    live = metadata.set_ttl(ttl, &*self.clock).is_ok();
+                }
+            })?;
+            updated += usize::from(live);
+        }
+        Ok(updated)
+    }
+
+    /// Keep only the live items for which `f`, called with their key and metadata, returns `true`,
+    /// and remove the others. Return the number of items removed.
+    ///
+    /// An item whose metadata changed since `f` was called is kept.
+    pub fn retain<F>(&self, mut f: F) -> Result<usize, ShmapError>
+    where
+        F: FnMut(&str, &Metadata) -> bool,
+    {
+        let mut removed = 0;
+        for (filename, metadata) in self.live_items()? {
+            if f(&metadata.key, &metadata) {
+                continue;
+            }
+            let metadata_filename = metadata_key(&filename);
+            let still_removable = || {
+                self.get_metadata_unlocked(&filename)
+                    .is_ok_and(|current| current.as_ref() == Some(&metadata))
+            };
+            if self.remove_locked(&filename, &[&filename, &metadata_filename], still_removable) {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Remove the live items of the namespace whose creator process exited, among the ones whose
+    /// process id was recorded (see [`ShmapBuilder::with_creator_pid`] and
+    /// [`Shmap::insert_owned`]). Return the number of items removed.
+    ///
+    /// Processes are checked with `kill(pid, 0)`, so items created in another pid namespace may be
+    /// removed while their creator is still running.
+    pub fn reap_dead_owners(&self) -> Result<usize, ShmapError> {
+        self.retain(|_, metadata| metadata.creator_pid.is_none_or(is_process_alive))
+    }
+
+    /// Remove the live items of the namespace inserted with [`Shmap::insert_owned`] whose creator
+    /// process exited, like [`Shmap::reap_dead_owners`], keeping the ones whose process id was only
+    /// recorded by [`ShmapBuilder::with_creator_pid`].
+    pub fn reap_dead_owned(&self) -> Result<usize, ShmapError> {
+        self.retain(|_, metadata| {
+            !metadata.owned || metadata.creator_pid.is_none_or(is_process_alive)
+        })
+    }
+
+    /// Remove an item by its key.
+    ///
+    /// Its value and metadata are removed under the same lock, so that they are never seen one
+    /// without the other.
+    pub fn remove(&self, key: &str) -> Result<(), ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        self.remove_item(&sanitized_key)?;
+        self.record_changes([key])
+    }
+
+    /// Remove an item by its key, and return its value, deserialized (using `bincode`) to T.
+    ///
+    /// The value is read and removed under the item lock, so that when several threads or
+    /// processes pop the same item, only one of them gets it. The item is kept if it can not be
+    /// deserialized.
+    pub fn pop<T>(&self, key: &str) -> Result<Option<T>, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, &sanitized_key))?;
+
+        let metadata = self.get_metadata_unlocked(&sanitized_key)?;
+        let value = self.read_live_unlocked(&sanitized_key, metadata.as_ref())?;
+        self.unlink_item_unlocked(&sanitized_key)?;
+        self.record_changes([key])?;
+        Ok(value)
+    }
+
+    /// Remove an item by its key, only if its current value, deserialized (using `bincode`) to T,
+    /// is `expected`. Return whether it was removed.
+    ///
+    /// The value is compared and removed under the item lock, e.g. to release a lease only if it
+    /// is still held by its owner. Expired items are never removed.
+    pub fn remove_if<T>(&self, key: &str, expected: &T) -> Result<bool, ShmapError>
+    where
+        T: DeserializeOwned + PartialEq,
+    {
+        let sanitized_key = self.sanitize_key(key)?;
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, &sanitized_key))?;
+
+        let metadata = self.get_metadata_unlocked(&sanitized_key)?;
+        let current = self.read_live_unlocked::<T>(&sanitized_key, metadata.as_ref())?;
+        if current.as_ref() != Some(expected) {
+            return Ok(false);
+        }
+        self.unlink_item_unlocked(&sanitized_key)?;
+        self.record_changes([key])?;
+        Ok(true)
+    }
+
+    /// Read the value of an item with `metadata`, deserialized to T, the caller holding its lock.
+    /// Return `None` if it is absent or expired.
+    fn read_live_unlocked<T>(
+        &self,
+        sanitized_key: &str,
+        metadata: Option<&Metadata>,
+    ) -> Result<Option<T>, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        let value = match metadata {
+            Some(metadata) if self.cipher.is_some() && metadata.aad => {
+                return Err(ShmapError::AadMismatch);
+            }
+            Some(metadata) if self.is_expired(metadata) => None,
+            _ => {
+                self.check_type::<T>(metadata)?;
+                self.read_value_unlocked(sanitized_key, metadata, &[])?
+            }
+        };
+        match value {
+            Some(mut bytes) => Ok(Some(self.deserialize_zeroize(sanitized_key, &mut bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Unlink the value, parts and metadata of an item, the caller holding its lock.
+    fn unlink_item_unlocked(&self, sanitized_key: &str) -> Result<(), ShmapError> {
+        self.unlink(sanitized_key)?;
+        self.unlink_parts(sanitized_key, 1);
+        self.unlink(&ephemeral_key(sanitized_key))?;
+        self.unlink(&metadata_key(sanitized_key))
+    }
+
+    /// Remove an item by a binary key, see [`Shmap::insert_bytes_key`].
+    pub fn remove_bytes_key(&self, key: &[u8]) -> Result<(), ShmapError> {
+        let sanitized_key = self.sanitize_bytes_key(key)?;
+        self.remove_item(&sanitized_key)?;
+        // Named like in its metadata, see `Shmap::insert_bytes_key`
+        let key = std::str::from_utf8(key).map_or_else(|_| hex_encode(key), str::to_owned);
+        self.record_changes([key.as_str()])
+    }
+
+    /// Remove an item by its key, like [`Shmap::remove`], and also remove its lock file instead of
+    /// waiting for [`Shmap::clean`] to do it.
+    ///
+    /// The lock file is created again by its next user, so this is only worth it for keys that
+    /// are not accessed anymore.
+    pub fn remove_full(&self, key: &str) -> Result<(), ShmapError> {
+        let sanitized_key = self.sanitize_key(key)?;
+        self.remove_item(&sanitized_key)?;
+        self.record_changes([key])?;
+        // The guard was released by `remove_item`
+        self._remove(&lock_key(&sanitized_key))
+    }
+
+    /// Unlink the value, parts and metadata of an item under its lock.
+    /// Remove an item found expired, unless it was inserted again since, e.g. with
+    /// [`Shmap::insert_if_changed`].
+    fn remove_expired(&self, sanitized_key: &str) -> Result<(), ShmapError> {
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, sanitized_key))?;
+        if self
+            .get_metadata_unlocked(sanitized_key)?
+            .is_some_and(|metadata| self.is_expired(&metadata))
+        {
+            self.unlink_item_unlocked(sanitized_key)?;
+        }
+        Ok(())
+    }
+
+    fn remove_item(&self, sanitized_key: &str) -> Result<(), ShmapError> {
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, sanitized_key))?;
+        self.unlink_item_unlocked(sanitized_key)
+    }
+
+    fn _remove(&self, sanitized_key: &str) -> Result<(), ShmapError> {
+        if has_suffix(sanitized_key, LOCK_SUFFIX) {
+            // Its waiters then lock the new lock file instead, see `crate::lock`
+            let _guard = WriteGuard::lock(&self.lock_dir.join(sanitized_key))?;
+            return self.unlink(sanitized_key);
+        }
+
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, sanitized_key))?;
+        self.unlink(sanitized_key)?;
+        if is_item_filename(sanitized_key) {
+            self.unlink_parts(sanitized_key, 1);
+        }
+
+        Ok(())
+    }
+
+    /// Current generation of the store, i.e. its number of changes, and the keys of the namespace
+    /// inserted or removed since `generation`, each once, in the order of their last change.
+    /// Passing the returned generation to the next call gives the next changes, including the
+    /// ones of other processes. Only the changes made with [`ShmapBuilder::with_change_log`] are
+    /// recorded.
+    ///
+    /// The log has a bounded history: if the generation advanced by more than its capacity since
+    /// `generation`, the oldest changes are missing, and any item may have changed. It lives in
+    /// shm like the items, so it is lost on reboot, and generations start over from 0.
+    pub fn changed_since(&self, generation: u64) -> Result<(u64, Vec<String>), ShmapError> {
+        let _guard = ReadGuard::lock(&lock_path(&self.lock_dir, CHANGES_KEY))?;
+
+        let log = self.read_change_log_unlocked()?;
+        let keys = log.changed_since(self.namespace.as_deref(), generation);
+        Ok((log.generation, keys))
+    }
+
+    /// Record changes of `keys` in the change log, if enabled.
+    fn record_changes<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a str>,
+    ) -> Result<(), ShmapError> {
+        let Some(capacity) = self.change_log else {
+            return Ok(());
+        };
+        let _guard = WriteGuard::lock(&lock_path(&self.lock_dir, CHANGES_KEY))?;
+
+        let mut log = match self.read_change_log_unlocked() {
+            Ok(log) => log,
+            Err(e) => {
+                warn!(
+                    target: LOG_TARGET,
+                    key = CHANGES_KEY,
+                    op = "changes";
+                    "Change log can not be read, starting a new one: {e}"
+                );
+                ChangeLog::default()
+            }
+        };
+        for key in keys {
+            log.record(self.namespace.as_deref(), key, capacity);
+        }
+        // Not durable: generations start over with the other shm files
+        let bytes = self.seal(
+            CHANGES_KEY,
+            &serialize(Format::Bincode, CHANGES_KEY, &log)?,
+            &[],
+        )?;
+        self.write_shm_unlocked(CHANGES_KEY, &bytes)
+    }
+
+    /// Read the change log, the caller holding its lock.
+    fn read_change_log_unlocked(&self) -> Result<ChangeLog, ShmapError> {
+        match self.read_unlocked(CHANGES_KEY, &[])? {
+            Content::Value(bytes) => deserialize(Format::Bincode, None, CHANGES_KEY, &bytes),
+            Content::Missing | Content::Empty => Ok(ChangeLog::default()),
+        }
+    }
+
+    /// List available keys.
+    pub fn keys(&self) -> Result<Vec<String>, ShmapError> {
+        self.clean()
+    }
+
+    /// List available keys, sorted in lexicographic order of the original keys (unlike
+    /// [`Shmap::keys`], which follows the nondeterministic directory order).
+    pub fn keys_sorted(&self) -> Result<Vec<String>, ShmapError> {
+        let mut keys = self.keys()?;
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
+    /// Incrementally iterate over available keys, in the manner of Redis `SCAN`.
+    ///
+    /// Start with a `cursor` of 0, and call again with the returned cursor until it is 0 again.
+    /// Each call returns up to `count` live keys. A `count` of 0 is taken as 1, so that the cursor
+    /// always moves forward.
+    ///
+    /// There is no snapshot semantic (see [Listing items](crate#listing-items)), and a key may be
+    /// returned twice if the directory changes between two calls.
+    pub fn scan(&self, cursor: usize, count: usize) -> Result<(usize, Vec<String>), ShmapError> {
+        let count = count.max(1);
+        let mut keys = Vec::<String>::new();
+        let Some(read_dir) = self.read_dir()? else {
+            return Ok((0, keys));
+        };
+        let filenames = read_dir
+            .flatten()
+            .filter_map(|dir_entry| dir_entry.file_name().into_string().ok())
+            .filter(|filename| is_item_filename(filename))
+            .skip(cursor);
+        for (scanned, filename) in filenames.enumerate() {
+            if keys.len() >= count {
+                return Ok((cursor + scanned, keys));
+            }
+
+            if let Ok(Some(metadata)) = self.get_metadata(&filename) {
+                if !self.is_expired(&metadata) && metadata.namespace == self.namespace {
+                    keys.push(metadata.key);
+                }
+            }
+        }
+        Ok((0, keys))
+    }
+
+    /// Call `f` with the key and the metadata of each live item, as the directory is read, without
+    /// collecting them first like [`Shmap::keys`]. Stop at the first error returned by `f`, and
+    /// return it.
+    ///
+    /// Expired items are skipped, but not removed.
+    pub fn for_each<F>(&self, mut f: F) -> Result<(), ShmapError>
+    where
+        F: FnMut(&str, &Metadata) -> Result<(), ShmapError>,
+    {
+        for (_, metadata) in self.iter_live_items()? {
+            f(&metadata.key, &metadata)?;
+        }
+        Ok(())
+    }
+
+    /// List the live keys which will not be expired in `min_remaining`, e.g. to skip refreshing the
+    /// items about to expire anyway. Keys without TTL are always returned.
+    pub fn keys_valid_for(&self, min_remaining: Duration) -> Result<Vec<String>, ShmapError> {
+        Ok(self
+            .iter_live_items()?
+            .filter(|(_, metadata)| !metadata.expires_within(min_remaining, &*self.clock))
+            .map(|(_, metadata)| metadata.key)
+            .collect())
+    }
+
+    /// Earliest expiration of the live items, or `None` if none of them has a TTL, to schedule the
+    /// next [`Shmap::clean`] without a background thread.
+    pub fn next_expiry(&self) -> Result<Option<DateTime<Utc>>, ShmapError> {
+        Ok(self
+            .live_items()?
+            .into_iter()
+            .filter_map(|(_, metadata)| metadata.expiration)
+            .min())
+    }
+
+    /// Keys of the live items with a TTL, with their expiration, from the soonest to the latest,
+    /// e.g. to build an eviction queue. Items without TTL are not returned.
+    pub fn keys_by_expiry(&self) -> Result<Vec<(String, DateTime<Utc>)>, ShmapError> {
+        let mut keys = self
+            .live_items()?
+            .into_iter()
+            .filter_map(|(_, metadata)| Some((metadata.key, metadata.expiration?)))
+            .collect::<Vec<_>>();
+        keys.sort_by_key(|(_, expiration)| *expiration);
+        Ok(keys)
+    }
+
+    /// Count the live items by remaining TTL, e.g. for dashboards: with `buckets` in increasing
+    /// order, the count at index `i` is the one of the items expiring within `buckets[i]` but not
+    /// within the previous bucket, followed by the count of the items expiring later than the
+    /// last bucket, then by the count of the items without TTL.
+    pub fn ttl_histogram(&self, buckets: &[Duration]) -> Result<Vec<usize>, ShmapError> {
+        let mut counts = vec![0; buckets.len() + 2];
+        for (_, metadata) in self.iter_live_items()? {
+            let bucket = if metadata.expiration.is_none() {
+                buckets.len() + 1
+            } else {
+                buckets
+                    .iter()
+                    .position(|&bucket| metadata.expires_within(bucket, &*self.clock))
+                    .unwrap_or(buckets.len())
+            };
+            counts[bucket] += 1;
+        }
+        Ok(counts)
+    }
+
+    /// Table of the live items of the namespace, sorted by key, one per line after a header, with
+    /// the size of their value (of all its parts, encrypted if it is) and metadata files, whether
+    /// they are encrypted, and their remaining TTL, e.g. to be printed on a test failure.
+    ///
+    /// Items removed while the table is built are listed with the files found.
+    pub fn debug_dump(&self) -> Result<String, ShmapError> {
+        use std::fmt::Write;
+
+        let file_len = |filename: &str| {
+            fs::metadata(self.dir.join(filename)).map_or(0, |metadata| metadata.len())
+        };
+        let now = self.clock.now();
+        let mut rows = self
+            .live_items()?
+            .into_iter()
+            .map(|(filename, metadata)| {
+                let size: u64 = (0..metadata.parts.max(1) as usize)
+                    .map(|part| file_len(&part_key(&filename, part)))
+                    .sum();
+                let ttl = metadata.expiration.map_or_else(
+                    || "-".to_owned(),
+                    |expiration| {
+                        let remaining = (expiration - now).to_std().unwrap_or_default();
+                        format!("{:.3}s", remaining.as_secs_f64())
+                    },
+                );
+                (
+                    metadata.key,
+                    size,
+                    file_len(&metadata_key(&filename)),
+                    metadata.encrypted,
+                    ttl,
+                )
+            })
+            .collect::<Vec<_>>();
+        rows.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let width = rows.iter().map(|row| row.0.len()).fold(3, usize::max);
+        let mut dump = format!(
+            "{:width$}  {:>10}  {:>8}  {:9}  ttl\n",
+            "key", "size", "metadata", "encrypted"
+        );
+        for (key, size, metadata_size, encrypted, ttl) in rows {
+            let encrypted = if encrypted { "yes" } else { "no" };
+            let _ = writeln!(
+                dump,
+                "{key:width$}  {size:>10}  {metadata_size:>8}  {encrypted:9}  {ttl}"
+            );
+        }
+        Ok(dump)
+    }
+
+    /// List the live keys of all the namespaces in [`SHM_DIR`], as `(namespace, key)` pairs, the
+    /// namespace being empty for the items without one.
+    ///
+    /// Expired items are skipped, but not cleaned, and so are the items of encrypted stores, as
+    /// their metadata can not be read without the key. No Shmap is built, so nothing else is
+    /// done to the directory.
+    pub fn all_keys() -> Result<Vec<(String, String)>, ShmapError> {
+        all_namespaces_keys(Path::new(SHM_DIR))
+    }
+
+    /// File names and metadata of the live items of the namespace, found by listing the
+    /// directory, without a snapshot semantic. Items without metadata are skipped.
+    fn live_items(&self) -> Result<Vec<(String, Metadata)>, ShmapError> {
+        Ok(self.iter_live_items()?.collect())
+    }
+
+    /// Like [`Shmap::live_items`], as the directory is read.
+    fn iter_live_items(&self) -> Result<impl Iterator<Item = (String, Metadata)> + '_, ShmapError> {
+        Ok(self
+            .read_dir()?
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|dir_entry| dir_entry.file_name().into_string().ok())
+            .filter(|filename| is_item_filename(filename))
+            .filter_map(|filename| {
+                let metadata = self.get_metadata(&filename).ok()??;
+                (!self.is_expired(&metadata) && metadata.namespace == self.namespace)
+                    .then_some((filename, metadata))
+            }))
+    }
+
+    /// Entries of the items directory, or `None` if it does not exist, like a missing item is not
+    /// an error.
+    fn read_dir(&self) -> Result<Option<fs::ReadDir>, ShmapError> {
+        match fs::read_dir(&self.dir) {
+            Ok(read_dir) => Ok(Some(read_dir)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(ShmapError::IOError(e)),
+        }
+    }
+
+    /// Lock files of the lock directory, if it is not the items directory, see
+    /// [`ShmapBuilder::with_lock_dir`].
+    fn lock_dir_filenames(&self) -> Result<Vec<String>, ShmapError> {
+        if self.lock_dir == self.dir {
+            return Ok(Vec::new());
+        }
+        match fs::read_dir(&self.lock_dir) {
+            Ok(read_dir) => Ok(read_dir
+                .flatten()
+                .filter_map(|dir_entry| dir_entry.file_name().into_string().ok())
+                .filter(|filename| has_suffix(filename, LOCK_SUFFIX))
+                .collect()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(ShmapError::IOError(e)),
+        }
+    }
+
+    /// Clean expired items.
+    pub fn clean(&self) -> Result<Vec<String>, ShmapError> {
+        let (keys, _) = self._clean(ORPHAN_GRACE_PERIOD)?;
+        Ok(keys
+            .into_iter()
+            .filter(|(namespace, _)| *namespace == self.namespace)
+            .map(|(_, key)| key)
+            .collect())
+    }
+
+    /// Clean expired items, like [`Shmap::clean`], and list the live keys of several namespaces
+    /// at once, by namespace, the empty one standing for the items without namespace. This scans
+    /// the directory once, instead of once per namespace with a Shmap for each of them.
+    ///
+    /// Every namespace of `prefixes` is in the returned map, with no keys if it has no live item.
+    pub fn clean_prefixes(
+        &self,
+        prefixes: &[&str],
+    ) -> Result<HashMap<String, Vec<String>>, ShmapError> {
+        let mut buckets = prefixes
+            .iter()
+            .map(|prefix| ((*prefix).to_owned(), Vec::new()))
+            .collect::<HashMap<_, _>>();
+        let (keys, _) = self._clean(ORPHAN_GRACE_PERIOD)?;
+        for (namespace, key) in keys {
+            if let Some(bucket) = buckets.get_mut(namespace.as_deref().unwrap_or_default()) {
+                bucket.push(key);
+            }
+        }
+        Ok(buckets)
+    }
+
+    /// Clean expired items, like [`Shmap::clean`], processing at most `max_items` files (an item
+    /// has its value, metadata and lock files), or until `max_time` elapses, to bound the latency
+    /// of a call on a large store.
+    ///
+    /// Files are processed in the order of their names, each call resuming after the last file
+    /// processed by the previous one, also by the clones of the Shmap: `remaining` is set in the
+    /// report until the end of the directory is reached, then the next call starts over. Files
+    /// inserted meanwhile before the position are processed by the next pass.
+    pub fn clean_limited(
+        &self,
+        max_items: usize,
+        max_time: Duration,
+    ) -> Result<CleanReport, ShmapError> {
+        let deadline = Instant::now().checked_add(max_time);
+        let mut report = CleanReport::default();
+        let Some(read_dir) = self.read_dir()? else {
+            return Ok(report);
+        };
+        let mut filenames = read_dir
+            .flatten()
+            .filter_map(|dir_entry| dir_entry.file_name().into_string().ok())
+            .chain(self.lock_dir_filenames()?)
+            .collect::<Vec<_>>();
+        filenames.sort_unstable();
+
+        // Held until the end, so that concurrent calls do not process the same files
+        let mut cursor = self
+            .clean_cursor
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let start = cursor.as_ref().map_or(0, |cursor| {
+            filenames.partition_point(|filename| filename <= cursor)
+        });
+        let mut keys = Vec::new();
+        for (processed, filename) in filenames[start..].iter().enumerate() {
+            if processed == max_items || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                if processed > 0 {
+                    *cursor = Some(filenames[start + processed - 1].clone());
+                }
+                report.remaining = true;
+                return Ok(report);
+            }
+            self.clean_file(filename, ORPHAN_GRACE_PERIOD, &mut keys, &mut report);
+        }
+        *cursor = None;
+        drop(cursor);
+        Ok(report)
+    }
+
+    /// Clean expired items, and remove all orphan files (items without metadata, metadata without
+    /// item, and locks without both) and ephemeral items right away, while [`Shmap::clean`] leaves
+    /// them a grace period of 5 seconds, in case they are being inserted.
+    ///
+    /// This is intended for recovery, e.g. at startup after a crash: an item concurrently inserted
+    /// may be removed before its metadata is written.
+    pub fn compact(&self) -> Result<CleanReport, ShmapError> {
+        let (_, report) = self._clean(Duration::ZERO)?;
+        Ok(report)
+    }
+
+    /// Check every item of the directory: its metadata is present and readable, it is not
+    /// expired, and its value is present and, if encrypted, decryptable. If `repair` is set,
+    /// broken items are removed, as well as metadata without item.
+    ///
+    /// Like [`Shmap::compact`], this is intended for recovery, e.g. after an ungraceful shutdown:
+    /// there is no grace period for items being inserted. All the users of the directory must
+    /// share the encryption key, as the items which can not be decrypted are corrupt. Items
+    /// inserted with associated data are not decrypted.
+    pub fn verify(&self, repair: bool) -> Result<VerifyReport, ShmapError> {
+        let mut report = VerifyReport::default();
+        let Some(read_dir) = self.read_dir()? else {
+            return Ok(report);
+        };
+        for dir_entry in read_dir.flatten() {
+            let Ok(filename) = dir_entry.file_name().into_string() else {
+                continue;
+            };
+            if is_item_filename(&filename) {
+                self.verify_item(&filename, repair, &mut report);
+            } else if filename.starts_with(SHMAP_PREFIX) && has_suffix(&filename, METADATA_SUFFIX) {
+                let item_filename = filename.trim_end_matches(&format!(".{METADATA_SUFFIX}"));
+                // Skipped if removed with its item meanwhile
+                if self.modified_time(item_filename).is_some()
+                    || self.modified_time(&filename).is_none()
+                {
+                    continue;
+                }
+                report.orphaned += 1;
+                if repair
+                    && self.remove_locked(item_filename, &[&filename], || {
+                        self.modified_time(item_filename).is_none()
+                    })
+                {
+                    log!(
+                        target: LOG_TARGET,
+                        self.routine_level(),
+                        key = filename.as_str(),
+                        op = "verify";
+                        "Metadata exists, but item not found, removing"
+                    );
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Re-encrypt every encrypted item of the directory from `old_key` to `new_key`, e.g. to
+    /// rotate the encryption key of a store, along with the metadata encrypted with `old_key` (the
+    /// one of stores built with it).
+    ///
+    /// Each item is rewritten under its lock, and a failing item does not stop the run, its error
+    /// being collected in the report. Items which already decrypt with `new_key` are skipped, so
+    /// that an interrupted run can be resumed. Plaintext items are skipped too, as well as items
+    /// without metadata (see [`Shmap::insert_raw`]), which do not tell whether they are encrypted.
+    /// Items inserted with [`Shmap::insert_with_aad`] can not be decrypted without their
+    /// associated data, and fail with [`ShmapError::AadMismatch`].
+    pub fn reencrypt(
+        &self,
+        old_key: &[u8; 32],
+        new_key: &[u8; 32],
+    ) -> Result<ReencryptReport, ShmapError> {
+        let mut report = ReencryptReport::default();
+        let Some(read_dir) = self.read_dir()? else {
+            return Ok(report);
+        };
+        let old = self.with_cipher(old_key);
+        let new = self.with_cipher(new_key);
+        let plain = Self {
+            cipher: None,
+            key_ring: Vec::new(),
+            ..self.clone()
+        };
+        for dir_entry in read_dir.flatten() {
+            let Ok(filename) = dir_entry.file_name().into_string() else {
+                continue;
+            };
+            if !is_item_filename(&filename) {
+                continue;
+            }
+            match Self::reencrypt_item(&filename, &old, &new, &plain) {
+                Ok(true) => report.migrated += 1,
+                Ok(false) => report.skipped += 1,
+                Err(e) => report.errors.push((filename, e)),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Re-encrypt an item for [`Shmap::reencrypt`], under its lock, with the clones of the Shmap
+    /// using the old key, the new one, and none. Return whether it was rewritten.
+    fn reencrypt_item(
+        filename: &str,
+        old: &Self,
+        new: &Self,
+        plain: &Self,
+    ) -> Result<bool, ShmapError> {
+        let _guard = WriteGuard::lock(&lock_path(&old.lock_dir, filename))?;
+
+        // The metadata is encrypted with the key of the store, if any
+        if new.get_metadata_unlocked(filename).is_ok() {
+            return Ok(false);
+        }
+        let (metadata_writer, metadata) = match old.get_metadata_unlocked(filename) {
+            Ok(metadata) => (new, metadata),
+            Err(_) => (plain, plain.get_metadata_unlocked(filename)?),
+        };
+        let Some(mut metadata) = metadata else {
+            return Ok(false);
+        };
+        if !metadata.encrypted {
+            return Ok(false);
+        }
+        if metadata.aad {
+            return Err(ShmapError::AadMismatch);
+        }
+        // Values encrypted with their own key, see `Shmap::insert_encrypted_with_key`
+        if metadata_writer.cipher.is_none()
+            && new
+                .read_value_unlocked(filename, Some(&metadata), &[])
+                .is_ok()
+        {
+            return Ok(false);
+        }
+
+        let Some(mut bytes) = old.read_value_unlocked(filename, Some(&metadata), &[])? else {
+            return Ok(false);
+        };
+        let ret =
+            metadata_writer.write_item_with_unlocked(new, filename, &bytes, &[], &mut metadata);
+        if old.protect_plaintext() {
+            bytes.zeroize();
+        }
+        ret.map(|()| true)
+    }
+
+    /// Check an item for [`Shmap::verify`], under its lock, and remove it if broken and `repair`
+    /// is set.
+    fn verify_item(&self, filename: &str, repair: bool, report: &mut VerifyReport) {
+        let Ok(_guard) = WriteGuard::lock(&lock_path(&self.lock_dir, filename)) else {
+            return;
+        };
+        let message = match self.get_metadata_unlocked(filename) {
+            Ok(Some(metadata)) if self.is_expired(&metadata) => {
+                report.expired += 1;
+                "Item expired, removing"
+            }
+            Ok(Some(metadata)) => {
+                if self.verify_value_unlocked(filename, &metadata) {
+                    report.ok += 1;
+                    return;
+                }
+                report.corrupt += 1;
+                "Item value is corrupt, removing"
+            }
+            // Present, but empty or too short to be decrypted
+            Ok(None) if self.modified_time(&metadata_key(filename)).is_some() => {
+                report.corrupt += 1;
+                "Item metadata is corrupt, removing"
+            }
+            Ok(None) => {
+                report.orphaned += 1;
+                "Item metadata not found, removing"
+            }
+            Err(_) => {
+                report.corrupt += 1;
+                "Item metadata is corrupt, removing"
+            }
+        };
+        if !repair {
+            return;
+        }
+        log!(
+            target: LOG_TARGET,
+            self.routine_level(),
+            key = filename,
+            op = "verify";
+            "{message}"
+        );
+        self.unlink_parts(filename, 1);
+        let _ = self.unlink(filename);
+        let _ = self.unlink(&metadata_key(filename));
+    }
+
+    /// Whether the value of a live item can be read, the caller holding its lock. It is only
+    /// decrypted if the item was encrypted with the key of the Shmap, without associated data.
+    fn verify_value_unlocked(&self, filename: &str, metadata: &Metadata) -> bool {
+        if metadata.aad || metadata.encrypted != self.cipher.is_some() {
+            return self.modified_time(filename).is_some();
+        }
+        match self.read_value_unlocked(filename, Some(metadata), &[]) {
+            Ok(Some(mut bytes)) => {
+                if self.protect_plaintext() {
+                    bytes.zeroize();
+                }
+                true
+            }
+            Ok(None) | Err(_) => false,
+        }
+    }
+
+    /// Clean expired items, and orphan files older than `grace_period`. Return the live keys of
+    /// all the namespaces, with their namespace.
+    #[allow(clippy::type_complexity)]
+    fn _clean(
+        &self,
+        grace_period: Duration,
+    ) -> Result<(Vec<(Option<String>, String)>, CleanReport), ShmapError> {
+        let mut keys = Vec::new();
+        let mut report = CleanReport::default();
+        let Some(read_dir) = self.read_dir()? else {
+            return Ok((keys, report));
+        };
+        // Non UTF-8 names are not ours, and could not be rebuilt from a lossy conversion
+        let filenames = read_dir
+            .flatten()
+            .filter_map(|dir_entry| dir_entry.file_name().into_string().ok())
+            .chain(self.lock_dir_filenames()?);
+        for filename in filenames {
+            self.clean_file(&filename, grace_period, &mut keys, &mut report);
+        }
+        Ok((keys, report))
+    }
+
+    /// Clean a file for [`Shmap::_clean`], adding the key to `keys` if it is a live item.
+    ///
+    /// Files may be inserted or removed concurrently: decisions are checked again under the item
+    /// lock before removing anything, and files which disappear are silently skipped.
+    fn clean_file(
+        &self,
+        filename: &str,
+        grace_period: Duration,
+        keys: &mut Vec<(Option<String>, String)>,
+        report: &mut CleanReport,
+    ) {
+        let Some(modified_time) = self.modified_time(filename) else {
+            return;
+        };
+        let orphan_expired = is_older_than(modified_time, grace_period);
+        if is_item_filename(filename) {
+            self.clean_item(filename, orphan_expired, grace_period, keys, report);
+        } else if filename.starts_with(SHMAP_PREFIX) && has_suffix(filename, METADATA_SUFFIX) {
+            let item_filename = filename.trim_end_matches(&format!(".{METADATA_SUFFIX}"));
+            if !orphan_expired || self.modified_time(item_filename).is_some() {
+                return;
+            }
+            let removed = self.remove_locked(item_filename, &[filename], || {
+                self.modified_time(item_filename).is_none()
+                    && self
+                        .modified_time(filename)
+                        .is_some_and(|modified| is_older_than(modified, grace_period))
+            });
+            if removed {
+                log!(
+                    target: LOG_TARGET,
+                    self.routine_level(),
+                    key = filename,
+                    op = "clean";
+                    "Metadata exists, but item not found, removing metadata"
+                );
+                report.orphan_metadata += 1;
+                self.remove_created_lock(filename, grace_period);
+            }
+        } else if filename.starts_with(SHMAP_PREFIX) && has_suffix(filename, EPHEMERAL_SUFFIX) {
+            // Not an orphan: ephemeral items are only kept while they are written
+            let removed = orphan_expired
+                && self.remove_locked(filename, &[filename], || {
+                    self.modified_time(filename)
+                        .is_some_and(|modified| is_older_than(modified, grace_period))
+                });
+            if removed {
+                log!(
+                    target: LOG_TARGET,
+                    self.routine_level(),
+                    key = filename,
+                    op = "clean";
+                    "Ephemeral item not written anymore, removing"
+                );
+                report.ephemeral += 1;
+                self.remove_created_lock(filename, grace_period);
+            }
+        } else if filename.starts_with(SHMAP_PREFIX) && has_suffix(filename, SHADOW_SUFFIX) {
+            // Left by a transaction or an atomic write which crashed before renaming it, checked
+            // before parts, which have their own shadows
+            let target = filename.trim_end_matches(&format!(".{SHADOW_SUFFIX}"));
+            if orphan_expired && self.remove_locked(target, &[filename], || true) {
+                log!(
+                    target: LOG_TARGET,
+                    self.routine_level(),
+                    key = filename,
+                    op = "clean";
+                    "Shadow left by an interrupted write, removing"
+                );
+                report.orphan_items += 1;
+            }
+        } else if let Some(item_filename) = filename
+            .strip_prefix(SHMAP_PREFIX)
+            .and_then(|_| part_item_key(filename))
+        {
+            if !orphan_expired || self.modified_time(item_filename).is_some() {
+                return;
+            }
+            let removed = self.remove_locked(item_filename, &[filename], || {
+                self.modified_time(item_filename).is_none()
+            });
+            if removed {
+                log!(
+                    target: LOG_TARGET,
+                    self.routine_level(),
+                    key = filename,
+                    op = "clean";
+                    "Part exists, but item not found, removing"
+                );
+                report.orphan_items += 1;
+            }
+        } else if filename.starts_with(SHMAP_PREFIX) && has_suffix(filename, LOCK_SUFFIX) {
+            let item_filename = filename.trim_end_matches(&format!(".{LOCK_SUFFIX}"));
+            let is_orphan = || {
+                self.modified_time(item_filename).is_none()
+                    && self
+                        .modified_time(&format!("{item_filename}.{METADATA_SUFFIX}"))
+                        .is_none()
+                    && self.modified_time(&ephemeral_key(item_filename)).is_none()
+            };
+            if orphan_expired
+                && is_orphan()
+                && self.remove_locked(item_filename, &[filename], is_orphan)
+            {
+                log!(
+                    target: LOG_TARGET,
+                    self.routine_level(),
+                    key = filename,
+                    op = "clean";
+                    "Lock exists, but item not found, removing"
+                );
+                report.orphan_locks += 1;
+            }
+        }
+    }
+
+    /// Clean an item file, which is expired or has no metadata, or add its key to `keys`.
+    fn clean_item(
+        &self,
+        filename: &str,
+        orphan_expired: bool,
+        grace_period: Duration,
+        keys: &mut Vec<(Option<String>, String)>,
+        report: &mut CleanReport,
+    ) {
+        let metadata_filename = format!("{filename}.{METADATA_SUFFIX}");
+        match self.get_metadata(filename) {
+            Ok(Some(metadata)) if self.is_expired(&metadata) => {
+                // Expired, remove item and metadata, unless inserted again meanwhile. Inserts
+                // write the value before the metadata, so a value newer than its metadata was
+                // written by `insert_raw`, without a TTL.
+                let removed = self.remove_locked(filename, &[filename, &metadata_filename], || {
+                    self.get_metadata_unlocked(filename).is_ok_and(|metadata| {
+                        metadata.is_some_and(|metadata| self.is_expired(&metadata))
+                    }) && self
+                        .modified_time(filename)
+                        .zip(self.modified_time(&metadata_filename))
+                        .is_some_and(|(modified, metadata_modified)| modified <= metadata_modified)
+                });
+                if removed {
+                    log!(
+                        target: LOG_TARGET,
+                        self.routine_level(),
+                        key = filename,
+                        op = "clean";
+                        "Item expired, removing"
+                    );
+                    report.expired += 1;
+                }
+            }
+            Ok(Some(metadata)) => {
+                // Not expired, add to list
+                keys.push((metadata.namespace, metadata.key));
+            }
+            Ok(None) => {
+                if !orphan_expired {
+                    return;
+                }
+                // Item exists, but metadata not found, remove item
+                let removed = self.remove_locked(filename, &[filename], || {
+                    self.get_metadata_unlocked(filename)
+                        .is_ok_and(|metadata| metadata.is_none())
+                        && self
+                            .modified_time(filename)
+                            .is_some_and(|modified| is_older_than(modified, grace_period))
+                });
+                if removed {
+                    log!(
+                        target: LOG_TARGET,
+                        self.routine_level(),
+                        key = filename,
+                        op = "clean";
+                        "Item metadata not found, removing"
+                    );
+                    report.orphan_items += 1;
+                    self.remove_created_lock(filename, grace_period);
+                }
+            }
+            Err(e) => {
+                // Can't deserialized metadata or something else happens
+                error!(
+                    target: LOG_TARGET,
+                    key = filename,
+                    op = "clean";
+                    "Could not get metadata : {e}"
+                );
+            }
+        }
+    }
+
+    /// Modification time of a file, or `None` if it does not exist (anymore).
+    fn modified_time(&self, filename: &str) -> Option<SystemTime> {
+        let dir = if has_suffix(filename, LOCK_SUFFIX) {
+            &self.lock_dir
+        } else {
+            &self.dir
+        };
+        fs::metadata(dir.join(filename))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Remove `filenames` under the lock of an item, if `still_removable` holds once locked.
+    /// Return whether they were removed.
+    fn remove_locked<F>(&self, sanitized_key: &str, filenames: &[&str], still_removable: F) -> bool
+    where
+        F: FnOnce() -> bool,
+    {
+        let Ok(_guard) = WriteGuard::lock(&lock_path(&self.lock_dir, sanitized_key)) else {
+            return false;
+        };
+        if !still_removable() {
+            return false;
+        }
+        filenames.iter().all(|filename| {
+            if is_item_filename(filename) {
+                self.unlink_parts(filename, 1);
+            }
+            self.unlink(filename).is_ok()
+        })
+    }
+
+    /// Get the metadata of an item, the caller holding its lock.
+    fn get_metadata_unlocked(&self, sanitized_key: &str) -> Result<Option<Metadata>, ShmapError> {
+        let metadata_key = metadata_key(sanitized_key);
+        match self.read_unlocked(&metadata_key, &[])? {
+            Content::Value(bytes) => Ok(Some(decode_metadata(&metadata_key, &bytes)?)),
+            Content::Missing | Content::Empty => Ok(None),
+        }
+    }
+
+    /// Removing an orphan takes its lock, thus creating a new orphan lock file, which is only
+    /// removed right away without grace period.
+    fn remove_created_lock(&self, sanitized_key: &str, grace_period: Duration) {
+        if grace_period.is_zero() {
+            let _ = self._remove(&lock_key(sanitized_key));
+        }
+    }
+}
+
+/// Result of [`Shmap::get_status`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GetStatus<T> {
+    /// The item is available.
+    Present(T),
+    /// The item was found expired, and removed.
+    Expired,
+    /// The item does not exist.
+    Missing,
+}
+
+/// Result of [`Shmap::get_result`].
+#[derive(Debug)]
+pub enum GetResult<T> {
+    /// The item is available.
+    Found(T),
+    /// The item does not exist, or is expired.
+    NotFound,
+    /// The item can not be read: it can not be decrypted or deserialized, or is of another type.
+    Corrupt(ShmapError),
+}
+
+/// Files removed by [`Shmap::compact`] or [`Shmap::clean_limited`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CleanReport {
+    /// Expired items.
+    pub expired: usize,
+    /// Items without metadata.
+    pub orphan_items: usize,
+    /// Metadata without item.
+    pub orphan_metadata: usize,
+    /// Locks without item nor metadata.
+    pub orphan_locks: usize,
+    /// Items inserted with [`Shmap::insert_ephemeral`] and not written anymore.
+    pub ephemeral: usize,
+    /// Whether [`Shmap::clean_limited`] stopped before the end of the directory.
+    pub remaining: bool,
+}
 
-fn sanitize_metadata_key(key: &str) -> String {
-    format!("{}.{}", sanitize_key(key), METADATA_SUFFIX)
+/// Items checked by [`Shmap::verify`], which removes all but the `ok` ones if repairing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Readable items.
+    pub ok: usize,
+    /// Expired items.
+    pub expired: usize,
+    /// Items with unreadable metadata or value.
+    pub corrupt: usize,
+    /// Items without metadata, and metadata without item.
+    pub orphaned: usize,
+}
+
+/// Outcome of [`Shmap::reencrypt`].
+#[derive(Debug, Default)]
+pub struct ReencryptReport {
+    /// Items re-encrypted with the new key.
+    pub migrated: usize,
+    /// Plaintext items, items without metadata, and items already encrypted with the new key.
+    pub skipped: usize,
+    /// Items which could not be re-encrypted, by file name, with their error.
+    pub errors: Vec<(String, ShmapError)>,
+}
+
+/// Whether a file modified at `modified_time` is older than `duration`. A modification time in
+/// the future is considered as now.
+fn is_older_than(modified_time: SystemTime, duration: Duration) -> bool {
+    SystemTime::now()
+        .duration_since(modified_time)
+        .unwrap_or_default()
+        >= duration
+}
+
+/// Encode the metadata of an item. In debug builds, it is decoded back, so that a layout change
+/// which would only fail on a later read (e.g. by `clean`) fails right at write time instead.
+pub fn encode_metadata(metadata_key: &str, metadata: &Metadata) -> Result<Vec<u8>, ShmapError> {
+    let bytes = metadata.encode().map_err(|e| ShmapError::Serialization {
+        key: metadata_key.to_owned(),
+        source: e,
+    })?;
+    if cfg!(debug_assertions) && decode_metadata(metadata_key, &bytes)? != *metadata {
+        return Err(ShmapError::Serialization {
+            key: metadata_key.to_owned(),
+            source: SerializationError::InvalidMetadata("does not round-trip"),
+        });
+    }
+    Ok(bytes)
+}
+
+/// Live keys of all the namespaces in `dir`, as `(namespace, key)` pairs, see
+/// [`Shmap::all_keys`].
+pub fn all_namespaces_keys(dir: &Path) -> Result<Vec<(String, String)>, ShmapError> {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(ShmapError::IOError(e)),
+    };
+    Ok(read_dir
+        .flatten()
+        .filter_map(|dir_entry| dir_entry.file_name().into_string().ok())
+        .filter(|filename| is_item_filename(filename))
+        .filter_map(|filename| {
+            let metadata = read_plain_metadata(dir, &filename)?;
+            (!metadata.is_expired_on(&SystemClock))
+                .then(|| (metadata.namespace.unwrap_or_default(), metadata.key))
+        })
+        .collect())
+}
+
+/// Metadata of an item of `dir` without encryption, read under its lock, or `None` if it can
+/// not be read.
+fn read_plain_metadata(dir: &Path, sanitized_key: &str) -> Option<Metadata> {
+    let metadata_key = metadata_key(sanitized_key);
+    let _guard = ReadGuard::lock(&lock_path(dir, sanitized_key)).ok()?;
+    let fd = shm::open_read(dir, &metadata_key).ok()?;
+    // SAFETY: Mmap call is unsafe
+    let mmap = unsafe { Mmap::map(fd) }.ok()?;
+    decode_metadata(&metadata_key, &mmap).ok()
+}
+
+fn decode_metadata(metadata_key: &str, bytes: &[u8]) -> Result<Metadata, ShmapError> {
+    Metadata::decode(bytes).map_err(|e| ShmapError::Serialization {
+        key: metadata_key.to_owned(),
+        source: e,
+    })
+}
+
+fn serialize<T>(format: Format, sanitized_key: &str, value: &T) -> Result<Vec<u8>, ShmapError>
+where
+    T: Serialize + ?Sized,
+{
+    format.encode(value).map_err(|e| ShmapError::Serialization {
+        key: sanitized_key.to_owned(),
+        source: e,
+    })
+}
+
+fn deserialize<T>(
+    format: Format,
+    decode_limit: Option<usize>,
+    sanitized_key: &str,
+    bytes: &[u8],
+) -> Result<T, ShmapError>
+where
+    T: DeserializeOwned,
+{
+    format
+        .decode(bytes, decode_limit)
+        .map_err(|e| ShmapError::Serialization {
+            key: sanitized_key.to_owned(),
+            source: e,
+        })
+}
+
+#[cfg(test)]
+pub fn sanitize_key(namespace: Option<&str>, key: &str) -> String {
+    sanitize_key_with(KeyHash::default(), namespace, key)
+}
+
+/// Name of the shm file of an item, hashing its key with `key_hash`.
+pub fn sanitize_key_with(
+    key_hash: KeyHash,
+    namespace: Option<&str>,
+    key: impl AsRef<[u8]>,
+) -> String {
+    let digest = key_hash.hex_digest(key.as_ref());
+    namespace.map_or_else(
+        || format!("{SHMAP_PREFIX}.{digest}"),
+        |namespace| format!("{SHMAP_PREFIX}.{namespace}.{digest}"),
+    )
+}
+
+/// Name of the shm file of an item with [`ShmapBuilder::with_raw_keys`], which is the key itself.
+fn raw_key(namespace: Option<&str>, key: &[u8]) -> Result<String, ShmapError> {
+    if key.is_empty()
+        || !key
+            .iter()
+            .all(|&c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+    {
+        return Err(ShmapError::InvalidRawKey);
+    }
+    // Only ASCII characters
+    let key = String::from_utf8_lossy(key);
+    if is_reserved_raw_key(&key) {
+        return Err(ShmapError::InvalidRawKey);
+    }
+    Ok(namespace.map_or_else(
+        || format!("{SHMAP_PREFIX}.{key}"),
+        |namespace| format!("{SHMAP_PREFIX}.{namespace}.{key}"),
+    ))
+}
+
+/// Whether a raw key would name a reserved file, or a file of another item, with or without a
+/// namespace.
+fn is_reserved_raw_key(key: &str) -> bool {
+    let filename = format!("{SHMAP_PREFIX}.{key}");
+    filename == CONFIG_KEY
+        || filename == CHANGES_KEY
+        || [
+            METADATA_SUFFIX,
+            LOCK_SUFFIX,
+            SHADOW_SUFFIX,
+            EPHEMERAL_SUFFIX,
+            DURABLE_TMP_SUFFIX,
+        ]
+        .iter()
+        .any(|suffix| has_suffix(&filename, suffix))
+        || part_item_key(&filename).is_some()
+}
+
+/// Path of the lock file shared by an item and its metadata, or its ephemeral value.
+pub fn lock_path(dir: &Path, sanitized_key: &str) -> PathBuf {
+    dir.join(lock_key(sanitized_key))
+}
+
+fn lock_key(sanitized_key: &str) -> String {
+    let item_key = [METADATA_SUFFIX, EPHEMERAL_SUFFIX]
+        .iter()
+        .find_map(|suffix| sanitized_key.strip_suffix(&format!(".{suffix}")))
+        .unwrap_or(sanitized_key);
+    format!("{item_key}.{LOCK_SUFFIX}")
+}
+
+fn is_item_filename(filename: &str) -> bool {
+    filename.starts_with(SHMAP_PREFIX)
+        && !has_suffix(filename, METADATA_SUFFIX)
+        && !has_suffix(filename, LOCK_SUFFIX)
+        && !has_suffix(filename, SHADOW_SUFFIX)
+        && !has_suffix(filename, EPHEMERAL_SUFFIX)
+        && filename != CONFIG_KEY
+        && filename != CHANGES_KEY
+        && part_item_key(filename).is_none()
+}
+
+/// Whether a file name ends with `.<suffix>`.
+fn has_suffix(filename: &str, suffix: &str) -> bool {
+    filename
+        .strip_suffix(suffix)
+        .is_some_and(|name| name.ends_with('.'))
+}
+
+/// Name of the item file of a part file, see [`part_key`], or `None` if `filename` is not one.
+fn part_item_key(filename: &str) -> Option<&str> {
+    let (item_key, part) = filename.rsplit_once(&format!(".{PART_SUFFIX}"))?;
+    (!part.is_empty() && part.bytes().all(|c| c.is_ascii_digit())).then_some(item_key)
+}
+
+/// Name of the shadow file written by a transaction before being renamed to `filename`.
+fn shadow_key(filename: &str) -> String {
+    format!("{filename}.{SHADOW_SUFFIX}")
+}
+
+/// Name of the shm file of a part of an item value, the first one being the item file itself.
+fn part_key(sanitized_key: &str, part: usize) -> String {
+    if part == 0 {
+        sanitized_key.to_owned()
+    } else {
+        format!("{sanitized_key}.{PART_SUFFIX}{part}")
+    }
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// Whether the process `pid` is running, or may be: only a process which is known not to exist
+/// is dead.
+fn is_process_alive(pid: u32) -> bool {
+    // 0 and negative pids designate process groups
+    let Some(pid) = libc::pid_t::try_from(pid).ok().filter(|&pid| pid > 0) else {
+        return false;
+    };
+    // SAFETY: the null signal is never sent, only the existence of the process is checked
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    std::io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+fn metadata_key(sanitized_key: &str) -> String {
+    format!("{sanitized_key}.{METADATA_SUFFIX}")
+}
+
+fn ephemeral_key(sanitized_key: &str) -> String {
+    format!("{sanitized_key}.{EPHEMERAL_SUFFIX}")
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
         tests::map::{init_logger, rand_string},
-        Shmap,
+        Format, Shmap, ShmapBuilder,
     };
 
     #[test]
@@ -411,11 +3847,29 @@ mod tests {
         let value = rand_string(50);
 
         shmap.insert(&key, value).unwrap();
-        let _ = shmap.get_metadata(&key).unwrap().unwrap();
+        let sanitized_key = shmap.sanitize_key(&key).unwrap();
+        let _ = shmap.get_metadata(&sanitized_key).unwrap().unwrap();
 
         let shmap = Shmap::new();
         shmap.remove(&key).unwrap();
-        let should_be_none = shmap.get_metadata(&key).unwrap();
+        let should_be_none = shmap.get_metadata(&sanitized_key).unwrap();
         assert!(should_be_none.is_none());
     }
+
+    #[test]
+    fn test_decrypted_zeroized() {
+        init_logger();
+
+        let shmap = ShmapBuilder::new()
+            .with_encryption(&[42u8; 32])
+            .with_mlock(true)
+            .build()
+            .unwrap();
+        let value = rand_string(50);
+
+        let mut bytes = super::serialize(Format::Bincode, "test", &value).unwrap();
+        let ret_value: String = shmap.deserialize_zeroize("test", &mut bytes).unwrap();
+        assert_eq!(ret_value, value);
+        assert!(bytes.iter().all(|b| *b == 0));
+    }
 }
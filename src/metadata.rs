@@ -1,34 +1,399 @@
+//! Metadata of items, stored in their own shm file with a compact binary layout:
+//!
+//! | bytes | content                                                   |
+//! |-------|-----------------------------------------------------------|
+//! | 1     | format version                                            |
+//! | 1     | flags                                                     |
+//! | 8     | expiration, as milliseconds since the UNIX epoch (LE i64) |
+//! | 4     | key length (LE u32)                                       |
+//! | n     | key, UTF-8                                                |
+//!
+//! Then optional sections, only present if their flag is set, in the order of the flags below,
+//! and, if any extended flag is set, a byte of extended flags followed by their own sections.
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::{clock, errors::SerializationError, Clock, ShmapError, SystemClock};
+
+pub const FORMAT_VERSION: u8 = 1;
+
+/// The item value is encrypted.
+const FLAG_ENCRYPTED: u8 = 1;
+/// The expiration field is set, otherwise the item has no TTL.
+const FLAG_EXPIRATION: u8 = 1 << 1;
+/// Section: namespace length (u8), then namespace, UTF-8.
+const FLAG_NAMESPACE: u8 = 1 << 2;
+/// Section: access count (LE u64), then last access as milliseconds since the UNIX epoch (LE i64).
+const FLAG_ACCESS_STATS: u8 = 1 << 3;
+/// The item value is bound to a user-supplied associated data.
+const FLAG_AAD: u8 = 1 << 4;
+/// Section: number of parts (LE u32), then total length of the value (LE u64).
+const FLAG_PARTS: u8 = 1 << 5;
+/// The original key is not UTF-8, the key field holds its hexadecimal encoding.
+const FLAG_BINARY_KEY: u8 = 1 << 6;
+/// Section: boot id (LE u128), then expiration as nanoseconds since boot (LE u64), see
+/// [`MonotonicDeadline`].
+const FLAG_MONOTONIC: u8 = 1 << 7;
 
-use crate::ShmapError;
+/// Extended section: type name length (LE u16), then type name, UTF-8, see
+/// [`crate::ShmapBuilder::with_type_check`].
+const EXT_FLAG_TYPE_NAME: u8 = 1;
+/// Extended section: process id of the creator of the item (LE u32), see
+/// [`crate::ShmapBuilder::with_creator_pid`].
+const EXT_FLAG_CREATOR_PID: u8 = 1 << 1;
+/// The item is owned by its creator, see [`crate::Shmap::insert_owned`].
+const EXT_FLAG_OWNED: u8 = 1 << 2;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Metadata of an item, stored next to it.
+// Flags of the binary layout
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Metadata {
+    /// Original key of the item, hex-encoded if it is a binary key.
     pub key: String,
+    /// Whether the item was inserted with a key which is not UTF-8 (see
+    /// [`crate::Shmap::insert_bytes_key`]), `key` being its hexadecimal encoding.
+    pub binary_key: bool,
+    /// Namespace of the item, if any.
+    pub namespace: Option<String>,
+    /// Date after which the item is expired, if it has a TTL.
     pub expiration: Option<DateTime<Utc>>,
+    /// Expiration on the monotonic clock of the machine, if it was available at insertion, see
+    /// [`Clock::monotonic_now`]. It takes precedence over `expiration` during the same boot.
+    pub monotonic_expiration: Option<MonotonicDeadline>,
+    /// Whether the item value is encrypted.
     pub encrypted: bool,
+    /// Whether the item value is encrypted with a user-supplied associated data, see
+    /// [`crate::Shmap::insert_with_aad`].
+    pub aad: bool,
+    /// Number of times the item was read, if access statistics are enabled.
+    pub access_count: u64,
+    /// Date of the last read of the item, if access statistics are enabled.
+    pub last_access: Option<DateTime<Utc>>,
+    /// Number of shm files the value is split across, 0 if it is not split, see
+    /// [`crate::ShmapBuilder::with_chunk_size`].
+    pub parts: u32,
+    /// Total length of the value, if it is split.
+    pub len: u64,
+    /// Name of the type the value was serialized from, if type checks are enabled, see
+    /// [`crate::ShmapBuilder::with_type_check`].
+    pub type_name: Option<String>,
+    /// Process id of the creator of the item, if it was recorded, see
+    /// [`crate::ShmapBuilder::with_creator_pid`].
+    pub creator_pid: Option<u32>,
+    /// Whether the item lives only as long as its creator, see [`crate::Shmap::insert_owned`].
+    pub owned: bool,
 }
 
 impl Metadata {
-    pub fn new(
+    pub(crate) fn new(
         key: &str,
-        ttl: Option<std::time::Duration>,
+        namespace: Option<&str>,
+        ttl: Option<Duration>,
         encrypted: bool,
+        clock: &dyn Clock,
     ) -> Result<Self, ShmapError> {
-        let expiration = match ttl {
-            Some(ttl) => Some(
-                Utc::now()
-                    + chrono::Duration::from_std(ttl)
-                        .map_err(|_| ShmapError::DurationOutOfRangeError)?,
-            ),
-            None => None,
+        let mut metadata = Self {
+            key: key.to_owned(),
+            binary_key: false,
+            namespace: namespace.map(ToOwned::to_owned),
+            expiration: None,
+            monotonic_expiration: None,
+            encrypted,
+            aad: false,
+            access_count: 0,
+            last_access: None,
+            parts: 0,
+            len: 0,
+            type_name: None,
+            creator_pid: None,
+            owned: false,
+        };
+        if let Some(ttl) = ttl {
+            metadata.set_ttl(ttl, clock)?;
+        }
+        Ok(metadata)
+    }
+
+    /// Make the item expire after `ttl`, from now.
+    pub(crate) fn set_ttl(&mut self, ttl: Duration, clock: &dyn Clock) -> Result<(), ShmapError> {
+        self.expiration = Some(expiration_after(clock.now(), ttl)?);
+        self.monotonic_expiration = None;
+        if let (Some(boot_id), Some(now)) = (clock::boot_id(), clock.monotonic_now()) {
+            // Beyond 584 years, the wall clock is precise enough
+            self.monotonic_expiration = now
+                .checked_add(ttl)
+                .filter(|deadline| u64::try_from(deadline.as_nanos()).is_ok())
+                .map(|since_boot| MonotonicDeadline {
+                    boot_id,
+                    since_boot,
+                });
+        }
+        Ok(())
+    }
+
+    pub(crate) const fn record_access(&mut self, now: DateTime<Utc>) {
+        self.access_count += 1;
+        self.last_access = DateTime::from_timestamp_millis(now.timestamp_millis());
+    }
+
+    /// Whether the item is expired, according to the system clock.
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_on(&SystemClock)
+    }
+
+    /// Whether the item is expired according to `clock`.
+    ///
+    /// The monotonic expiration is used if the clock has a monotonic time and the machine did not
+    /// reboot since the insertion (stores outside of `/dev/shm` outlive reboots), otherwise this
+    /// falls back to the wall clock.
+    #[must_use]
+    pub fn is_expired_on(&self, clock: &dyn Clock) -> bool {
+        self.expires_within(Duration::ZERO, clock)
+    }
+
+    /// Whether the item will be expired in `within` according to `clock`, like
+    /// [`Metadata::is_expired_on`]. Items without TTL never expire.
+    pub(crate) fn expires_within(&self, within: Duration, clock: &dyn Clock) -> bool {
+        if self.expiration.is_none() {
+            return false;
+        }
+        let monotonic = self
+            .monotonic_expiration
+            .filter(|deadline| clock::boot_id() == Some(deadline.boot_id))
+            .zip(clock.monotonic_now());
+        match monotonic {
+            Some((deadline, now)) => now.saturating_add(within) > deadline.since_boot,
+            // Beyond the range of dates, every expiration is within
+            None => chrono::Duration::from_std(within)
+                .ok()
+                .and_then(|within| clock.now().checked_add_signed(within))
+                .is_none_or(|later| self.is_expired_at(later)),
+        }
+    }
+
+    /// Whether the item is expired at `now` on the wall clock, ignoring the monotonic expiration.
+    #[must_use]
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expiration.is_some_and(|expiration| now > expiration)
+    }
+
+    pub(crate) fn encode(&self) -> Result<Vec<u8>, SerializationError> {
+        let mut flags = 0;
+        if self.encrypted {
+            flags |= FLAG_ENCRYPTED;
+        }
+        if self.aad {
+            flags |= FLAG_AAD;
+        }
+        if self.parts > 0 {
+            flags |= FLAG_PARTS;
+        }
+        if self.binary_key {
+            flags |= FLAG_BINARY_KEY;
+        }
+        if self.expiration.is_some() {
+            flags |= FLAG_EXPIRATION;
+        }
+        if self.namespace.is_some() {
+            flags |= FLAG_NAMESPACE;
+        }
+        if self.last_access.is_some() {
+            flags |= FLAG_ACCESS_STATS;
+        }
+        if self.monotonic_expiration.is_some() {
+            flags |= FLAG_MONOTONIC;
+        }
+        let mut ext_flags = 0;
+        if self.type_name.is_some() {
+            ext_flags |= EXT_FLAG_TYPE_NAME;
+        }
+        if self.creator_pid.is_some() {
+            ext_flags |= EXT_FLAG_CREATOR_PID;
+        }
+        if self.owned {
+            ext_flags |= EXT_FLAG_OWNED;
+        }
+
+        let key_len = u32::try_from(self.key.len())
+            .map_err(|_| SerializationError::InvalidMetadata("key too long"))?;
+        let expiration = self
+            .expiration
+            .map_or(0, |expiration| expiration.timestamp_millis());
+
+        let mut bytes = Vec::with_capacity(14 + self.key.len());
+        bytes.push(FORMAT_VERSION);
+        bytes.push(flags);
+        bytes.extend_from_slice(&expiration.to_le_bytes());
+        bytes.extend_from_slice(&key_len.to_le_bytes());
+        bytes.extend_from_slice(self.key.as_bytes());
+        if let Some(namespace) = &self.namespace {
+            let namespace_len = u8::try_from(namespace.len())
+                .map_err(|_| SerializationError::InvalidMetadata("namespace too long"))?;
+            bytes.push(namespace_len);
+            bytes.extend_from_slice(namespace.as_bytes());
+        }
+        if let Some(last_access) = self.last_access {
+            bytes.extend_from_slice(&self.access_count.to_le_bytes());
+            bytes.extend_from_slice(&last_access.timestamp_millis().to_le_bytes());
+        }
+        if self.parts > 0 {
+            bytes.extend_from_slice(&self.parts.to_le_bytes());
+            bytes.extend_from_slice(&self.len.to_le_bytes());
+        }
+        if let Some(deadline) = self.monotonic_expiration {
+            let since_boot = u64::try_from(deadline.since_boot.as_nanos())
+                .map_err(|_| SerializationError::InvalidMetadata("monotonic expiration too far"))?;
+            bytes.extend_from_slice(&deadline.boot_id.to_le_bytes());
+            bytes.extend_from_slice(&since_boot.to_le_bytes());
+        }
+        if ext_flags != 0 {
+            bytes.push(ext_flags);
+        }
+        if let Some(type_name) = &self.type_name {
+            let type_name_len = u16::try_from(type_name.len())
+                .map_err(|_| SerializationError::InvalidMetadata("type name too long"))?;
+            bytes.extend_from_slice(&type_name_len.to_le_bytes());
+            bytes.extend_from_slice(type_name.as_bytes());
+        }
+        if let Some(creator_pid) = self.creator_pid {
+            bytes.extend_from_slice(&creator_pid.to_le_bytes());
+        }
+        Ok(bytes)
+    }
+
+    pub(crate) fn decode(bytes: &[u8]) -> Result<Self, SerializationError> {
+        let mut reader = Reader(bytes);
+
+        let version = reader.u8()?;
+        if version != FORMAT_VERSION {
+            return Err(SerializationError::InvalidMetadata(
+                "unknown format version",
+            ));
+        }
+        let flags = reader.u8()?;
+        let expiration = i64::from_le_bytes(reader.array()?);
+        let key_len = u32::from_le_bytes(reader.array()?) as usize;
+        let key = reader.string(key_len)?;
+
+        let expiration = if flags & FLAG_EXPIRATION == 0 {
+            None
+        } else {
+            Some(
+                DateTime::from_timestamp_millis(expiration)
+                    .ok_or(SerializationError::InvalidMetadata("invalid expiration"))?,
+            )
+        };
+        let namespace = if flags & FLAG_NAMESPACE == 0 {
+            None
+        } else {
+            let namespace_len = reader.u8()? as usize;
+            Some(reader.string(namespace_len)?)
+        };
+        let (access_count, last_access) = if flags & FLAG_ACCESS_STATS == 0 {
+            (0, None)
+        } else {
+            let access_count = u64::from_le_bytes(reader.array()?);
+            let last_access = DateTime::from_timestamp_millis(i64::from_le_bytes(reader.array()?))
+                .ok_or(SerializationError::InvalidMetadata("invalid last access"))?;
+            (access_count, Some(last_access))
+        };
+        let (parts, len) = if flags & FLAG_PARTS == 0 {
+            (0, 0)
+        } else {
+            (
+                u32::from_le_bytes(reader.array()?),
+                u64::from_le_bytes(reader.array()?),
+            )
+        };
+        let monotonic_expiration = if flags & FLAG_MONOTONIC == 0 {
+            None
+        } else {
+            Some(MonotonicDeadline {
+                boot_id: u128::from_le_bytes(reader.array()?),
+                since_boot: Duration::from_nanos(u64::from_le_bytes(reader.array()?)),
+            })
+        };
+        // Written by older versions without extended flags
+        let ext_flags = if reader.0.is_empty() { 0 } else { reader.u8()? };
+        let type_name = if ext_flags & EXT_FLAG_TYPE_NAME == 0 {
+            None
+        } else {
+            let type_name_len = u16::from_le_bytes(reader.array()?) as usize;
+            Some(reader.string(type_name_len)?)
+        };
+        let creator_pid = if ext_flags & EXT_FLAG_CREATOR_PID == 0 {
+            None
+        } else {
+            Some(u32::from_le_bytes(reader.array()?))
         };
 
         Ok(Self {
-            key: key.to_owned(),
+            key,
+            binary_key: flags & FLAG_BINARY_KEY != 0,
+            namespace,
             expiration,
-            encrypted,
+            monotonic_expiration,
+            encrypted: flags & FLAG_ENCRYPTED != 0,
+            aad: flags & FLAG_AAD != 0,
+            access_count,
+            last_access,
+            parts,
+            len,
+            type_name,
+            creator_pid,
+            owned: ext_flags & EXT_FLAG_OWNED != 0,
         })
     }
 }
+
+/// Expiration of an item on the monotonic clock of the machine, unaffected by wall-clock steps.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonotonicDeadline {
+    /// Boot of the machine the deadline is relative to, from `/proc/sys/kernel/random/boot_id`.
+    pub boot_id: u128,
+    /// Time since boot after which the item is expired.
+    pub since_boot: Duration,
+}
+
+/// Expiration of an item inserted at `now` with `ttl`.
+pub fn expiration_after(now: DateTime<Utc>, ttl: Duration) -> Result<DateTime<Utc>, ShmapError> {
+    let ttl = chrono::Duration::from_std(ttl).map_err(|_| ShmapError::DurationOutOfRangeError)?;
+    let expiration = now
+        .checked_add_signed(ttl)
+        .ok_or(ShmapError::DurationOutOfRangeError)?;
+    // Only milliseconds are stored
+    DateTime::from_timestamp_millis(expiration.timestamp_millis())
+        .ok_or(ShmapError::DurationOutOfRangeError)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    const fn take(&mut self, len: usize) -> Result<&'a [u8], SerializationError> {
+        if self.0.len() < len {
+            return Err(SerializationError::InvalidMetadata("truncated"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, SerializationError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SerializationError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn string(&mut self, len: usize) -> Result<String, SerializationError> {
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| SerializationError::InvalidMetadata("invalid UTF-8"))
+    }
+}
@@ -0,0 +1,256 @@
+//! In-memory store with the same core API as [`crate::Shmap`], with the `mock` feature.
+
+use crate::{
+    builder::ShmapBuilder,
+    errors::{SerializationError, ShmapError},
+    metadata::Metadata,
+    Clock, SystemClock,
+};
+use aes_gcm::{
+    aead::{generic_array::GenericArray, Aead, Payload},
+    Aes256Gcm, KeyInit, Nonce,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+/// Item values, by store key (see [`MemoryShmap::store_key`]), with their metadata.
+type Items = HashMap<String, (Vec<u8>, Metadata)>;
+
+/// Items of the process.
+static STORE: LazyLock<Mutex<Items>> = LazyLock::new(Mutex::default);
+
+/// In-memory store, enabled by the `mock` feature, to test code using Shmap where `/dev/shm` is
+/// not available (e.g. a restricted CI or container), built with
+/// [`ShmapBuilder::build_in_memory`].
+///
+/// Items are stored in a process-wide map instead of shm files: they are shared by all the
+/// `MemoryShmap` instances of the process, but do not outlive it, and are not seen by
+/// [`crate::Shmap`]. Only the core API of [`crate::Shmap`] is provided, with the same signatures
+/// so that one can stand in for the other (e.g. behind a type alias), and only the encryption,
+/// namespace, default TTL and clock options of [`ShmapBuilder`] apply.
+#[derive(Clone)]
+pub struct MemoryShmap {
+    cipher: Option<Aes256Gcm>,
+    namespace: Option<String>,
+    default_ttl: Option<Duration>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for MemoryShmap {
+    /// Default is a store without encryption.
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryShmap {
+    /// Initialize a store with no TTL or encryption.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::_new(ShmapBuilder::new())
+    }
+
+    /// Initialize a store with AES256 encryption key (random bytes).
+    #[must_use]
+    pub fn new_with_encryption(encryption_key: &[u8; 32]) -> Self {
+        Self::_new(ShmapBuilder::new().with_encryption(encryption_key))
+    }
+
+    /// Initialize a store from the environment, see [`ShmapBuilder::from_env`].
+    pub fn from_env() -> Result<Self, ShmapError> {
+        ShmapBuilder::from_env()?.build_in_memory()
+    }
+
+    pub(crate) fn _new(builder: ShmapBuilder) -> Self {
+        let cipher = builder.encryption_key.map(|key| {
+            let key = GenericArray::from_slice(&key);
+            Aes256Gcm::new(key)
+        });
+        Self {
+            cipher,
+            namespace: builder.namespace,
+            default_ttl: builder.default_ttl,
+            clock: builder.clock.unwrap_or_else(|| Arc::new(SystemClock)),
+        }
+    }
+
+    /// Key of an item in [`STORE`]. Namespaces can not contain `/`, nor be empty, so that it is
+    /// unambiguous.
+    fn store_key(&self, key: &str) -> String {
+        format!("{}/{key}", self.namespace.as_deref().unwrap_or_default())
+    }
+
+    /// Get an item value by its key, and deserialize it (using `bincode`) to T.
+    pub fn get<T>(&self, key: &str) -> Result<Option<T>, ShmapError>
+    where
+        T: DeserializeOwned,
+    {
+        match self.get_raw(key)? {
+            Some(bytes) => Ok(Some(deserialize(key, &bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Get an item by its key, without deserialization, as bytes.
+    pub fn get_raw(&self, key: &str) -> Result<Option<Vec<u8>>, ShmapError> {
+        let store_key = self.store_key(key);
+        let Some((bytes, metadata)) = lock_store().get(&store_key).cloned() else {
+            return Ok(None);
+        };
+        if metadata.is_expired_on(&*self.clock) {
+            // Checked again, it may have been inserted again meanwhile
+            lock_store().retain(|k, (_, metadata)| {
+                k != &store_key || !metadata.is_expired_on(&*self.clock)
+            });
+            return Ok(None);
+        }
+
+        // If an encryption key was provided, decrypt the value
+        let Some(cipher) = &self.cipher else {
+            return Ok(Some(bytes));
+        };
+        if bytes.len() < 12 {
+            return Ok(None);
+        }
+        let payload = Payload {
+            msg: &bytes[12..],
+            aad: store_key.as_bytes(),
+        };
+        Ok(Some(
+            cipher.decrypt(Nonce::from_slice(&bytes[..12]), payload)?,
+        ))
+    }
+
+    /// Get the metadata of an item by its key.
+    ///
+    /// Expiration is not checked, so this may return the metadata of a logically expired item.
+    pub fn metadata(&self, key: &str) -> Result<Option<Metadata>, ShmapError> {
+        Ok(lock_store()
+            .get(&self.store_key(key))
+            .map(|(_, metadata)| metadata.clone()))
+    }
+
+    /// Insert a new item, using `bincode` serialization, with the default TTL if any.
+    pub fn insert<T>(&self, key: &str, value: T) -> Result<(), ShmapError>
+    where
+        T: Serialize,
+    {
+        self.insert_bytes(key, &serialize(key, &value)?, self.default_ttl)
+    }
+
+    /// Insert a new item, using `bincode` serialization, without TTL, even if the Shmap has a
+    /// default one.
+    pub fn insert_no_expiry<T>(&self, key: &str, value: T) -> Result<(), ShmapError>
+    where
+        T: Serialize,
+    {
+        self.insert_bytes(key, &serialize(key, &value)?, None)
+    }
+
+    /// Insert a new item, using `bincode` serialization, with a TTL.
+    pub fn insert_with_ttl<T>(&self, key: &str, value: T, ttl: Duration) -> Result<(), ShmapError>
+    where
+        T: Serialize,
+    {
+        self.insert_bytes(key, &serialize(key, &value)?, Some(ttl))
+    }
+
+    /// Insert a new item, without serialization.
+    pub fn insert_raw(&self, key: &str, value: &[u8]) -> Result<(), ShmapError> {
+        self.insert_bytes(key, value, self.default_ttl)
+    }
+
+    fn insert_bytes(
+        &self,
+        key: &str,
+        value: &[u8],
+        ttl: Option<Duration>,
+    ) -> Result<(), ShmapError> {
+        let store_key = self.store_key(key);
+        let metadata = Metadata::new(
+            key,
+            self.namespace.as_deref(),
+            ttl,
+            self.cipher.is_some(),
+            &*self.clock,
+        )?;
+
+        // If an encryption key was provided, encrypt the value
+        let bytes = if let Some(cipher) = &self.cipher {
+            let mut nonce = rand::random::<[u8; 12]>().to_vec();
+            let payload = Payload {
+                msg: value,
+                aad: store_key.as_bytes(),
+            };
+            let mut ciphertext = cipher.encrypt(Nonce::from_slice(&nonce), payload)?;
+            nonce.append(&mut ciphertext);
+            nonce
+        } else {
+            value.to_vec()
+        };
+
+        lock_store().insert(store_key, (bytes, metadata));
+        Ok(())
+    }
+
+    /// Remove an item by its key.
+    pub fn remove(&self, key: &str) -> Result<(), ShmapError> {
+        lock_store().remove(&self.store_key(key));
+        Ok(())
+    }
+
+    /// List available keys.
+    pub fn keys(&self) -> Result<Vec<String>, ShmapError> {
+        self.clean()
+    }
+
+    /// Clean expired items. Return the live keys.
+    pub fn clean(&self) -> Result<Vec<String>, ShmapError> {
+        let mut store = lock_store();
+        store.retain(|_, (_, metadata)| !metadata.is_expired_on(&*self.clock));
+        Ok(store
+            .values()
+            .filter(|(_, metadata)| metadata.namespace == self.namespace)
+            .map(|(_, metadata)| metadata.key.clone())
+            .collect())
+    }
+}
+
+/// Lock [`STORE`], even if a thread panicked while holding it: every change is a single map
+/// operation, so it is never left half-done.
+fn lock_store() -> MutexGuard<'static, Items> {
+    STORE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn serialize<T>(key: &str, value: &T) -> Result<Vec<u8>, ShmapError>
+where
+    T: Serialize + ?Sized,
+{
+    bincode::serde::encode_to_vec(value, bincode::config::standard()).map_err(|e| {
+        ShmapError::Serialization {
+            key: key.to_owned(),
+            source: SerializationError::Encode(e),
+        }
+    })
+}
+
+fn deserialize<T>(key: &str, bytes: &[u8]) -> Result<T, ShmapError>
+where
+    T: DeserializeOwned,
+{
+    let (value, _): (T, usize) =
+        bincode::serde::decode_from_slice(bytes, bincode::config::standard()).map_err(|e| {
+            ShmapError::Serialization {
+                key: key.to_owned(),
+                source: SerializationError::Decode {
+                    len: bytes.len(),
+                    source: e,
+                },
+            }
+        })?;
+    Ok(value)
+}
@@ -0,0 +1,43 @@
+use crate::{map::LOG_TARGET, Shmap};
+use log::warn;
+
+/// Guard returned by [`Shmap::insert_scoped`], which removes its item when dropped.
+#[must_use = "the item is removed as soon as the guard is dropped"]
+pub struct ScopedKey<'a> {
+    shmap: &'a Shmap,
+    key: Option<String>,
+}
+
+impl<'a> ScopedKey<'a> {
+    pub(crate) fn new(shmap: &'a Shmap, key: &str) -> Self {
+        Self {
+            shmap,
+            key: Some(key.to_owned()),
+        }
+    }
+
+    /// Key of the guarded item.
+    #[must_use]
+    pub fn key(&self) -> &str {
+        self.key.as_deref().unwrap_or_default()
+    }
+
+    /// Drop the guard without removing the item, which then lives on like any other.
+    pub fn forget(mut self) {
+        self.key = None;
+    }
+}
+
+impl Drop for ScopedKey<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            if let Err(e) = self.shmap.remove(&key) {
+                warn!(
+                    target: LOG_TARGET,
+                    op = "remove";
+                    "Could not remove scoped key <{key}> : {e}"
+                );
+            }
+        }
+    }
+}
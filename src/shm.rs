@@ -1,13 +1,27 @@
 //! Provides functions that create shared memory file descriptors.
 //! Inspired by <https://github.com/unrelentingtech/shmemfdrs>
+//!
+//! Files are created with `shm_open` in [`SHM_DIR`], or with a plain `open` in any other directory
+//! (which should preferably be a tmpfs too).
 
 use crate::errors::ShmapError;
 use memmap2::{MmapAsRawDesc, MmapRawDescriptor};
-use std::os::unix::io::RawFd;
+use std::{
+    ffi::CString,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, IntoRawFd, RawFd},
+    },
+    path::Path,
+};
 
 pub const SHM_DIR: &str = "/dev/shm";
+/// Maximum length of a shm file name.
+pub const NAME_MAX: usize = 255;
 
-/// File descriptor struct, allowing to close fd on Drop
+/// File descriptor struct, allowing to close fd on Drop.
+///
+/// Use [`IntoRawFd::into_raw_fd`] to take ownership of the descriptor, which is then not closed.
 #[derive(Debug)]
 pub struct Fd(RawFd);
 
@@ -23,6 +37,21 @@ impl MmapAsRawDesc for Fd {
     }
 }
 
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl IntoRawFd for Fd {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.0;
+        // Disarm Drop, the caller now owns the descriptor
+        std::mem::forget(self);
+        fd
+    }
+}
+
 impl Drop for Fd {
     fn drop(&mut self) {
         // SAFETY: libc call is unsafe
@@ -33,54 +62,205 @@ impl Drop for Fd {
 }
 
 /// Open shm in readonly.
-pub fn open_read(name: &str) -> Result<Fd, ShmapError> {
-    let fd = shm_open(name, libc::O_RDONLY)?;
-    // On success, returns a file descriptor (a nonnegative integer)
-    if fd < 0 {
-        let err = std::io::Error::last_os_error();
-        // If the error is "file not found", return a custom error, else, errno
-        if err.kind() == std::io::ErrorKind::NotFound {
+pub fn open_read(dir: &Path, name: &str) -> Result<Fd, ShmapError> {
+    match shm_open(dir, name, libc::O_RDONLY) {
+        // If the error is "file not found", return a custom error
+        Err(ShmapError::SyscallError { source, .. })
+            if source.kind() == std::io::ErrorKind::NotFound =>
+        {
             Err(ShmapError::ShmFileNotFound)
-        } else {
-            Err(ShmapError::IOError(err))
         }
-    } else {
-        Ok(fd.into())
+        ret => ret,
     }
 }
 
 /// Open shm with read/write rights, and initialze it to `length`size.
-pub fn open_write(name: &str, length: usize) -> Result<Fd, ShmapError> {
-    let fd = shm_open(name, libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC)?;
-    // On success, returns a file descriptor (a nonnegative integer)
-    if fd < 0 {
-        let err = std::io::Error::last_os_error();
-        return Err(ShmapError::IOError(err));
+///
+/// If `fallocate` is set, the space is reserved with `posix_fallocate` instead of `ftruncate`,
+/// which leaves a sparse file: a full filesystem is then reported as
+/// [`ShmapError::OutOfSpace`] here, rather than as a `SIGBUS` on the first write to the mapping.
+pub fn open_write(
+    dir: &Path,
+    name: &str,
+    length: usize,
+    fallocate: bool,
+) -> Result<Fd, ShmapError> {
+    open_sized(
+        dir,
+        name,
+        length,
+        fallocate,
+        libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC,
+    )
+}
+
+/// Create shm with read/write rights, like [`open_write`], only if it does not exist yet: the
+/// kernel atomically fails with [`ShmapError::ShmFileExists`] otherwise (`O_EXCL`).
+///
+/// If it can not be initialized to `length` size, the created file is unlinked, so that a
+/// failure never leaves a file behind, nor removes one created by someone else.
+pub fn open_create_exclusive(
+    dir: &Path,
+    name: &str,
+    length: usize,
+    fallocate: bool,
+) -> Result<Fd, ShmapError> {
+    let length = libc::off_t::try_from(length).map_err(|_| ShmapError::ValueTooLarge)?;
+
+    let fd = match shm_open(dir, name, libc::O_RDWR | libc::O_CREAT | libc::O_EXCL) {
+        Err(ShmapError::SyscallError { source, .. })
+            if source.kind() == std::io::ErrorKind::AlreadyExists =>
+        {
+            return Err(ShmapError::ShmFileExists);
+        }
+        ret => ret?,
+    };
+    if let Err(e) = set_len(&fd, length, fallocate) {
+        drop(fd);
+        let _ = unlink(dir, name);
+        return Err(e);
+    }
+    Ok(fd)
+}
+
+/// Open shm with `flags`, and initialize it to `length` size, see [`open_write`].
+fn open_sized(
+    dir: &Path,
+    name: &str,
+    length: usize,
+    fallocate: bool,
+    flags: i32,
+) -> Result<Fd, ShmapError> {
+    // Validate the length before creating anything, it would wrap into a garbage size otherwise
+    let length = libc::off_t::try_from(length).map_err(|_| ShmapError::ValueTooLarge)?;
+
+    let fd = shm_open(dir, name, flags)?;
+    set_len(&fd, length, fallocate)?;
+    Ok(fd)
+}
+
+/// Initialize shm to `length` size, see [`open_write`].
+fn set_len(fd: &Fd, length: libc::off_t, fallocate: bool) -> Result<(), ShmapError> {
+    if fallocate && length > 0 {
+        // SAFETY: libc call is unsafe
+        let ret = unsafe { libc::posix_fallocate(fd.0, 0, length) };
+        // Returns the error number instead of setting errno
+        return match ret {
+            0 => Ok(()),
+            libc::ENOSPC => Err(ShmapError::OutOfSpace),
+            errno => Err(ShmapError::SyscallError {
+                syscall: "posix_fallocate",
+                source: std::io::Error::from_raw_os_error(errno),
+            }),
+        };
     }
 
     // SAFETY: libc call is unsafe
-    #[allow(clippy::cast_possible_wrap)]
-    let ret = unsafe { libc::ftruncate(fd, length as libc::off_t) };
+    let ret = unsafe { libc::ftruncate(fd.0, length) };
     if ret != 0 {
+        Err(ShmapError::SyscallError {
+            syscall: "ftruncate",
+            source: std::io::Error::last_os_error(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Open shm with `flags`.
+///
+/// The fd limit raised when the Shmap is built may have been lowered since (e.g. with
+/// `setrlimit`): on `EMFILE` or `ENFILE`, it is raised again once before retrying, and a
+/// persistent failure is reported as [`ShmapError::TooManyOpenFiles`].
+fn shm_open(dir: &Path, name: &str, flags: i32) -> Result<Fd, ShmapError> {
+    let mut raised = false;
+    loop {
+        let fd = shm_open_raw(dir, name, flags)?;
+        // On success, returns a file descriptor (a nonnegative integer)
+        if fd >= 0 {
+            return Ok(fd.into());
+        }
         let err = std::io::Error::last_os_error();
-        Err(ShmapError::IOError(err))
+        match err.raw_os_error() {
+            Some(libc::EMFILE | libc::ENFILE) if raised => {
+                return Err(ShmapError::TooManyOpenFiles);
+            }
+            Some(libc::EMFILE | libc::ENFILE) => {
+                raised = true;
+                let _ = fdlimit::raise_fd_limit();
+            }
+            _ => {
+                return Err(ShmapError::SyscallError {
+                    syscall: open_syscall(dir),
+                    source: err,
+                })
+            }
+        }
+    }
+}
+
+/// Name of the syscall opening the files of `dir`, for errors.
+fn open_syscall(dir: &Path) -> &'static str {
+    if dir == Path::new(SHM_DIR) {
+        "shm_open"
     } else {
-        Ok(fd.into())
+        "open"
     }
 }
 
-fn shm_open(name: &str, flags: i32) -> Result<RawFd, ShmapError> {
-    let name = std::ffi::CString::new(name)?;
-    // SAFETY: libc call is unsafe
-    let fd = unsafe { libc::shm_open(name.as_ptr(), flags, 0o600) };
+fn shm_open_raw(dir: &Path, name: &str, flags: i32) -> Result<RawFd, ShmapError> {
+    let fd = if dir == Path::new(SHM_DIR) {
+        let name = CString::new(name)?;
+        // SAFETY: libc call is unsafe
+        unsafe { libc::shm_open(name.as_ptr(), flags, 0o600) }
+    } else {
+        // Same flags as the ones shm_open adds
+        let path = CString::new(dir.join(name).as_os_str().as_bytes())?;
+        // SAFETY: libc call is unsafe
+        unsafe {
+            libc::open(
+                path.as_ptr(),
+                flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                0o600,
+            )
+        }
+    };
     Ok(fd)
 }
 
-/// Unlink (remove) shm by its name.
-pub fn unlink(name: &str) -> Result<(), ShmapError> {
-    let c_name = std::ffi::CString::new(name)?;
+/// Space available to unprivileged users on the filesystem of `dir`, in bytes.
+// Block counts and sizes are narrower than u64 on some platforms
+#[allow(clippy::useless_conversion)]
+pub fn available_bytes(dir: &Path) -> Result<u64, ShmapError> {
+    let path = CString::new(dir.as_os_str().as_bytes())?;
+    // SAFETY: zeroed memory is a valid statvfs, only written to
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
     // SAFETY: libc call is unsafe
-    let ret = unsafe { libc::shm_unlink(c_name.as_ptr()) };
+    if unsafe { libc::statvfs(path.as_ptr(), &raw mut stat) } != 0 {
+        return Err(ShmapError::SyscallError {
+            syscall: "statvfs",
+            source: std::io::Error::last_os_error(),
+        });
+    }
+    Ok(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+}
+
+/// Rename shm `from` to `to`, atomically replacing `to` if it exists.
+pub fn rename(dir: &Path, from: &str, to: &str) -> Result<(), ShmapError> {
+    std::fs::rename(dir.join(from), dir.join(to)).map_err(ShmapError::IOError)
+}
+
+/// Unlink (remove) shm by its name.
+pub fn unlink(dir: &Path, name: &str) -> Result<(), ShmapError> {
+    let ret = if dir == Path::new(SHM_DIR) {
+        let c_name = CString::new(name)?;
+        // SAFETY: libc call is unsafe
+        unsafe { libc::shm_unlink(c_name.as_ptr()) }
+    } else {
+        let c_path = CString::new(dir.join(name).as_os_str().as_bytes())?;
+        // SAFETY: libc call is unsafe
+        unsafe { libc::unlink(c_path.as_ptr()) }
+    };
     // returns 0 on success, or -1 on error
     if ret != 0 {
         let err = std::io::Error::last_os_error();
@@ -88,7 +268,15 @@ pub fn unlink(name: &str) -> Result<(), ShmapError> {
         if err.kind() == std::io::ErrorKind::NotFound {
             Ok(())
         } else {
-            Err(ShmapError::IOError(err))
+            let syscall = if dir == Path::new(SHM_DIR) {
+                "shm_unlink"
+            } else {
+                "unlink"
+            };
+            Err(ShmapError::SyscallError {
+                syscall,
+                source: err,
+            })
         }
     } else {
         Ok(())
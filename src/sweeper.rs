@@ -0,0 +1,71 @@
+//! Background cleaning of expired items, see [`crate::ShmapBuilder::with_sweep_interval`].
+
+use crate::{map::LOG_TARGET, Shmap};
+use log::error;
+use std::{
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+/// Resources shared by all the clones of a Shmap, released once, when the last one is dropped:
+/// the sweeper thread is stopped and joined, then the final clean is run if enabled.
+pub struct Teardown {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    sweeper: Option<JoinHandle<()>>,
+    clean_on_drop: Option<Shmap>,
+}
+
+impl Teardown {
+    /// Start the sweeper if `sweep_interval` is set. `shmap` must not hold a teardown itself,
+    /// otherwise it would never be dropped.
+    pub fn new(shmap: &Shmap, sweep_interval: Option<Duration>, clean_on_drop: bool) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let sweeper = sweep_interval.map(|interval| {
+            let shmap = shmap.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || sweep(&shmap, interval, &stop))
+        });
+        Self {
+            stop,
+            sweeper,
+            clean_on_drop: clean_on_drop.then(|| shmap.clone()),
+        }
+    }
+}
+
+impl Drop for Teardown {
+    fn drop(&mut self) {
+        let (stopped, condvar) = &*self.stop;
+        *stopped.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        condvar.notify_all();
+        if let Some(sweeper) = self.sweeper.take() {
+            let _ = sweeper.join();
+        }
+        if let Some(shmap) = &self.clean_on_drop {
+            if let Err(e) = shmap.clean() {
+                error!(target: LOG_TARGET, op = "clean"; "Error while cleaning shmap keys: {e}");
+            }
+        }
+    }
+}
+
+/// Clean `shmap` every `interval`, until `stop` is set.
+// The guard is released before cleaning, the lint misses it
+#[allow(clippy::significant_drop_tightening)]
+fn sweep(shmap: &Shmap, interval: Duration, stop: &(Mutex<bool>, Condvar)) {
+    let (stopped, condvar) = stop;
+    loop {
+        let guard = stopped.lock().unwrap_or_else(PoisonError::into_inner);
+        let (guard, _) = condvar
+            .wait_timeout_while(guard, interval, |stopped| !*stopped)
+            .unwrap_or_else(PoisonError::into_inner);
+        if *guard {
+            return;
+        }
+        drop(guard);
+        if let Err(e) = shmap.clean() {
+            error!(target: LOG_TARGET, op = "sweep"; "Error while cleaning shmap keys: {e}");
+        }
+    }
+}
@@ -0,0 +1,880 @@
+use crate::{
+    map::{sanitize_key, sanitize_key_with},
+    tests::map::{capture_logs, init_logger, rand_string, tmp_dir},
+    Clock, EvictionPolicy, KeyHash, SerializationError, Shmap, ShmapBuilder, ShmapError,
+    SystemClock,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bincode::error::DecodeError;
+use chrono::{DateTime, Utc};
+use log::Level;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+// test configuration from environment variables, read from a map as they are process-wide
+#[test]
+fn test_from_env() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let key = rand_string(20);
+    let value = rand_string(50);
+
+    let mut vars = HashMap::from([
+        ("SHMAP_DIR", OsString::from(dir.as_os_str())),
+        ("SHMAP_DEFAULT_TTL_SECS", OsString::from("60")),
+        ("SHMAP_KEY", OsString::from(STANDARD.encode([42u8; 32]))),
+    ]);
+    let from_vars =
+        |vars: &HashMap<&str, OsString>| ShmapBuilder::from_vars(|name| vars.get(name).cloned());
+    let shmap = from_vars(&vars).unwrap().build().unwrap();
+
+    shmap.insert(&key, value.clone()).unwrap();
+    assert!(dir.join(sanitize_key(None, &key)).exists());
+    let (_, _, metadata) = shmap.dump_entries(&[&key]).unwrap().remove(0);
+    assert!(metadata.encrypted);
+    let remaining = metadata.expiration.unwrap() - Utc::now();
+    assert!(remaining > chrono::Duration::seconds(55));
+    assert!(remaining <= chrono::Duration::seconds(60));
+    let ret_value: String = shmap.get(&key).unwrap().unwrap();
+    assert_eq!(ret_value, value);
+
+    vars.insert("SHMAP_DEFAULT_TTL_SECS", OsString::from("one minute"));
+    assert!(matches!(
+        from_vars(&vars),
+        Err(ShmapError::InvalidEnvVar("SHMAP_DEFAULT_TTL_SECS"))
+    ));
+    vars.remove("SHMAP_DEFAULT_TTL_SECS");
+
+    vars.insert("SHMAP_KEY", OsString::from(STANDARD.encode([42u8; 16])));
+    assert!(matches!(
+        from_vars(&vars),
+        Err(ShmapError::InvalidEnvVar("SHMAP_KEY"))
+    ));
+}
+
+#[test]
+fn test_default_ttl() {
+    init_logger();
+
+    let shmap = ShmapBuilder::new()
+        .with_default_ttl(Duration::from_secs(30))
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let key_no_expiry = rand_string(20);
+    let key_ttl = rand_string(20);
+
+    shmap.insert(&key, 1).unwrap();
+    shmap.insert_no_expiry(&key_no_expiry, 2).unwrap();
+    shmap
+        .insert_with_ttl(&key_ttl, 3, Duration::from_secs(90))
+        .unwrap();
+
+    let entries = shmap
+        .dump_entries(&[&key, &key_no_expiry, &key_ttl])
+        .unwrap();
+    let remaining = entries[0].2.expiration.unwrap() - Utc::now();
+    assert!(remaining > chrono::Duration::seconds(25));
+    assert!(remaining <= chrono::Duration::seconds(30));
+    assert!(entries[1].2.expiration.is_none());
+    let remaining = entries[2].2.expiration.unwrap() - Utc::now();
+    assert!(remaining > chrono::Duration::seconds(85));
+
+    for key in [key, key_no_expiry, key_ttl] {
+        shmap.remove(&key).unwrap();
+    }
+}
+
+#[test]
+fn test_mlock() {
+    init_logger();
+
+    let shmap = ShmapBuilder::new()
+        .with_encryption(&[42u8; 32])
+        .with_mlock(true)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let value = rand_string(50);
+
+    // Fails with `MlockError` if mlock is attempted but not permitted
+    shmap.insert(&key, value.clone()).unwrap();
+    let ret_value: String = shmap.get(&key).unwrap().unwrap();
+    assert_eq!(ret_value, value);
+
+    shmap.remove(&key).unwrap();
+}
+
+#[test]
+fn test_access_stats() {
+    init_logger();
+
+    let shmap = ShmapBuilder::new().with_access_stats(true).build().unwrap();
+    let key = rand_string(20);
+
+    shmap.insert(&key, rand_string(50)).unwrap();
+    let metadata = shmap.metadata(&key).unwrap().unwrap();
+    assert_eq!(metadata.access_count, 0);
+    assert!(metadata.last_access.is_none());
+
+    for _ in 0..3 {
+        let _: String = shmap.get(&key).unwrap().unwrap();
+    }
+    let metadata = shmap.metadata(&key).unwrap().unwrap();
+    assert_eq!(metadata.access_count, 3);
+    assert!(metadata.last_access.is_some());
+
+    // Disabled by default
+    let _: String = Shmap::new().get(&key).unwrap().unwrap();
+    assert_eq!(shmap.metadata(&key).unwrap().unwrap().access_count, 3);
+
+    shmap.remove(&key).unwrap();
+}
+
+#[test]
+fn test_max_bytes() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_max_bytes(350)
+        .with_eviction_policy(EvictionPolicy::Lru)
+        .build()
+        .unwrap();
+    let keys: Vec<String> = (0..5).map(|_| rand_string(20)).collect();
+
+    for key in &keys {
+        // ~101 bytes once serialized
+        shmap.insert(key, rand_string(100)).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    for key in &keys[..2] {
+        assert_eq!(shmap.get::<String>(key).unwrap(), None);
+    }
+    for key in &keys[2..] {
+        assert!(shmap.get::<String>(key).unwrap().is_some());
+    }
+}
+
+#[test]
+fn test_builder() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = Shmap::builder()
+        .with_encryption(&[3u8; 32])
+        .with_namespace("builder")
+        .with_dir(&dir)
+        .with_default_ttl(Duration::from_secs(30))
+        .with_access_stats(true)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+
+    shmap.insert(&key, 42).unwrap();
+    assert!(dir.join(sanitize_key(Some("builder"), &key)).exists());
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), Some(42));
+
+    let metadata = shmap.metadata(&key).unwrap().unwrap();
+    assert!(metadata.encrypted);
+    assert_eq!(metadata.namespace.as_deref(), Some("builder"));
+    assert!(metadata.expiration.is_some());
+    assert_eq!(metadata.access_count, 1);
+
+    assert!(matches!(
+        Shmap::builder().with_namespace("not valid").build(),
+        Err(ShmapError::InvalidNamespace)
+    ));
+}
+
+#[test]
+fn test_quiet() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_quiet(true)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    shmap
+        .insert_with_ttl(&key, 1, Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    let logs = capture_logs(|| {
+        shmap.clean().unwrap();
+    });
+    assert!(logs.iter().all(|log| log.level > Level::Warn));
+    assert!(logs
+        .iter()
+        .any(|log| log.level == Level::Debug && log.message.contains("expired")));
+}
+
+#[test]
+fn test_config_check() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_config_check(true)
+        .build()
+        .unwrap();
+    let shmap_enc = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_encryption(&[9u8; 32])
+        .with_config_check(true)
+        .build()
+        .unwrap();
+    assert_ne!(shmap.config_fingerprint(), shmap_enc.config_fingerprint());
+    assert_eq!(
+        shmap.config_fingerprint(),
+        ShmapBuilder::new().build().unwrap().config_fingerprint()
+    );
+    for builder in [
+        ShmapBuilder::new().with_key_hash(KeyHash::Sha256),
+        ShmapBuilder::new().with_raw_keys(true),
+    ] {
+        assert_ne!(
+            shmap.config_fingerprint(),
+            builder.build().unwrap().config_fingerprint()
+        );
+    }
+
+    shmap.insert("key", 1).unwrap();
+    assert!(matches!(
+        shmap_enc.insert("key", 2),
+        Err(ShmapError::ConfigMismatch { .. })
+    ));
+    assert_eq!(shmap.get::<i32>("key").unwrap(), Some(1));
+}
+
+#[test]
+fn test_key_hash() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let key = rand_string(20);
+    for (key_hash, hex_len) in [(KeyHash::Sha224, 56), (KeyHash::Sha256, 64)] {
+        let shmap = ShmapBuilder::new()
+            .with_dir(&dir)
+            .with_key_hash(key_hash)
+            .build()
+            .unwrap();
+        shmap.insert(&key, format!("{key_hash:?}")).unwrap();
+
+        let filename = sanitize_key_with(key_hash, None, &key);
+        assert_eq!(filename.len(), "shmap.".len() + hex_len);
+        assert!(dir.join(&filename).exists());
+        assert_eq!(
+            shmap.get::<String>(&key).unwrap(),
+            Some(format!("{key_hash:?}"))
+        );
+    }
+    assert_eq!(
+        sanitize_key_with(KeyHash::default(), None, &key),
+        sanitize_key(None, &key)
+    );
+}
+
+struct ManualClock(Mutex<DateTime<Utc>>);
+
+impl ManualClock {
+    fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += chrono::Duration::from_std(duration).unwrap();
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[test]
+fn test_clock() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let clock = Arc::new(ManualClock(Mutex::new(Utc::now())));
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_clock(clock.clone())
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let key_clean = rand_string(20);
+    let key_no_expiry = rand_string(20);
+    shmap
+        .insert_with_ttl(&key, 1, Duration::from_mins(1))
+        .unwrap();
+    shmap
+        .insert_with_ttl(&key_clean, 2, Duration::from_mins(1))
+        .unwrap();
+    shmap.insert(&key_no_expiry, 3).unwrap();
+
+    clock.advance(Duration::from_secs(59));
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), Some(1));
+
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), None);
+    assert_eq!(shmap.clean().unwrap(), vec![key_no_expiry.clone()]);
+    assert!(!dir.join(sanitize_key(None, &key_clean)).exists());
+
+    // Ages are measured on the clock too
+    assert!(shmap.age(&key_no_expiry).unwrap().unwrap() >= Duration::from_mins(1));
+}
+
+/// Wall clock of the system shifted by an offset, to simulate steps, with the system monotonic time.
+struct SteppedClock(Mutex<chrono::Duration>);
+
+impl SteppedClock {
+    fn step(&self, step: chrono::Duration) {
+        *self.0.lock().unwrap() += step;
+    }
+}
+
+impl Clock for SteppedClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now() + *self.0.lock().unwrap()
+    }
+
+    fn monotonic_now(&self) -> Option<Duration> {
+        SystemClock.monotonic_now()
+    }
+}
+
+#[test]
+fn test_monotonic_ttl() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let clock = Arc::new(SteppedClock(Mutex::new(chrono::Duration::zero())));
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_clock(clock.clone())
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let key_short = rand_string(20);
+
+    // A step forward of the wall clock does not expire the item early
+    shmap
+        .insert_with_ttl(&key, 1, Duration::from_hours(1))
+        .unwrap();
+    clock.step(chrono::Duration::hours(2));
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), Some(1));
+
+    // A step backward (e.g. by NTP) does not keep the item late
+    shmap
+        .insert_with_ttl(&key_short, 2, Duration::from_millis(50))
+        .unwrap();
+    clock.step(-chrono::Duration::hours(4));
+    assert_eq!(shmap.get::<i32>(&key_short).unwrap(), Some(2));
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(shmap.get::<i32>(&key_short).unwrap(), None);
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), Some(1));
+    assert_eq!(shmap.clean().unwrap(), vec![key.clone()]);
+
+    let metadata = shmap.metadata(&key).unwrap().unwrap();
+    assert!(metadata.monotonic_expiration.is_some());
+    assert!(!metadata.is_expired());
+}
+
+#[test]
+fn test_durable_dir() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let durable_dir = tmp_dir();
+    let builder = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_durable_dir(&durable_dir)
+        .with_encryption(&[3u8; 32])
+        .with_chunk_size(16);
+    let shmap = builder.clone().build().unwrap();
+    let key = rand_string(20);
+    let key_split = rand_string(20);
+    let key_expired = rand_string(20);
+    let key_removed = rand_string(20);
+    let value = rand_string(50);
+    shmap
+        .insert_with_ttl(&key, value.clone(), Duration::from_mins(1))
+        .unwrap();
+    shmap.insert(&key_split, rand_string(100)).unwrap();
+    shmap
+        .insert_with_ttl(&key_expired, 1, Duration::from_millis(1))
+        .unwrap();
+    shmap.insert(&key_removed, 2).unwrap();
+    shmap.remove(&key_removed).unwrap();
+    let value_split = shmap.get::<String>(&key_split).unwrap();
+    assert!(value_split.is_some());
+
+    // Copies are encrypted too
+    let copy = std::fs::read(durable_dir.join(sanitize_key(None, &key))).unwrap();
+    assert!(!copy
+        .windows(value.len())
+        .any(|window| window == value.as_bytes()));
+
+    // Reboot
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::create_dir(&dir).unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    let shmap = builder.build().unwrap();
+    assert_eq!(shmap.get::<String>(&key).unwrap(), Some(value));
+    assert_eq!(shmap.get::<String>(&key_split).unwrap(), value_split);
+    assert_eq!(shmap.get::<i32>(&key_expired).unwrap(), None);
+    assert_eq!(shmap.get::<i32>(&key_removed).unwrap(), None);
+    assert!(!durable_dir.join(sanitize_key(None, &key_expired)).exists());
+    assert!(!durable_dir.join(sanitize_key(None, &key_removed)).exists());
+}
+
+#[test]
+fn test_raw_keys() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_raw_keys(true)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    shmap.insert(&key, 1).unwrap();
+    assert!(dir.join(format!("shmap.{key}")).exists());
+    assert!(dir.join(format!("shmap.{key}.metadata")).exists());
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), Some(1));
+    assert_eq!(shmap.keys().unwrap(), vec![key.clone()]);
+
+    for key in [
+        "", "a/b", "a\0b", "a.b", "clé", "config", "changes", "metadata", "lock", "shadow", "eph",
+        "tmp", "part1",
+    ] {
+        assert!(matches!(
+            shmap.insert(key, 1),
+            Err(ShmapError::InvalidRawKey)
+        ));
+    }
+    assert!(matches!(
+        shmap.insert(&rand_string(300), 1),
+        Err(ShmapError::KeyNameTooLong)
+    ));
+
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_namespace("raw")
+        .with_raw_keys(true)
+        .build()
+        .unwrap();
+    shmap.insert(&key, 2).unwrap();
+    assert!(dir.join(format!("shmap.raw.{key}")).exists());
+
+    // Keys which only end like reserved names are items
+    for key in ["unlock", "partial", "part", "config-1"] {
+        shmap.insert(key, 3).unwrap();
+    }
+    assert_eq!(shmap.clean().unwrap().len(), 5);
+    assert_eq!(shmap.keys().unwrap().len(), 5);
+    assert_eq!(shmap.get::<i32>("unlock").unwrap(), Some(3));
+    assert!(matches!(
+        shmap.insert("lock", 1),
+        Err(ShmapError::InvalidRawKey)
+    ));
+}
+
+#[test]
+fn test_sweeper() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_sweep_interval(Duration::from_millis(10))
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    shmap
+        .insert_with_ttl(&key, 1, Duration::from_millis(1))
+        .unwrap();
+    let filename = dir.join(sanitize_key(None, &key));
+    let start = std::time::Instant::now();
+    while filename.exists() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    drop(shmap);
+
+    // Dropping the last clone joins the sweeper, even while it waits for the next interval
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_sweep_interval(Duration::from_hours(1))
+        .with_clean_on_drop(true)
+        .build()
+        .unwrap();
+    shmap
+        .insert_with_ttl(&key, 1, Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    let clone = shmap.clone();
+    drop(shmap);
+    assert!(filename.exists());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        drop(clone);
+        sender.send(()).unwrap();
+    });
+    receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(!filename.exists());
+}
+
+#[test]
+fn test_lock_dir() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let lock_dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_lock_dir(&lock_dir)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let lock_filename = shmap.shm_lock_filename(&key).unwrap();
+
+    shmap.insert(&key, 1).unwrap();
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), Some(1));
+    assert!(dir.join(sanitize_key(None, &key)).exists());
+    assert!(lock_dir.join(&lock_filename).exists());
+    assert!(!dir.join(&lock_filename).exists());
+
+    // Orphan locks are removed from the lock directory
+    shmap.remove(&key).unwrap();
+    assert!(lock_dir.join(&lock_filename).exists());
+    let report = shmap.compact().unwrap();
+    assert!(report.orphan_locks >= 1);
+    assert!(!lock_dir.join(&lock_filename).exists());
+
+    std::fs::remove_dir_all(dir).unwrap();
+    std::fs::remove_dir_all(lock_dir).unwrap();
+}
+
+#[test]
+fn test_type_check() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let builder = ShmapBuilder::new().with_dir(&dir);
+    let shmap = builder.clone().with_type_check(true).build().unwrap();
+    let key = rand_string(20);
+
+    shmap.insert(&key, 42u32).unwrap();
+    assert_eq!(shmap.get::<u32>(&key).unwrap(), Some(42));
+    match shmap.get::<String>(&key) {
+        Err(ShmapError::TypeMismatch { expected, found }) => {
+            assert_eq!(expected, std::any::type_name::<String>());
+            assert_eq!(found, "u32");
+        }
+        ret => panic!("unexpected {ret:?}"),
+    }
+
+    // Opt-in: without type checks, the bytes are decoded as is
+    let unchecked = builder.build().unwrap();
+    assert!(matches!(
+        unchecked.get::<String>(&key),
+        Err(ShmapError::Serialization { .. })
+    ));
+
+    // Untyped items are read as any type
+    unchecked.insert(&key, 42u32).unwrap();
+    assert!(matches!(
+        shmap.get::<String>(&key),
+        Err(ShmapError::Serialization { .. })
+    ));
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_gzip_json_format() {
+    use crate::Format;
+
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_format(Format::GzipJson)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let value = vec![rand_string(50), rand_string(50)];
+
+    shmap.insert(&key, &value).unwrap();
+    assert_eq!(shmap.get::<Vec<String>>(&key).unwrap(), Some(value.clone()));
+
+    let path = dir.join(sanitize_key(None, &key));
+    assert_eq!(std::fs::read(&path).unwrap()[..2], [0x1f, 0x8b]);
+    let output = std::process::Command::new("gzip")
+        .arg("-dc")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("[\"{}\",\"{}\"]", value[0], value[1])
+    );
+
+    // Not readable as bincode
+    let bincode = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_config_check(false)
+        .build()
+        .unwrap();
+    assert!(bincode.get::<Vec<String>>(&key).is_err());
+    assert_ne!(bincode.config_fingerprint(), shmap.config_fingerprint());
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_gzip_json_decode_limit() {
+    use crate::Format;
+
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_format(Format::GzipJson)
+        .with_decode_limit(1000)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let value = rand_string(500);
+    shmap.insert(&key, &value).unwrap();
+    assert_eq!(shmap.get::<String>(&key).unwrap(), Some(value));
+
+    // A few KiB which decompress past the limit
+    shmap.insert(&key, "a".repeat(1 << 20)).unwrap();
+    assert!(
+        std::fs::metadata(dir.join(sanitize_key(None, &key)))
+            .unwrap()
+            .len()
+            < 10_000
+    );
+    assert!(matches!(
+        shmap.get::<String>(&key),
+        Err(ShmapError::Serialization {
+            source: SerializationError::Gzip(_),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_empty_values() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_empty_values(true)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    shmap.insert_raw(&key, b"").unwrap();
+    assert_eq!(shmap.get_raw(&key).unwrap(), Some(Vec::new()));
+    assert_eq!(shmap.get_raw(&key).unwrap(), Some(Vec::new()));
+    assert!(dir.join(sanitize_key(None, &key)).exists());
+
+    // Removed by default
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    assert_eq!(shmap.get_raw(&key).unwrap(), None);
+    assert!(!dir.join(sanitize_key(None, &key)).exists());
+}
+
+#[test]
+fn test_key_ring() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let old_key = [3u8; 32];
+    let new_key = [4u8; 32];
+    let old = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_encryption(&old_key)
+        .build()
+        .unwrap();
+    let migrated = rand_string(20);
+    old.insert(&migrated, 1).unwrap();
+
+    let ring = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_key_ring(&new_key, &[[5u8; 32], old_key])
+        .build()
+        .unwrap();
+    assert_eq!(ring.get::<i32>(&migrated).unwrap(), Some(1));
+
+    // Writes use the primary key only
+    let inserted = rand_string(20);
+    ring.insert(&inserted, 2).unwrap();
+    ring.insert(&migrated, 3).unwrap();
+    let new = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_encryption(&new_key)
+        .build()
+        .unwrap();
+    assert_eq!(new.get::<i32>(&inserted).unwrap(), Some(2));
+    assert_eq!(new.get::<i32>(&migrated).unwrap(), Some(3));
+    assert!(old.get::<i32>(&inserted).is_err());
+    assert_eq!(ring.get::<i32>(&migrated).unwrap(), Some(3));
+}
+
+#[test]
+fn test_max_read_size() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_max_read_size(64)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    shmap.insert(&key, rand_string(20)).unwrap();
+    assert!(shmap.get::<String>(&key).unwrap().is_some());
+
+    // Written out-of-band, e.g. by a rogue process
+    std::fs::write(dir.join(sanitize_key(None, &key)), vec![0u8; 1 << 20]).unwrap();
+    assert!(matches!(
+        shmap.get::<Vec<u8>>(&key),
+        Err(ShmapError::ValueTooLarge)
+    ));
+    assert!(matches!(
+        shmap.get_raw(&key),
+        Err(ShmapError::ValueTooLarge)
+    ));
+
+    // Parts are checked on their total length
+    let chunked = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_chunk_size(16)
+        .with_max_read_size(64)
+        .build()
+        .unwrap();
+    chunked.insert(&key, vec![1u8; 100]).unwrap();
+    assert!(matches!(
+        chunked.get::<Vec<u8>>(&key),
+        Err(ShmapError::ValueTooLarge)
+    ));
+}
+
+#[test]
+fn test_decode_limit() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_decode_limit(1 << 16)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    shmap.insert(&key, rand_string(1000)).unwrap();
+    assert!(shmap.get::<String>(&key).unwrap().is_some());
+    shmap.insert(&key, rand_string(100_000)).unwrap();
+    assert!(matches!(
+        shmap.get::<String>(&key),
+        Err(ShmapError::Serialization {
+            source: SerializationError::Decode {
+                source: DecodeError::LimitExceeded,
+                ..
+            },
+            ..
+        })
+    ));
+
+    // A string claiming 1 TiB, written out-of-band, fails before allocating it
+    let mut bytes = vec![0xfd];
+    bytes.extend_from_slice(&(1u64 << 40).to_le_bytes());
+    bytes.extend_from_slice(b"tampered");
+    shmap.insert(&key, "").unwrap();
+    std::fs::write(dir.join(sanitize_key(None, &key)), bytes).unwrap();
+    assert!(matches!(
+        shmap.get::<String>(&key),
+        Err(ShmapError::Serialization {
+            source: SerializationError::Decode {
+                source: DecodeError::LimitExceeded,
+                ..
+            },
+            ..
+        })
+    ));
+
+    // Small limits are rounded up to the next power of two only
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_decode_limit(100)
+        .build()
+        .unwrap();
+    shmap.insert(&key, rand_string(100)).unwrap();
+    assert!(shmap.get::<String>(&key).unwrap().is_some());
+    shmap.insert(&key, rand_string(200)).unwrap();
+    assert!(matches!(
+        shmap.get::<String>(&key),
+        Err(ShmapError::Serialization {
+            source: SerializationError::Decode {
+                source: DecodeError::LimitExceeded,
+                ..
+            },
+            ..
+        })
+    ));
+
+    // Limits which can not be enforced are rejected
+    assert!(matches!(
+        ShmapBuilder::new()
+            .with_dir(&dir)
+            .with_decode_limit((1 << 31) + 1)
+            .build(),
+        Err(ShmapError::InvalidDecodeLimit)
+    ));
+}
+
+#[test]
+fn test_config_getters() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = Shmap::builder()
+        .with_encryption(&[3u8; 32])
+        .with_namespace("getters")
+        .with_dir(&dir)
+        .with_default_ttl(Duration::from_secs(30))
+        .with_max_bytes(1024)
+        .build()
+        .unwrap();
+    assert!(shmap.is_encrypted());
+    assert_eq!(shmap.default_ttl(), Some(Duration::from_secs(30)));
+    assert_eq!(shmap.shm_dir(), &*dir);
+    assert_eq!(shmap.prefix(), "shmap");
+    assert_eq!(shmap.namespace(), Some("getters"));
+    assert_eq!(shmap.max_bytes(), Some(1024));
+
+    let shmap = Shmap::new();
+    assert!(!shmap.is_encrypted());
+    assert_eq!(shmap.default_ttl(), None);
+    assert_eq!(shmap.shm_dir(), std::path::Path::new(crate::shm::SHM_DIR));
+    assert_eq!(shmap.namespace(), None);
+    assert_eq!(shmap.max_bytes(), None);
+}
@@ -0,0 +1,253 @@
+//! Tests of the core API, run against each backend.
+
+use crate::tests::map::{init_logger, rand_string};
+use crate::{ShmapBuilder, ShmapError};
+use rand::{prelude::SliceRandom, thread_rng};
+use std::{collections::HashSet, time::Duration};
+
+/// Define the core tests, building stores with the `$build` method of [`ShmapBuilder`].
+macro_rules! core_tests {
+    ($build:ident) => {
+        #[test]
+        #[should_panic(expected = "Option::unwrap()")]
+        fn test_get_unknown() {
+            init_logger();
+
+            let shmap = ShmapBuilder::new().$build().unwrap();
+            let key = rand_string(30);
+            let _: String = shmap.get(&key).unwrap().unwrap();
+        }
+
+        #[test]
+        fn simple_test() {
+            init_logger();
+
+            let shmap = ShmapBuilder::new().$build().unwrap();
+            let key = rand_string(31);
+            let value = rand_string(50);
+
+            shmap.insert(&key, value.clone()).unwrap();
+            let ret_value: String = shmap.get(&key).unwrap().unwrap();
+            assert_eq!(ret_value, value);
+            shmap.remove(&key).unwrap();
+        }
+
+        #[test]
+        fn test_different_size() {
+            init_logger();
+
+            let key = rand_string(32);
+
+            let shmap = ShmapBuilder::new().$build().unwrap();
+            let value = rand_string(50);
+            shmap.insert(&key, value.clone()).unwrap();
+            let ret_value: String = shmap.get(&key).unwrap().unwrap();
+            assert_eq!(ret_value, value);
+
+            let shmap = ShmapBuilder::new().$build().unwrap();
+            let value = rand_string(100);
+            shmap.insert(&key, value.clone()).unwrap();
+            let ret_value: String = shmap.get(&key).unwrap().unwrap();
+            assert_eq!(ret_value, value);
+
+            let shmap = ShmapBuilder::new().$build().unwrap();
+            let value = rand_string(20);
+            shmap.insert(&key, value.clone()).unwrap();
+            let ret_value: String = shmap.get(&key).unwrap().unwrap();
+            assert_eq!(ret_value, value);
+
+            shmap.remove(&key).unwrap();
+        }
+
+        #[test]
+        fn test_bad_key() {
+            init_logger();
+
+            let key = rand_string(35);
+            let value = rand_string(50);
+
+            let mut secret: Vec<u8> = (0..32).collect();
+            secret.shuffle(&mut thread_rng());
+            let shmap = ShmapBuilder::new()
+                .with_encryption(&secret.try_into().unwrap())
+                .$build()
+                .unwrap();
+            shmap.insert(&key, value.clone()).unwrap();
+            let ret_value: String = shmap.get(&key).unwrap().unwrap();
+            assert_eq!(ret_value, value);
+
+            let mut secret: Vec<u8> = (0..32).collect();
+            secret.shuffle(&mut thread_rng());
+            let shmap = ShmapBuilder::new()
+                .with_encryption(&secret.try_into().unwrap())
+                .$build()
+                .unwrap();
+            assert!(
+                shmap.get::<String>(&key).is_err(),
+                "It should not have been possible to decrypt here, with a different key"
+            );
+            shmap.remove(&key).unwrap();
+        }
+
+        #[test]
+        fn test_set_and_get() {
+            init_logger();
+
+            let shmap = ShmapBuilder::new().$build().unwrap();
+            let key = rand_string(36);
+            let value = rand_string(50);
+
+            shmap.insert(&key, value.clone()).unwrap();
+
+            let ret_value: String = shmap.get(&key).unwrap().unwrap();
+            assert_eq!(ret_value, value);
+
+            let ret_value: String = shmap.get(&key).unwrap().unwrap();
+            assert_eq!(ret_value, value);
+
+            shmap.remove(&key).unwrap();
+
+            let key = rand_string(37);
+            let value = vec!["Test".to_string(), "Vec".to_string()];
+
+            shmap.insert(&key, value.clone()).unwrap();
+
+            let ret_value: Vec<String> = shmap.get(&key).unwrap().unwrap();
+            assert_eq!(ret_value, value);
+
+            let ret_value: Vec<String> = shmap.get(&key).unwrap().unwrap();
+            assert_eq!(ret_value, value);
+
+            shmap.remove(&key).unwrap();
+        }
+
+        #[test]
+        fn test_set_and_get_big() {
+            init_logger();
+
+            let shmap = ShmapBuilder::new().$build().unwrap();
+            let key = rand_string(38);
+            let value = rand_string(5 * 1024 * 1024);
+
+            shmap.insert(&key, value.clone()).unwrap();
+
+            let ret_value: String = shmap.get(&key).unwrap().unwrap();
+            assert_eq!(ret_value, value);
+
+            let ret_value: String = shmap.get(&key).unwrap().unwrap();
+            assert_eq!(ret_value, value);
+
+            shmap.remove(&key).unwrap();
+        }
+
+        #[test]
+        fn test_remove() {
+            init_logger();
+
+            let shmap = ShmapBuilder::new().$build().unwrap();
+            let key = rand_string(39);
+            let value = rand_string(50);
+
+            shmap.insert(&key, value).unwrap();
+
+            shmap.remove(&key).unwrap();
+        }
+
+        #[test]
+        fn test_remove_not_found() {
+            init_logger();
+
+            let shmap = ShmapBuilder::new().$build().unwrap();
+            let key = rand_string(40);
+            shmap.remove(&key).unwrap();
+        }
+
+        #[test]
+        #[should_panic(expected = "Option::unwrap()")]
+        fn test_expiration() {
+            init_logger();
+
+            let shmap = ShmapBuilder::new().$build().unwrap();
+            let key = rand_string(41);
+            let value = rand_string(50);
+
+            shmap
+                .insert_with_ttl(&key, value.clone(), Duration::from_millis(200))
+                .unwrap();
+            shmap.clean().unwrap();
+            let ret_value: String = shmap.get(&key).unwrap().unwrap();
+            assert_eq!(ret_value, value);
+
+            std::thread::sleep(Duration::from_millis(300));
+
+            let _: String = shmap.get(&key).unwrap().unwrap();
+        }
+
+        // test key listing
+        #[test]
+        fn test_list_keys() {
+            const NUM: usize = 5;
+            init_logger();
+
+            let shmap = ShmapBuilder::new().$build().unwrap();
+
+            let keys = (0..NUM).map(rand_string).collect::<HashSet<_>>();
+            for key in &keys {
+                let value = rand_string(50);
+                shmap.insert(key, value).unwrap();
+            }
+
+            // Other tests may run in parallel. Ensure that at least NUM keys are present.
+            assert!(shmap.keys().unwrap().len() >= NUM);
+
+            // At least all inserted keys must be present.
+            let current_keys = shmap.keys().unwrap().into_iter().collect();
+            assert!(keys.is_subset(&current_keys));
+
+            for key in &keys {
+                shmap.remove(key).unwrap();
+            }
+        }
+
+        // test namespace isolation
+        #[test]
+        fn test_namespace() {
+            init_logger();
+
+            let shmap = ShmapBuilder::new().$build().unwrap();
+            let shmap_ns = ShmapBuilder::new()
+                .with_namespace(&rand_string(10))
+                .$build()
+                .unwrap();
+            let key = rand_string(48);
+            let value = rand_string(50);
+
+            shmap_ns.insert(&key, value.clone()).unwrap();
+            assert!(shmap.get::<String>(&key).unwrap().is_none());
+            assert!(!shmap.keys().unwrap().contains(&key));
+            let ret_value: String = shmap_ns.get(&key).unwrap().unwrap();
+            assert_eq!(ret_value, value);
+            assert!(shmap_ns.keys().unwrap().contains(&key));
+
+            shmap_ns.remove(&key).unwrap();
+
+            assert!(matches!(
+                ShmapBuilder::new().with_namespace("bad/namespace").$build(),
+                Err(ShmapError::InvalidNamespace)
+            ));
+        }
+    };
+}
+
+mod shm {
+    use super::*;
+
+    core_tests!(build);
+}
+
+#[cfg(feature = "mock")]
+mod memory {
+    use super::*;
+
+    core_tests!(build_in_memory);
+}
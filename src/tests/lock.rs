@@ -0,0 +1,116 @@
+use crate::{
+    lock::{ReadGuard, WriteGuard},
+    shm::SHM_DIR,
+    tests::map::rand_string,
+};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+// test that readers share the lock, while a writer waits for all of them
+#[test]
+fn test_readers_overlap_writer_waits() {
+    let path = PathBuf::from(SHM_DIR).join(format!("shmap.{}.lock", rand_string(20)));
+
+    let start = Instant::now();
+    let readers: Vec<_> = (0..2)
+        .map(|_| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let _guard = ReadGuard::lock(&path).unwrap();
+                std::thread::sleep(Duration::from_millis(500));
+            })
+        })
+        .collect();
+
+    // Let both readers acquire the lock before the writer tries to
+    std::thread::sleep(Duration::from_millis(100));
+    let guard = WriteGuard::lock(&path).unwrap();
+    let write_acquired = start.elapsed();
+    drop(guard);
+
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    assert!(write_acquired >= Duration::from_millis(500));
+    // Both reads lasted 500ms: if they were serialized, it would have taken at least 1s
+    assert!(elapsed < Duration::from_secs(1));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+// test that a lock acquired on an unlinked lock file is acquired again on the current one
+#[test]
+fn test_unlinked_lock_file() {
+    let path = PathBuf::from(SHM_DIR).join(format!("shmap.{}.lock", rand_string(20)));
+
+    let guard = WriteGuard::lock(&path).unwrap();
+    let writer = {
+        let path = path.clone();
+        std::thread::spawn(move || {
+            let _guard = WriteGuard::lock(&path).unwrap();
+            std::thread::sleep(Duration::from_millis(500));
+        })
+    };
+
+    // Unlinked while the writer waits on it, like an orphan lock removed by clean
+    std::thread::sleep(Duration::from_millis(100));
+    std::fs::remove_file(&path).unwrap();
+    drop(guard);
+
+    // The writer now holds the lock on the new lock file
+    std::thread::sleep(Duration::from_millis(100));
+    let start = Instant::now();
+    let _guard = ReadGuard::lock(&path).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(300));
+
+    writer.join().unwrap();
+    std::fs::remove_file(path).unwrap();
+}
+
+/// CPU time consumed by the calling thread.
+fn thread_cpu_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: libc call is unsafe
+    let ret = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &raw mut time) };
+    assert_eq!(ret, 0);
+    Duration::new(
+        u64::try_from(time.tv_sec).unwrap(),
+        u32::try_from(time.tv_nsec).unwrap(),
+    )
+}
+
+// test that contenders sleep in the kernel while waiting for the lock, instead of spinning
+#[test]
+fn test_contention_does_not_spin() {
+    let path = PathBuf::from(SHM_DIR).join(format!("shmap.{}.lock", rand_string(20)));
+
+    let guard = WriteGuard::lock(&path).unwrap();
+    let waiters: Vec<_> = (0..8)
+        .map(|_| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let cpu_start = thread_cpu_time();
+                let start = Instant::now();
+                drop(WriteGuard::lock(&path).unwrap());
+                (start.elapsed(), thread_cpu_time().saturating_sub(cpu_start))
+            })
+        })
+        .collect();
+
+    std::thread::sleep(Duration::from_millis(500));
+    drop(guard);
+    for waiter in waiters {
+        let (waited, cpu) = waiter.join().unwrap();
+        assert!(waited >= Duration::from_millis(300));
+        assert!(cpu < Duration::from_millis(50), "{cpu:?} of CPU time");
+    }
+
+    std::fs::remove_file(path).unwrap();
+}
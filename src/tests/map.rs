@@ -1,14 +1,92 @@
-use crate::shm;
-use crate::{map::sanitize_key, Shmap};
-use log::LevelFilter;
+use crate::shm::{self, SHM_DIR};
+use crate::{
+    lock::ReadGuard,
+    map::{all_namespaces_keys, encode_metadata, lock_path, sanitize_key},
+    CleanReport, GetResult, GetStatus, KeyHash, Shmap, ShmapBuilder, ShmapError, VerifyReport,
+};
+use chrono::Utc;
+use log::{
+    kv::{Key, Value, VisitSource},
+    Level, LevelFilter, Log, Record,
+};
 use memmap2::Mmap;
 use rand::{distributions::Alphanumeric, prelude::SliceRandom, thread_rng, Rng};
+use std::cell::RefCell;
 use std::io::Write;
-use std::{collections::HashSet, str::FromStr, time::Duration};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    time::Duration,
+};
+
+thread_local! {
+    static CAPTURED_LOGS: RefCell<Option<Vec<CapturedLog>>> = const { RefCell::new(None) };
+}
+
+/// A log recorded by [`capture_logs`].
+pub struct CapturedLog {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl<'kvs> VisitSource<'kvs> for CapturedLog {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        self.fields.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+/// Logger forwarding to `env_logger`, which also records the logs of the current thread during
+/// [`capture_logs`].
+struct CaptureLogger(env_logger::Logger);
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        CAPTURED_LOGS.with_borrow_mut(|logs| {
+            if let Some(logs) = logs {
+                let mut log = CapturedLog {
+                    level: record.level(),
+                    target: record.target().to_owned(),
+                    message: record.args().to_string(),
+                    fields: Vec::new(),
+                };
+                let _ = record.key_values().visit(&mut log);
+                logs.push(log);
+            }
+        });
+        if self.0.matches(record) {
+            self.0.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+/// Run `f`, and return the logs it emitted on the current thread, at any level.
+pub fn capture_logs<F: FnOnce()>(f: F) -> Vec<CapturedLog> {
+    init_logger();
+    CAPTURED_LOGS.set(Some(Vec::new()));
+    f();
+    CAPTURED_LOGS.take().unwrap_or_default()
+}
 
 pub fn init_logger() {
     let level = std::env::var("RUST_LOG").unwrap_or_else(|_| "debug".to_string());
-    let _ = env_logger::builder()
+    let logger = env_logger::builder()
         .is_test(true)
         .filter_level(LevelFilter::from_str(&level).unwrap())
         .format(|buf, record| {
@@ -24,7 +102,10 @@ pub fn init_logger() {
                 record.args()
             )
         })
-        .try_init();
+        .build();
+    if log::set_boxed_logger(Box::new(CaptureLogger(logger))).is_ok() {
+        log::set_max_level(LevelFilter::Trace);
+    }
 }
 
 pub fn rand_string(len: usize) -> String {
@@ -35,60 +116,57 @@ pub fn rand_string(len: usize) -> String {
         .collect()
 }
 
-fn read_from_shm(sanitized_key: &str) -> Vec<u8> {
-    let fd = shm::open_read(sanitized_key).unwrap();
-    // SAFETY: Mmap call is unsafe
-    let mmap = unsafe { Mmap::map(fd) }.unwrap();
-    mmap.to_vec()
-}
+/// Empty directory to store items, isolated from other tests, and removed on drop.
+#[derive(Debug)]
+pub struct TmpDir(PathBuf);
 
-#[test]
-#[should_panic(expected = "Option::unwrap()")]
-fn test_get_unknown() {
-    init_logger();
+impl Deref for TmpDir {
+    type Target = Path;
 
-    let shmap = Shmap::new();
-    let key = rand_string(30);
-    let _: String = shmap.get(&key).unwrap().unwrap();
+    fn deref(&self) -> &Path {
+        &self.0
+    }
 }
 
-#[test]
-fn simple_test() {
-    init_logger();
+impl AsRef<Path> for TmpDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
 
-    let shmap = Shmap::new();
-    let key = rand_string(31);
-    let value = rand_string(50);
+impl Drop for TmpDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
 
-    shmap.insert(&key, value.clone()).unwrap();
-    let ret_value: String = shmap.get(&key).unwrap().unwrap();
-    assert_eq!(ret_value, value);
-    shmap.remove(&key).unwrap();
+/// Create an empty directory to store items, isolated from other tests.
+pub fn tmp_dir() -> TmpDir {
+    let dir = std::env::temp_dir().join(format!("shmap-test-{}", rand_string(10)));
+    std::fs::create_dir(&dir).unwrap();
+    TmpDir(dir)
+}
+
+fn read_from_shm(sanitized_key: &str) -> Vec<u8> {
+    let fd = shm::open_read(Path::new(SHM_DIR), sanitized_key).unwrap();
+    // SAFETY: Mmap call is unsafe
+    let mmap = unsafe { Mmap::map(fd) }.unwrap();
+    mmap.to_vec()
 }
 
 #[test]
-fn test_different_size() {
+fn test_decode_error_context() {
     init_logger();
 
-    let key = rand_string(32);
-
-    let shmap = Shmap::new();
-    let value = rand_string(50);
-    shmap.insert(&key, value.clone()).unwrap();
-    let ret_value: String = shmap.get(&key).unwrap().unwrap();
-    assert_eq!(ret_value, value);
-
     let shmap = Shmap::new();
-    let value = rand_string(100);
-    shmap.insert(&key, value.clone()).unwrap();
-    let ret_value: String = shmap.get(&key).unwrap().unwrap();
-    assert_eq!(ret_value, value);
+    let key = rand_string(52);
 
-    let shmap = Shmap::new();
-    let value = rand_string(20);
-    shmap.insert(&key, value.clone()).unwrap();
-    let ret_value: String = shmap.get(&key).unwrap().unwrap();
-    assert_eq!(ret_value, value);
+    shmap.insert(&key, 2u8).unwrap();
+    let err = shmap.get::<bool>(&key).unwrap_err();
+    assert!(matches!(err, ShmapError::Serialization { .. }));
+    let message = err.to_string();
+    assert!(message.contains(&sanitize_key(None, &key)));
+    assert!(message.contains("1 bytes"));
 
     shmap.remove(&key).unwrap();
 }
@@ -115,8 +193,8 @@ fn test_encrypted() {
     let ret_value_2: String = shmap.get(&key_2).unwrap().unwrap();
     assert_eq!(ret_value_2, value);
     assert_eq!(ret_value_1, ret_value_2);
-    let raw_1 = read_from_shm(&sanitize_key(&key));
-    let raw_2 = read_from_shm(&sanitize_key(&key_2));
+    let raw_1 = read_from_shm(&sanitize_key(None, &key));
+    let raw_2 = read_from_shm(&sanitize_key(None, &key_2));
     assert_ne!(raw_1, raw_2);
 
     shmap_enc.remove(&key).unwrap();
@@ -124,74 +202,59 @@ fn test_encrypted() {
 }
 
 #[test]
-fn test_bad_key() {
+fn test_insert_ref() {
     init_logger();
 
-    let key = rand_string(35);
-    let value = rand_string(50);
+    let shmap = Shmap::new();
+    let key = rand_string(54);
+    let value = vec![rand_string(50), rand_string(50)];
 
-    let mut secret: Vec<u8> = (0..32).collect();
-    secret.shuffle(&mut thread_rng());
-    let shmap = Shmap::new_with_encryption(&secret.try_into().unwrap());
-    shmap.insert(&key, value.clone()).unwrap();
-    let ret_value: String = shmap.get(&key).unwrap().unwrap();
+    shmap.insert_ref(&key, &value).unwrap();
+    let ret_value: Vec<String> = shmap.get(&key).unwrap().unwrap();
+    assert_eq!(ret_value, value);
+
+    // Unsized values can be inserted too, and `value` is still usable
+    shmap
+        .insert_ref_with_ttl(&key, value.as_slice(), Duration::from_secs(10))
+        .unwrap();
+    let ret_value: Vec<String> = shmap.get(&key).unwrap().unwrap();
     assert_eq!(ret_value, value);
 
-    let mut secret: Vec<u8> = (0..32).collect();
-    secret.shuffle(&mut thread_rng());
-    let shmap = Shmap::new_with_encryption(&secret.try_into().unwrap());
-    assert!(
-        shmap.get::<String>(&key).is_err(),
-        "It should not have been possible to decrypt here, with a different key"
-    );
     shmap.remove(&key).unwrap();
 }
 
 #[test]
-fn test_set_and_get() {
+fn test_remove_full() {
     init_logger();
 
     let shmap = Shmap::new();
-    let key = rand_string(36);
+    let key = rand_string(51);
     let value = rand_string(50);
 
-    shmap.insert(&key, value.clone()).unwrap();
-
-    let ret_value: String = shmap.get(&key).unwrap().unwrap();
-    assert_eq!(ret_value, value);
-
-    let ret_value: String = shmap.get(&key).unwrap().unwrap();
-    assert_eq!(ret_value, value);
-
-    shmap.remove(&key).unwrap();
-
-    let key = rand_string(37);
-    let value = vec!["Test".to_string(), "Vec".to_string()];
-
-    shmap.insert(&key, value.clone()).unwrap();
-
-    let ret_value: Vec<String> = shmap.get(&key).unwrap().unwrap();
-    assert_eq!(ret_value, value);
-
-    let ret_value: Vec<String> = shmap.get(&key).unwrap().unwrap();
-    assert_eq!(ret_value, value);
+    shmap.insert(&key, value).unwrap();
+    let lock_path = lock_path(Path::new(SHM_DIR), &sanitize_key(None, &key));
+    assert!(lock_path.exists());
 
-    shmap.remove(&key).unwrap();
+    shmap.remove_full(&key).unwrap();
+    assert!(!lock_path.exists());
 }
 
 #[test]
-fn test_set_and_get_big() {
+fn test_insert_scoped() {
     init_logger();
 
     let shmap = Shmap::new();
-    let key = rand_string(38);
-    let value = rand_string(5 * 1024 * 1024);
-
-    shmap.insert(&key, value.clone()).unwrap();
+    let key = rand_string(55);
+    let value = rand_string(50);
 
+    let guard = shmap.insert_scoped(&key, value.clone()).unwrap();
+    assert_eq!(guard.key(), key);
     let ret_value: String = shmap.get(&key).unwrap().unwrap();
     assert_eq!(ret_value, value);
+    drop(guard);
+    assert!(shmap.get::<String>(&key).unwrap().is_none());
 
+    shmap.insert_scoped(&key, value.clone()).unwrap().forget();
     let ret_value: String = shmap.get(&key).unwrap().unwrap();
     assert_eq!(ret_value, value);
 
@@ -199,46 +262,52 @@ fn test_set_and_get_big() {
 }
 
 #[test]
-fn test_remove() {
+fn test_peek_expired() {
     init_logger();
 
     let shmap = Shmap::new();
-    let key = rand_string(39);
+    let key = rand_string(47);
     let value = rand_string(50);
 
-    shmap.insert(&key, value).unwrap();
-
-    shmap.remove(&key).unwrap();
-}
+    shmap
+        .insert_with_ttl(&key, value.clone(), Duration::from_millis(200))
+        .unwrap();
 
-#[test]
-fn test_remove_not_found() {
-    init_logger();
+    // Prevent `clean` from other tests to remove the expired item while we peek at it
+    let guard = ReadGuard::lock(&lock_path(Path::new(SHM_DIR), &sanitize_key(None, &key))).unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+    let ret_value: String = shmap.peek(&key).unwrap().unwrap();
+    assert_eq!(ret_value, value);
+    drop(guard);
 
-    let shmap = Shmap::new();
-    let key = rand_string(40);
-    shmap.remove(&key).unwrap();
+    assert!(shmap.get::<String>(&key).unwrap().is_none());
 }
 
 #[test]
-#[should_panic(expected = "Option::unwrap()")]
-fn test_expiration() {
+fn test_wait_for() {
     init_logger();
 
     let shmap = Shmap::new();
-    let key = rand_string(41);
+    let key = rand_string(53);
     let value = rand_string(50);
 
-    shmap
-        .insert_with_ttl(&key, value.clone(), Duration::from_millis(200))
-        .unwrap();
-    shmap.clean().unwrap();
-    let ret_value: String = shmap.get(&key).unwrap().unwrap();
+    let shmap_clone = shmap.clone();
+    let key_clone = key.clone();
+    let value_clone = value.clone();
+    let producer = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(200));
+        shmap_clone.insert(&key_clone, value_clone).unwrap();
+    });
+
+    // No deadline
+    let ret_value: String = shmap.wait_for(&key, Duration::MAX).unwrap().unwrap();
     assert_eq!(ret_value, value);
+    producer.join().unwrap();
+    shmap.remove(&key).unwrap();
 
-    std::thread::sleep(Duration::from_millis(300));
-
-    let _: String = shmap.get(&key).unwrap().unwrap();
+    // Timeout
+    let ret_value: Option<String> = shmap.wait_for(&key, Duration::from_millis(100)).unwrap();
+    assert!(ret_value.is_none());
 }
 
 #[test]
@@ -254,7 +323,7 @@ fn test_many_fd() {
             rlim_cur: 42,
             rlim_max: 42,
         };
-        if libc::setrlimit(libc::RLIMIT_NOFILE, &rlim) != 0 {
+        if libc::setrlimit(libc::RLIMIT_NOFILE, &raw const rlim) != 0 {
             let err = std::io::Error::last_os_error();
             panic!("raise_fd_limit: error calling setrlimit: {err}");
         }
@@ -327,6 +396,40 @@ fn test_get_concurrency() {
     shmap.remove(&key_clone).unwrap();
 }
 
+// test concurrency between remove and get: value and metadata are removed together
+#[test]
+fn test_remove_get_concurrency() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    let value = rand_string(50);
+    let sanitized_key = sanitize_key(None, &key);
+
+    let shmap_clone = shmap.clone();
+    let (key_clone, value_clone) = (key.clone(), value.clone());
+    let writer = std::thread::spawn(move || {
+        for _ in 0..1024 {
+            shmap_clone.insert(&key_clone, &value_clone).unwrap();
+            shmap_clone.remove(&key_clone).unwrap();
+        }
+    });
+
+    while !writer.is_finished() {
+        let got: Option<String> = shmap.get(&key).unwrap();
+        assert!(got.is_none_or(|got| got == value));
+
+        let _guard = ReadGuard::lock(&lock_path(&dir, &sanitized_key)).unwrap();
+        assert_eq!(
+            dir.join(&sanitized_key).exists(),
+            dir.join(shmap.shm_metadata_filename(&key).unwrap())
+                .exists()
+        );
+    }
+    writer.join().unwrap();
+}
+
 // test concurrency between set and get
 #[test]
 fn test_get_set_concurrency() {
@@ -375,31 +478,2302 @@ fn test_metadatas_concurrency() {
     for _i in 0..10 {
         handles.push(std::thread::spawn(task.clone()));
     }
-    handles.into_iter().for_each(|t| t.join().unwrap());
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+// test key listing when the directory does not exist
+#[test]
+fn test_list_keys_missing_dir() {
+    init_logger();
+
+    let dir = std::env::temp_dir().join(format!("shmap-test-{}", rand_string(10)));
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+
+    assert!(shmap.keys().unwrap().is_empty());
+    assert_eq!(shmap.scan(0, 10).unwrap(), (0, Vec::new()));
+}
+
+// test sorted key listing
+#[test]
+fn test_list_keys_sorted() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+
+    let mut keys = (0..10).map(|_| rand_string(10)).collect::<Vec<_>>();
+    for key in &keys {
+        shmap.insert(key, rand_string(10)).unwrap();
+    }
+
+    keys.sort();
+    assert_eq!(shmap.keys_sorted().unwrap(), keys);
+
+    std::fs::remove_dir_all(dir).unwrap();
 }
 
-// test key listing
+// test incremental key listing
 #[test]
-fn test_list_keys() {
-    const NUM: usize = 5;
+fn test_scan() {
+    const NUM: usize = 50;
     init_logger();
 
     let shmap = Shmap::new();
 
-    let keys = (0..NUM).map(rand_string).collect::<HashSet<_>>();
+    let keys = (0..NUM).map(|_| rand_string(46)).collect::<HashSet<_>>();
     for key in &keys {
-        let value = rand_string(50);
-        shmap.insert(key, value).unwrap();
+        shmap.insert(key, rand_string(10)).unwrap();
     }
 
-    // Other tests may run in parallel. Ensure that at least NUM keys are present.
-    assert!(shmap.keys().unwrap().len() >= NUM);
+    let mut scanned_keys = HashSet::new();
+    let mut cursor = 0;
+    loop {
+        let (next_cursor, batch) = shmap.scan(cursor, 7).unwrap();
+        assert!(batch.len() <= 7);
+        scanned_keys.extend(batch);
+        if next_cursor == 0 {
+            break;
+        }
+        cursor = next_cursor;
+    }
 
-    // At least all inserted keys must be present.
-    let current_keys = shmap.keys().unwrap().into_iter().collect();
-    assert!(keys.is_subset(&current_keys));
+    // Other tests may run in parallel. At least all inserted keys must be present.
+    assert!(keys.is_subset(&scanned_keys));
 
     for key in &keys {
         shmap.remove(key).unwrap();
     }
 }
+
+#[test]
+fn test_scan_zero_count() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let keys = (0..3).map(|_| rand_string(20)).collect::<HashSet<_>>();
+    for key in &keys {
+        shmap.insert(key, rand_string(10)).unwrap();
+    }
+
+    // A count of 0 still moves the cursor forward
+    let mut scanned_keys = HashSet::new();
+    let mut cursor = 0;
+    for _ in 0..=keys.len() {
+        let (next_cursor, batch) = shmap.scan(cursor, 0).unwrap();
+        assert!(batch.len() <= 1);
+        scanned_keys.extend(batch);
+        if next_cursor == 0 {
+            break;
+        }
+        assert!(next_cursor > cursor);
+        cursor = next_cursor;
+    }
+    assert_eq!(scanned_keys, keys);
+}
+
+// test that an item either fits the file name limit with all its files, or is not inserted at all
+#[test]
+fn test_key_name_too_long() {
+    init_logger();
+
+    // "shmap." + namespace + "." + 56 hex chars + ".metadata"
+    let max_namespace_len = shm::NAME_MAX - 6 - 1 - 56 - 9;
+    let key = rand_string(49);
+    let value = rand_string(50);
+
+    let namespace = rand_string(max_namespace_len);
+    let shmap = ShmapBuilder::new()
+        .with_namespace(&namespace)
+        .build()
+        .unwrap();
+    shmap.insert(&key, value.clone()).unwrap();
+    let ret_value: String = shmap.get(&key).unwrap().unwrap();
+    assert_eq!(ret_value, value);
+    shmap.remove(&key).unwrap();
+
+    let namespace = rand_string(max_namespace_len + 1);
+    let shmap = ShmapBuilder::new()
+        .with_namespace(&namespace)
+        .build()
+        .unwrap();
+    assert!(matches!(
+        shmap.insert(&key, value),
+        Err(ShmapError::KeyNameTooLong)
+    ));
+    assert!(matches!(
+        shmap.get::<String>(&key),
+        Err(ShmapError::KeyNameTooLong)
+    ));
+    // Neither the value nor the metadata must have been created
+    let orphan = std::fs::read_dir(SHM_DIR)
+        .unwrap()
+        .flatten()
+        .any(|dir_entry| {
+            dir_entry
+                .file_name()
+                .to_string_lossy()
+                .contains(namespace.as_str())
+        });
+    assert!(!orphan);
+}
+
+// test dumping raw entries together with their metadata
+#[test]
+fn test_dump_entries() {
+    init_logger();
+
+    let shmap = Shmap::new();
+    let key_1 = rand_string(50);
+    let key_2 = rand_string(50);
+    let missing_key = rand_string(50);
+    let value = rand_string(50);
+
+    shmap.insert(&key_1, value.clone()).unwrap();
+    shmap
+        .insert_with_ttl(&key_2, value.clone(), Duration::from_mins(1))
+        .unwrap();
+
+    let entries = shmap.dump_entries(&[&key_1, &missing_key, &key_2]).unwrap();
+    assert_eq!(entries.len(), 2);
+    for (key, bytes, metadata) in &entries {
+        assert_eq!(key, &metadata.key);
+        let (ret_value, _): (String, usize) =
+            bincode::serde::decode_from_slice(bytes, bincode::config::standard()).unwrap();
+        assert_eq!(ret_value, value);
+        assert!(!metadata.encrypted);
+    }
+    assert_eq!(entries[0].0, key_1);
+    assert!(entries[0].2.expiration.is_none());
+    assert_eq!(entries[1].0, key_2);
+    assert!(entries[1].2.expiration.is_some());
+
+    shmap.remove(&key_1).unwrap();
+    shmap.remove(&key_2).unwrap();
+}
+
+#[test]
+fn test_insert_encrypted_with_key() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let encryption_key = [7u8; 32];
+    let plain_key = rand_string(20);
+    let secret_key = rand_string(20);
+    let secret = rand_string(50);
+
+    shmap.insert(&plain_key, 1).unwrap();
+    shmap
+        .insert_encrypted_with_key(&secret_key, &secret, &encryption_key)
+        .unwrap();
+    assert_eq!(shmap.get::<i32>(&plain_key).unwrap(), Some(1));
+    assert_eq!(
+        shmap
+            .get_encrypted_with_key::<String>(&secret_key, &encryption_key)
+            .unwrap(),
+        Some(secret.clone())
+    );
+
+    // Only the value is encrypted, the item is listed like the others
+    let stored = std::fs::read(dir.join(sanitize_key(None, &secret_key))).unwrap();
+    assert!(!stored
+        .windows(secret.len())
+        .any(|window| window == secret.as_bytes()));
+    assert!(shmap.metadata(&secret_key).unwrap().unwrap().encrypted);
+    let mut keys = shmap.keys().unwrap();
+    keys.sort();
+    let mut expected = vec![plain_key.clone(), secret_key.clone()];
+    expected.sort();
+    assert_eq!(keys, expected);
+
+    assert!(matches!(
+        shmap.get::<String>(&secret_key),
+        Err(ShmapError::Encrypted)
+    ));
+    assert!(matches!(
+        shmap.get_encrypted_with_key::<String>(&secret_key, &[8u8; 32]),
+        Err(ShmapError::AesGcmError(_))
+    ));
+    assert!(matches!(
+        shmap.get_encrypted_with_key::<i32>(&plain_key, &encryption_key),
+        Err(ShmapError::NotEncrypted)
+    ));
+}
+
+#[test]
+fn test_reencrypt() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let (old_key, new_key) = ([1u8; 32], [2u8; 32]);
+    let old = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_encryption(&old_key)
+        .with_chunk_size(16)
+        .build()
+        .unwrap();
+    let entries = (0..5)
+        .map(|_| (rand_string(20), rand_string(50)))
+        .collect::<Vec<_>>();
+    for (key, value) in &entries {
+        old.insert(key, value).unwrap();
+    }
+
+    let report = old.reencrypt(&old_key, &new_key).unwrap();
+    assert_eq!((report.migrated, report.skipped), (5, 0));
+    assert!(report.errors.is_empty());
+    let new = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_encryption(&new_key)
+        .build()
+        .unwrap();
+    for (key, value) in &entries {
+        assert_eq!(new.get::<String>(key).unwrap().as_ref(), Some(value));
+        assert!(old.get::<String>(key).is_err());
+    }
+    assert_eq!(new.keys().unwrap().len(), 5);
+
+    // Resumable: migrated items are skipped
+    let report = new.reencrypt(&old_key, &new_key).unwrap();
+    assert_eq!((report.migrated, report.skipped), (0, 5));
+
+    // Items encrypted with their own key in a plaintext store
+    let dir = tmp_dir();
+    let plain = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let (plain_key, secret_key) = (rand_string(20), rand_string(20));
+    plain.insert(&plain_key, 1).unwrap();
+    plain
+        .insert_encrypted_with_key(&secret_key, 2, &old_key)
+        .unwrap();
+    let report = plain.reencrypt(&old_key, &new_key).unwrap();
+    assert_eq!((report.migrated, report.skipped), (1, 1));
+    assert_eq!(plain.get::<i32>(&plain_key).unwrap(), Some(1));
+    assert_eq!(
+        plain
+            .get_encrypted_with_key::<i32>(&secret_key, &new_key)
+            .unwrap(),
+        Some(2)
+    );
+    assert!(plain
+        .get_encrypted_with_key::<i32>(&secret_key, &old_key)
+        .is_err());
+
+    // Failures are collected
+    plain
+        .insert_encrypted_with_key(&rand_string(20), 3, &[3u8; 32])
+        .unwrap();
+    let report = plain.reencrypt(&old_key, &new_key).unwrap();
+    assert_eq!((report.migrated, report.skipped), (0, 2));
+    assert!(matches!(
+        report.errors.as_slice(),
+        [(_, ShmapError::AesGcmError(_))]
+    ));
+}
+
+#[test]
+fn test_aad() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_encryption(&[7u8; 32])
+        .with_dir(&dir)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let key_2 = rand_string(20);
+    let value = rand_string(50);
+
+    // A value moved to another key fails to decrypt
+    shmap.insert(&key, value.clone()).unwrap();
+    shmap.insert(&key_2, rand_string(50)).unwrap();
+    std::fs::copy(
+        dir.join(sanitize_key(None, &key)),
+        dir.join(sanitize_key(None, &key_2)),
+    )
+    .unwrap();
+    assert_eq!(shmap.get::<String>(&key).unwrap(), Some(value.clone()));
+    assert!(matches!(
+        shmap.get::<String>(&key_2),
+        Err(ShmapError::AesGcmError(_))
+    ));
+
+    // User-supplied associated data
+    shmap
+        .insert_with_aad(&key, value.clone(), b"tenant")
+        .unwrap();
+    assert!(shmap.metadata(&key).unwrap().unwrap().aad);
+    assert_eq!(
+        shmap.get_with_aad::<String>(&key, b"tenant").unwrap(),
+        Some(value)
+    );
+    assert!(matches!(
+        shmap.get_with_aad::<String>(&key, b"other"),
+        Err(ShmapError::AesGcmError(_))
+    ));
+    assert!(matches!(
+        shmap.get::<String>(&key),
+        Err(ShmapError::AadMismatch)
+    ));
+}
+
+#[test]
+fn test_insert_if_changed() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    let value = rand_string(50);
+    let path = dir.join(sanitize_key(None, &key));
+
+    assert!(shmap.insert_if_changed(&key, value.clone()).unwrap());
+    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+    std::thread::sleep(Duration::from_millis(20));
+    assert!(!shmap.insert_if_changed(&key, value.clone()).unwrap());
+    assert_eq!(
+        std::fs::metadata(&path).unwrap().modified().unwrap(),
+        modified
+    );
+    assert_eq!(shmap.get::<String>(&key).unwrap(), Some(value));
+
+    let value = rand_string(50);
+    assert!(shmap.insert_if_changed(&key, value.clone()).unwrap());
+    assert_eq!(shmap.get::<String>(&key).unwrap(), Some(value));
+}
+
+#[test]
+fn test_insert_if_changed_concurrency() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    let stop = Arc::new(AtomicBool::new(false));
+
+    // Expired items are removed by readers and cleaners meanwhile
+    let handles = (0..4)
+        .map(|_| {
+            let shmap = shmap.clone();
+            let key = key.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    shmap.get::<u32>(&key).unwrap();
+                    shmap.compact().unwrap();
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for i in 1..500 {
+        shmap
+            .insert_with_ttl(&key, 0u32, Duration::from_millis(1))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(shmap.insert_if_changed(&key, i).unwrap());
+        assert_eq!(shmap.get::<u32>(&key).unwrap(), Some(i));
+    }
+    stop.store(true, Ordering::Relaxed);
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn test_get_into() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+
+    let mut out = Vec::<String>::new();
+    assert!(!shmap.get_into(&key, &mut out).unwrap());
+    assert!(out.is_empty());
+
+    for i in 0..10 {
+        let value: Vec<String> = (0..i).map(|_| rand_string(10)).collect();
+        shmap.insert(&key, &value).unwrap();
+        assert!(shmap.get_into(&key, &mut out).unwrap());
+        assert_eq!(out, value);
+    }
+
+    shmap.remove(&key).unwrap();
+    assert!(!shmap.get_into(&key, &mut out).unwrap());
+    assert_eq!(out.len(), 9);
+}
+
+#[test]
+fn test_compact() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let live_key = rand_string(20);
+    shmap.insert(&live_key, rand_string(50)).unwrap();
+
+    let orphan_item = sanitize_key(None, &rand_string(20));
+    let orphan_metadata = format!("{}.metadata", sanitize_key(None, &rand_string(20)));
+    let orphan_lock = format!("{}.lock", sanitize_key(None, &rand_string(20)));
+    for orphan in [&orphan_item, &orphan_metadata, &orphan_lock] {
+        std::fs::write(dir.join(orphan), b"orphan").unwrap();
+    }
+
+    // Fresh orphans are kept by clean
+    shmap.clean().unwrap();
+    for orphan in [&orphan_item, &orphan_metadata, &orphan_lock] {
+        assert!(dir.join(orphan).exists());
+    }
+
+    let report = shmap.compact().unwrap();
+    assert_eq!(
+        report,
+        CleanReport {
+            expired: 0,
+            orphan_items: 1,
+            orphan_metadata: 1,
+            orphan_locks: 1,
+            ephemeral: 0,
+            remaining: false,
+        }
+    );
+    for orphan in [&orphan_item, &orphan_metadata, &orphan_lock] {
+        assert!(!dir.join(orphan).exists());
+    }
+    assert_eq!(shmap.keys().unwrap(), vec![live_key]);
+}
+
+#[test]
+fn test_verify() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_encryption(&[7u8; 32])
+        .build()
+        .unwrap();
+    let live_key = rand_string(20);
+    shmap.insert(&live_key, rand_string(50)).unwrap();
+    let expired_key = rand_string(20);
+    shmap
+        .insert_with_ttl(&expired_key, 1, Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    // Value which can not be decrypted, and metadata which can not be decoded
+    let corrupt_value = sanitize_key(None, &rand_string(20));
+    let corrupt_metadata = sanitize_key(None, &rand_string(20));
+    shmap.insert(&corrupt_value, 2).unwrap();
+    shmap.insert(&corrupt_metadata, 3).unwrap();
+    let corrupt_value = sanitize_key(None, &corrupt_value);
+    let corrupt_metadata = sanitize_key(None, &corrupt_metadata);
+    std::fs::write(dir.join(&corrupt_value), [0u8; 64]).unwrap();
+    std::fs::write(dir.join(format!("{corrupt_metadata}.metadata")), b"corrupt").unwrap();
+
+    let orphan_item = sanitize_key(None, &rand_string(20));
+    let orphan_metadata = format!("{}.metadata", sanitize_key(None, &rand_string(20)));
+    for orphan in [&orphan_item, &orphan_metadata] {
+        std::fs::write(dir.join(orphan), b"orphan").unwrap();
+    }
+
+    let expected = VerifyReport {
+        ok: 1,
+        expired: 1,
+        corrupt: 2,
+        orphaned: 2,
+    };
+    assert_eq!(shmap.verify(false).unwrap(), expected);
+    assert!(dir.join(&corrupt_value).exists());
+
+    assert_eq!(shmap.verify(true).unwrap(), expected);
+    for removed in [
+        &corrupt_value,
+        &corrupt_metadata,
+        &orphan_item,
+        &orphan_metadata,
+    ] {
+        assert!(!dir.join(removed).exists());
+    }
+    assert!(!dir
+        .join(format!("{}.metadata", sanitize_key(None, &expired_key)))
+        .exists());
+    assert_eq!(
+        shmap.verify(false).unwrap(),
+        VerifyReport {
+            ok: 1,
+            ..VerifyReport::default()
+        }
+    );
+    assert_eq!(shmap.keys().unwrap(), vec![live_key]);
+}
+
+#[test]
+fn test_age() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+
+    assert_eq!(shmap.age(&key).unwrap(), None);
+
+    shmap.insert(&key, rand_string(50)).unwrap();
+    std::thread::sleep(Duration::from_millis(100));
+    let age = shmap.age(&key).unwrap().unwrap();
+    assert!(age >= Duration::from_millis(100));
+    assert!(age < Duration::from_secs(10));
+
+    // Rewriting resets the age
+    shmap.insert(&key, rand_string(50)).unwrap();
+    assert!(shmap.age(&key).unwrap().unwrap() < age);
+}
+
+#[test]
+fn test_get_result() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    let value = rand_string(50);
+
+    assert!(matches!(
+        shmap.get_result::<String>(&key).unwrap(),
+        GetResult::NotFound
+    ));
+
+    shmap.insert(&key, value.clone()).unwrap();
+    assert!(matches!(
+        shmap.get_result::<String>(&key).unwrap(),
+        GetResult::Found(found) if found == value
+    ));
+
+    std::fs::write(dir.join(sanitize_key(None, &key)), [0xff; 3]).unwrap();
+    assert!(matches!(
+        shmap.get_result::<String>(&key).unwrap(),
+        GetResult::Corrupt(ShmapError::Serialization { .. })
+    ));
+    // Still an error for the strict getter
+    assert!(shmap.get::<String>(&key).is_err());
+}
+
+#[test]
+fn test_get_status() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    let value = rand_string(50);
+
+    assert_eq!(
+        shmap.get_status::<String>(&key).unwrap(),
+        GetStatus::Missing
+    );
+
+    shmap.insert(&key, value.clone()).unwrap();
+    assert_eq!(
+        shmap.get_status::<String>(&key).unwrap(),
+        GetStatus::Present(value.clone())
+    );
+
+    shmap
+        .insert_with_ttl(&key, value, Duration::from_millis(10))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(
+        shmap.get_status::<String>(&key).unwrap(),
+        GetStatus::Expired
+    );
+    // Removed by the previous call
+    assert_eq!(
+        shmap.get_status::<String>(&key).unwrap(),
+        GetStatus::Missing
+    );
+}
+
+#[test]
+fn test_clean_limited() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    for _ in 0..30 {
+        shmap
+            .insert_with_ttl(&rand_string(20), 1, Duration::from_millis(1))
+            .unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(10));
+
+    let report = shmap.clean_limited(10, Duration::ZERO).unwrap();
+    assert_eq!(report.expired, 0);
+    assert!(report.remaining);
+
+    let mut expired = 0;
+    let mut calls = 0;
+    loop {
+        let report = shmap.clean_limited(10, Duration::MAX).unwrap();
+        assert!(report.expired <= 10);
+        expired += report.expired;
+        calls += 1;
+        if !report.remaining {
+            break;
+        }
+        assert!(expired < 30);
+    }
+    assert_eq!(expired, 30);
+    // A value, a metadata and a lock file per item
+    assert_eq!(calls, 9);
+    assert!(shmap.keys().unwrap().is_empty());
+
+    // Then starts over, the locks being left their grace period
+    assert_eq!(
+        shmap.clean_limited(usize::MAX, Duration::MAX).unwrap(),
+        CleanReport::default()
+    );
+}
+
+#[test]
+fn test_reap_dead_owners() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_creator_pid(true)
+        .build()
+        .unwrap();
+    let set_creator_pid = |key: &str, pid: u32| {
+        let mut metadata = shmap.metadata(key).unwrap().unwrap();
+        metadata.creator_pid = Some(pid);
+        let metadata_filename = shmap.shm_metadata_filename(key).unwrap();
+        let bytes = encode_metadata(&metadata_filename, &metadata).unwrap();
+        std::fs::write(dir.join(metadata_filename), bytes).unwrap();
+    };
+
+    shmap.insert("alive", 1).unwrap();
+    shmap.insert("dead", 2).unwrap();
+    shmap.insert_owned("owned_alive", 3).unwrap();
+    shmap.insert_owned("owned_dead", 4).unwrap();
+    ShmapBuilder::new()
+        .with_dir(&dir)
+        .build()
+        .unwrap()
+        .insert("untracked", 5)
+        .unwrap();
+    assert_eq!(
+        shmap.metadata("alive").unwrap().unwrap().creator_pid,
+        Some(std::process::id())
+    );
+    assert!(shmap
+        .metadata("untracked")
+        .unwrap()
+        .unwrap()
+        .creator_pid
+        .is_none());
+    // Beyond the maximum pid of linux
+    let dead_pid = i32::MAX.unsigned_abs();
+    set_creator_pid("dead", dead_pid);
+    set_creator_pid("owned_dead", dead_pid);
+
+    assert_eq!(shmap.reap_dead_owned().unwrap(), 1);
+    assert_eq!(shmap.get::<i32>("owned_dead").unwrap(), None);
+    assert_eq!(shmap.get::<i32>("dead").unwrap(), Some(2));
+
+    assert_eq!(shmap.reap_dead_owners().unwrap(), 1);
+    assert_eq!(shmap.get::<i32>("dead").unwrap(), None);
+    assert_eq!(
+        shmap.keys_sorted().unwrap(),
+        ["alive", "owned_alive", "untracked"]
+    );
+}
+
+#[test]
+fn test_clean_prefixes() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let mut expected = HashMap::new();
+    for namespace in ["", "first", "second"] {
+        let mut builder = ShmapBuilder::new().with_dir(&dir);
+        if !namespace.is_empty() {
+            builder = builder.with_namespace(namespace);
+        }
+        let shmap = builder.build().unwrap();
+        let mut keys = (0..3).map(|_| rand_string(20)).collect::<Vec<_>>();
+        for key in &keys {
+            shmap.insert(key, 1).unwrap();
+        }
+        shmap
+            .insert_with_ttl(&rand_string(20), 1, Duration::from_millis(1))
+            .unwrap();
+        keys.sort();
+        expected.insert(namespace.to_owned(), keys);
+    }
+    // Not asked for
+    ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_namespace("other")
+        .build()
+        .unwrap()
+        .insert(&rand_string(20), 1)
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    expected.insert("empty".to_owned(), Vec::new());
+
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let mut buckets = shmap
+        .clean_prefixes(&["", "first", "second", "empty"])
+        .unwrap();
+    for keys in buckets.values_mut() {
+        keys.sort();
+    }
+    assert_eq!(buckets, expected);
+    // Expired items are removed once, for all the namespaces: 10 live items left, with metadata
+    let files = std::fs::read_dir(&dir)
+        .unwrap()
+        .flatten()
+        .filter(|entry| !entry.file_name().to_string_lossy().ends_with(".lock"))
+        .count();
+    assert_eq!(files, 10 * 2);
+}
+
+#[test]
+fn test_clean_non_utf8_filename() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    shmap.insert(&key, 1).unwrap();
+
+    // Item-like name, which would turn into "shmap.\u{FFFD}" if converted lossily
+    let path = dir.join(OsStr::from_bytes(b"shmap.\xff"));
+    std::fs::write(&path, b"foreign").unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - Duration::from_secs(10))
+        .unwrap();
+
+    assert_eq!(shmap.clean().unwrap(), vec![key]);
+    assert!(path.exists());
+}
+
+#[test]
+fn test_clean_concurrency() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let live: Vec<(String, String)> = (0..10)
+        .map(|_| (rand_string(20), rand_string(50)))
+        .collect();
+    for (key, value) in &live {
+        shmap.insert(key, value.clone()).unwrap();
+    }
+
+    let mut handles = Vec::new();
+    for _ in 0..4 {
+        let shmap = shmap.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..200 {
+                let key = rand_string(20);
+                let value = rand_string(i);
+                // Some items expire while being cleaned, and are inserted again
+                shmap
+                    .insert_with_ttl(&key, value.clone(), Duration::from_millis(1))
+                    .unwrap();
+                shmap
+                    .insert_with_ttl(&key, value.clone(), Duration::from_mins(1))
+                    .unwrap();
+                assert_eq!(shmap.get::<String>(&key).unwrap(), Some(value));
+                shmap.remove(&key).unwrap();
+            }
+        }));
+    }
+    for _ in 0..2 {
+        let shmap = shmap.clone();
+        handles.push(std::thread::spawn(move || {
+            for _ in 0..50 {
+                shmap.clean().unwrap();
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    for (key, value) in &live {
+        assert_eq!(shmap.get::<String>(key).unwrap().as_ref(), Some(value));
+    }
+}
+
+#[test]
+fn test_chunked() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_encryption(&[5u8; 32])
+        .with_chunk_size(100)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let sanitized_key = sanitize_key(None, &key);
+    let value: Vec<u8> = (0..1000).map(|_| rand::random()).collect();
+
+    shmap
+        .insert_raw_with_ttl(&key, &value, Duration::from_mins(1))
+        .unwrap();
+    let metadata = shmap.metadata(&key).unwrap().unwrap();
+    assert_eq!(metadata.parts, 10);
+    assert_eq!(metadata.len, 1000);
+    assert!(dir.join(format!("{sanitized_key}.part9")).exists());
+    assert_eq!(shmap.get_raw(&key).unwrap(), Some(value));
+    assert_eq!(shmap.keys().unwrap(), vec![key.clone()]);
+
+    // Serialized values are split too, and parts of a previous value are removed
+    let value = rand_string(250);
+    shmap.insert(&key, value.clone()).unwrap();
+    assert_eq!(shmap.metadata(&key).unwrap().unwrap().parts, 3);
+    assert_eq!(shmap.get::<String>(&key).unwrap(), Some(value));
+    assert!(!dir.join(format!("{sanitized_key}.part3")).exists());
+
+    shmap.insert(&key, 42).unwrap();
+    assert_eq!(shmap.metadata(&key).unwrap().unwrap().parts, 0);
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), Some(42));
+    assert!(!dir.join(format!("{sanitized_key}.part1")).exists());
+
+    shmap.insert(&key, rand_string(250)).unwrap();
+    shmap.remove(&key).unwrap();
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1); // the lock file
+}
+
+#[test]
+fn test_chunked_forged_len() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_chunk_size(16)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let sanitized_key = sanitize_key(None, &key);
+    shmap.insert(&key, rand_string(100)).unwrap();
+
+    // A length far beyond the parts is reported as corrupted, without reserving it
+    let mut metadata = shmap.metadata(&key).unwrap().unwrap();
+    assert!(metadata.parts > 1);
+    metadata.len = 1 << 60;
+    let metadata_key = format!("{sanitized_key}.metadata");
+    std::fs::write(
+        dir.join(&metadata_key),
+        encode_metadata(&metadata_key, &metadata).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(shmap.get::<String>(&key).unwrap(), None);
+}
+
+#[test]
+fn test_transaction() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let mut keys: Vec<String> = (0..3).map(|_| rand_string(20)).collect();
+    keys.sort_by_key(|key| sanitize_key(None, key));
+    for key in &keys {
+        shmap.insert(key, format!("{key}-original")).unwrap();
+    }
+
+    // Make writing the shadow of the last item fail, once the others are written
+    let blocker = dir.join(format!("{}.shadow", sanitize_key(None, &keys[2])));
+    std::fs::create_dir(&blocker).unwrap();
+    let entries: Vec<(&str, String)> = keys
+        .iter()
+        .map(|key| (key.as_str(), format!("{key}-updated")))
+        .collect();
+    assert!(shmap.transaction(&entries).is_err());
+    for key in &keys {
+        assert_eq!(
+            shmap.get::<String>(key).unwrap(),
+            Some(format!("{key}-original"))
+        );
+    }
+    std::fs::remove_dir(&blocker).unwrap();
+    // No shadow left behind
+    assert!(std::fs::read_dir(&dir)
+        .unwrap()
+        .flatten()
+        .all(|entry| !entry.file_name().to_string_lossy().ends_with("shadow")));
+
+    shmap.transaction(&entries).unwrap();
+    for key in &keys {
+        assert_eq!(
+            shmap.get::<String>(key).unwrap(),
+            Some(format!("{key}-updated"))
+        );
+    }
+
+    // Make the rename of the second item fail, once the first one is renamed
+    let blocker = dir.join(sanitize_key(None, &keys[1]));
+    std::fs::remove_file(&blocker).unwrap();
+    std::fs::create_dir(&blocker).unwrap();
+    std::fs::write(blocker.join("file"), b"").unwrap();
+    let entries: Vec<(&str, String)> = keys
+        .iter()
+        .map(|key| (key.as_str(), format!("{key}-again")))
+        .collect();
+    match shmap.transaction(&entries) {
+        Err(ShmapError::PartialTransaction { committed, .. }) => {
+            assert_eq!(committed, vec![keys[0].clone()]);
+        }
+        ret => panic!("unexpected result {ret:?}"),
+    }
+    assert_eq!(
+        shmap.get::<String>(&keys[0]).unwrap(),
+        Some(format!("{}-again", keys[0]))
+    );
+    assert_eq!(
+        shmap.get::<String>(&keys[2]).unwrap(),
+        Some(format!("{}-updated", keys[2]))
+    );
+    // No shadow left behind
+    assert!(std::fs::read_dir(&dir)
+        .unwrap()
+        .flatten()
+        .all(|entry| !entry.file_name().to_string_lossy().ends_with("shadow")));
+}
+
+#[test]
+fn test_atomic_writes() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_atomic_writes(true)
+        .with_chunk_size(16)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    shmap.insert_raw(&key, b"original").unwrap();
+
+    // Make the write fail midway, before the value is replaced
+    let blocker = dir.join(format!("{}.shadow", sanitize_key(None, &key)));
+    std::fs::create_dir(&blocker).unwrap();
+    assert!(shmap.insert_raw(&key, b"updated").is_err());
+    assert_eq!(shmap.get_raw(&key).unwrap().unwrap(), b"original");
+    std::fs::remove_dir(&blocker).unwrap();
+
+    shmap.insert_raw(&key, b"updated").unwrap();
+    assert_eq!(shmap.get_raw(&key).unwrap().unwrap(), b"updated");
+    let value = rand_string(100);
+    shmap.insert(&key, &value).unwrap();
+    assert_eq!(shmap.get::<String>(&key).unwrap(), Some(value));
+    // No shadow left behind
+    assert!(std::fs::read_dir(&dir)
+        .unwrap()
+        .flatten()
+        .all(|entry| !entry.file_name().to_string_lossy().ends_with("shadow")));
+}
+
+#[test]
+fn test_open_fd() {
+    use std::os::unix::io::{AsRawFd, IntoRawFd};
+
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    assert!(shmap.open_fd(&key).unwrap().is_none());
+
+    shmap.insert_raw(&key, b"raw value").unwrap();
+    let fd = shmap.open_fd(&key).unwrap().unwrap();
+    // SAFETY: libc call is unsafe
+    assert_ne!(unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) }, -1);
+    // SAFETY: Mmap call is unsafe
+    let mmap = unsafe { Mmap::map(fd.as_raw_fd()) }.unwrap();
+    assert_eq!(&mmap[..], b"raw value");
+
+    // Dropping the Fd must not close the descriptor given away
+    let raw_fd = fd.into_raw_fd();
+    // SAFETY: libc call is unsafe
+    assert_ne!(unsafe { libc::fcntl(raw_fd, libc::F_GETFD) }, -1);
+    // SAFETY: libc call is unsafe
+    assert_eq!(unsafe { libc::close(raw_fd) }, 0);
+}
+
+#[test]
+fn test_open_fd_alignment() {
+    use std::os::unix::io::AsRawFd;
+
+    #[repr(C, align(64))]
+    struct Overlay {
+        id: u64,
+        data: [u8; 56],
+    }
+
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    let mut value = 42u64.to_ne_bytes().to_vec();
+    value.extend_from_slice(&[7u8; 56]);
+    shmap.insert_raw(&key, &value).unwrap();
+
+    let fd = shmap.open_fd(&key).unwrap().unwrap();
+    // SAFETY: Mmap call is unsafe
+    let mmap = unsafe { Mmap::map(fd.as_raw_fd()) }.unwrap();
+    assert_eq!(mmap.as_ptr().align_offset(64), 0);
+    assert_eq!(mmap.len(), size_of::<Overlay>());
+    // SAFETY: the mapping is aligned and large enough, and any bytes are a valid Overlay
+    #[allow(clippy::cast_ptr_alignment)]
+    let overlay = unsafe { &*mmap.as_ptr().cast::<Overlay>() };
+    assert_eq!(overlay.id, 42);
+    assert_eq!(overlay.data, [7u8; 56]);
+}
+
+#[test]
+fn test_raw_encryption_parts() {
+    use aes_gcm::{
+        aead::{Aead, Payload},
+        Aes256Gcm, KeyInit, Nonce,
+    };
+
+    init_logger();
+
+    let encryption_key = [7u8; 32];
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_encryption(&encryption_key)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let value = rand_string(50);
+    assert!(shmap.raw_encryption_parts(&key).unwrap().is_none());
+
+    shmap.insert(&key, value.clone()).unwrap();
+    let (nonce, ciphertext) = shmap.raw_encryption_parts(&key).unwrap().unwrap();
+    assert_eq!(nonce.len(), 12);
+
+    let cipher = Aes256Gcm::new_from_slice(&encryption_key).unwrap();
+    let sanitized_key = sanitize_key(None, &key);
+    let payload = Payload {
+        msg: &ciphertext,
+        aad: sanitized_key.as_bytes(),
+    };
+    let bytes = cipher.decrypt(Nonce::from_slice(&nonce), payload).unwrap();
+    let (decrypted, _): (String, _) =
+        bincode::serde::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    assert_eq!(decrypted, value);
+
+    let dir = tmp_dir();
+    let plaintext = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    plaintext.insert(&key, value).unwrap();
+    assert!(matches!(
+        plaintext.raw_encryption_parts(&key),
+        Err(ShmapError::NotEncrypted)
+    ));
+}
+
+#[test]
+fn test_migrate() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    assert!(!shmap.migrate(&key, |old: u32| old.to_string()).unwrap());
+
+    shmap
+        .insert_with_ttl(&key, 42u32, Duration::from_mins(1))
+        .unwrap();
+    let expiration = shmap.metadata(&key).unwrap().unwrap().expiration;
+    assert!(shmap.migrate(&key, |old: u32| old.to_string()).unwrap());
+    assert_eq!(shmap.get::<String>(&key).unwrap(), Some("42".to_string()));
+    assert_eq!(
+        shmap.metadata(&key).unwrap().unwrap().expiration,
+        expiration
+    );
+
+    // Expired items are not migrated
+    shmap
+        .insert_with_ttl(&key, 1u32, Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    assert!(!shmap.migrate(&key, |old: u32| old.to_string()).unwrap());
+}
+
+#[test]
+fn test_shm_filename() {
+    init_logger();
+
+    let builders = [
+        ShmapBuilder::new(),
+        ShmapBuilder::new().with_namespace("ns"),
+        ShmapBuilder::new().with_key_hash(KeyHash::Sha256),
+        ShmapBuilder::new().with_raw_keys(true),
+    ];
+    for builder in builders {
+        let dir = tmp_dir();
+        let shmap = builder.with_dir(&dir).build().unwrap();
+        let key = rand_string(20);
+        shmap.insert(&key, rand_string(50)).unwrap();
+
+        let mut filenames: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        filenames.sort();
+        let mut expected = vec![
+            shmap.shm_filename(&key).unwrap(),
+            shmap.shm_lock_filename(&key).unwrap(),
+            shmap.shm_metadata_filename(&key).unwrap(),
+        ];
+        expected.sort();
+        assert_eq!(filenames, expected);
+    }
+}
+
+#[test]
+fn test_insert_raw_named() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_namespace("named")
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let value = rand_string(50);
+
+    let filename = shmap.insert_raw_named(&key, value.as_bytes()).unwrap();
+    assert_eq!(filename, sanitize_key(Some("named"), &key));
+    assert_eq!(
+        std::fs::read(dir.join(&filename)).unwrap(),
+        value.as_bytes()
+    );
+}
+
+#[test]
+fn test_insert_arc() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    let value: Arc<[u8]> = Arc::from(rand_string(50).into_bytes());
+
+    shmap.insert_arc(&key, Arc::clone(&value)).unwrap();
+    assert_eq!(shmap.get_arc(&key).unwrap(), Some(value));
+    assert_eq!(shmap.keys().unwrap(), std::slice::from_ref(&key));
+    assert_eq!(shmap.get_arc(&rand_string(20)).unwrap(), None);
+
+    shmap.remove(&key).unwrap();
+    assert_eq!(shmap.get_arc(&key).unwrap(), None);
+}
+
+#[test]
+fn test_next_expiry() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    assert_eq!(shmap.next_expiry().unwrap(), None);
+    shmap.insert(&rand_string(20), 1).unwrap();
+    assert_eq!(shmap.next_expiry().unwrap(), None);
+
+    let soonest = rand_string(20);
+    shmap
+        .insert_with_ttl(&rand_string(20), 2, Duration::from_hours(1))
+        .unwrap();
+    shmap
+        .insert_with_ttl(&soonest, 3, Duration::from_mins(1))
+        .unwrap();
+    shmap
+        .insert_with_ttl(&rand_string(20), 4, Duration::from_mins(10))
+        .unwrap();
+    shmap
+        .insert_with_ttl(&rand_string(20), 5, Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    let expiration = shmap.metadata(&soonest).unwrap().unwrap().expiration;
+    assert!(expiration.is_some());
+    assert_eq!(shmap.next_expiry().unwrap(), expiration);
+}
+
+#[test]
+fn test_to_hashmap() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let expected = (0..5)
+        .map(|_| (rand_string(20), rand_string(50)))
+        .collect::<HashMap<_, _>>();
+    for (key, value) in &expected {
+        shmap.insert(key, value).unwrap();
+    }
+    shmap.insert(&rand_string(20), true).unwrap();
+    shmap
+        .insert_with_ttl(&rand_string(20), rand_string(50), Duration::from_millis(1))
+        .unwrap();
+    shmap.insert_raw(&rand_string(20), b"raw").unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    assert_eq!(shmap.to_hashmap::<String>().unwrap(), expected);
+    assert_eq!(shmap.to_hashmap::<bool>().unwrap().len(), 1);
+}
+
+#[test]
+fn test_get_raw_prefix() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let prefix = rand_string(10);
+    let live = format!("{prefix}:live");
+    let live_no_expiry = format!("{prefix}:live_no_expiry");
+    shmap
+        .insert_raw_with_ttl(&live, b"live", Duration::from_mins(1))
+        .unwrap();
+    shmap
+        .insert_raw_with_ttl(
+            &format!("{prefix}:expired"),
+            b"expired",
+            Duration::from_millis(1),
+        )
+        .unwrap();
+    shmap.insert(&live_no_expiry, 1).unwrap();
+    shmap
+        .insert_raw_with_ttl(&rand_string(20), b"other", Duration::from_mins(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    let mut entries = shmap.get_raw_prefix(&prefix).unwrap();
+    entries.sort();
+    assert_eq!(
+        entries,
+        vec![
+            (live, b"live".to_vec()),
+            (
+                live_no_expiry,
+                shmap
+                    .get_raw(&format!("{prefix}:live_no_expiry"))
+                    .unwrap()
+                    .unwrap()
+            ),
+        ]
+    );
+    assert_eq!(shmap.get_raw_prefix(&rand_string(10)).unwrap(), vec![]);
+}
+
+#[test]
+fn test_retain() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key_ttl = rand_string(20);
+    let key_no_ttl = rand_string(20);
+    shmap
+        .insert_with_ttl(&key_ttl, 1, Duration::from_mins(1))
+        .unwrap();
+    shmap.insert(&key_no_ttl, 2).unwrap();
+    shmap.insert(&rand_string(20), 3).unwrap();
+
+    let mut visited = 0;
+    let removed = shmap
+        .retain(|_, metadata| {
+            visited += 1;
+            metadata.expiration.is_some()
+        })
+        .unwrap();
+    assert_eq!(removed, 2);
+    assert_eq!(visited, 3);
+    assert_eq!(shmap.keys().unwrap(), vec![key_ttl.clone()]);
+    assert_eq!(shmap.get::<i32>(&key_ttl).unwrap(), Some(1));
+    assert_eq!(shmap.get::<i32>(&key_no_ttl).unwrap(), None);
+}
+
+#[test]
+fn test_warm() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_chunk_size(16)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let key_split = rand_string(20);
+    let key_raw = rand_string(20);
+    let key_absent = rand_string(20);
+    let value_split = rand_string(100);
+    shmap.insert(&key, 1).unwrap();
+    shmap.insert(&key_split, value_split.clone()).unwrap();
+    shmap.insert_raw(&key_raw, b"raw").unwrap();
+
+    shmap
+        .warm(&[&key, &key_split, &key_raw, &key_absent])
+        .unwrap();
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), Some(1));
+    assert_eq!(shmap.get::<String>(&key_split).unwrap(), Some(value_split));
+    assert_eq!(shmap.get_raw(&key_raw).unwrap(), Some(b"raw".to_vec()));
+    assert_eq!(shmap.get::<i32>(&key_absent).unwrap(), None);
+}
+
+#[test]
+fn test_for_each() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let mut expected = (0..10).map(|_| rand_string(20)).collect::<Vec<_>>();
+    for key in &expected {
+        shmap.insert(key, 1).unwrap();
+    }
+    shmap
+        .insert_with_ttl(&rand_string(20), 1, Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    let mut keys = Vec::new();
+    shmap
+        .for_each(|key, metadata| {
+            assert_eq!(key, metadata.key);
+            keys.push(key.to_owned());
+            Ok(())
+        })
+        .unwrap();
+    keys.sort();
+    expected.sort();
+    assert_eq!(keys, expected);
+
+    // Stops at the first error
+    let mut visited = 0;
+    let ret = shmap.for_each(|_, _| {
+        visited += 1;
+        if visited == 3 {
+            return Err(ShmapError::ValueTooLarge);
+        }
+        Ok(())
+    });
+    assert!(matches!(ret, Err(ShmapError::ValueTooLarge)));
+    assert_eq!(visited, 3);
+}
+
+#[test]
+fn test_keys_valid_for() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let no_ttl = rand_string(20);
+    let long = rand_string(20);
+    shmap.insert(&no_ttl, 1).unwrap();
+    shmap
+        .insert_with_ttl(&long, 1, Duration::from_hours(1))
+        .unwrap();
+    shmap
+        .insert_with_ttl(&rand_string(20), 1, Duration::from_secs(30))
+        .unwrap();
+    shmap
+        .insert_with_ttl(&rand_string(20), 1, Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    let mut keys = shmap.keys_valid_for(Duration::from_mins(1)).unwrap();
+    keys.sort();
+    let mut expected = vec![no_ttl.clone(), long];
+    expected.sort();
+    assert_eq!(keys, expected);
+    assert_eq!(shmap.keys_valid_for(Duration::ZERO).unwrap().len(), 3);
+    assert_eq!(shmap.keys_valid_for(Duration::MAX).unwrap(), vec![no_ttl]);
+}
+
+#[test]
+fn test_changed_since() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let reader = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let (start, keys) = reader.changed_since(0).unwrap();
+    assert_eq!((start, keys), (0, Vec::new()));
+
+    // Another user of the store, like another process
+    let writer_dir = dir.to_path_buf();
+    std::thread::spawn(move || {
+        let writer = ShmapBuilder::new()
+            .with_dir(writer_dir)
+            .with_change_log(4)
+            .build()
+            .unwrap();
+        writer.insert("a", 1).unwrap();
+        writer.insert("b", 2).unwrap();
+        writer.remove("a").unwrap();
+    })
+    .join()
+    .unwrap();
+
+    let (generation, keys) = reader.changed_since(start).unwrap();
+    assert_eq!(generation, 3);
+    assert_eq!(keys, vec!["b".to_owned(), "a".to_owned()]);
+    assert_eq!(reader.changed_since(generation).unwrap(), (3, Vec::new()));
+
+    // Other namespaces share the generation, not the keys
+    let namespaced = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_namespace("other")
+        .with_change_log(4)
+        .build()
+        .unwrap();
+    namespaced.insert("c", 3).unwrap();
+    assert_eq!(reader.changed_since(generation).unwrap(), (4, Vec::new()));
+    assert_eq!(
+        namespaced.changed_since(generation).unwrap(),
+        (4, vec!["c".to_owned()])
+    );
+
+    // The history is bounded
+    let writer = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_change_log(4)
+        .build()
+        .unwrap();
+    for key in ["d", "e", "f", "g"] {
+        writer.insert(key, 4).unwrap();
+    }
+    let (generation, keys) = reader.changed_since(start).unwrap();
+    assert_eq!(generation, 8);
+    assert_eq!(keys, vec!["d", "e", "f", "g"]);
+    assert!(reader
+        .keys()
+        .unwrap()
+        .iter()
+        .all(|key| key != "shmap.changes"));
+}
+
+#[test]
+fn test_keys_by_expiry() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let ttls = [
+        Duration::from_mins(10),
+        Duration::from_mins(1),
+        Duration::from_hours(1),
+        Duration::from_secs(5),
+    ];
+    let mut keys = Vec::new();
+    for ttl in ttls {
+        let key = rand_string(20);
+        shmap.insert_with_ttl(&key, 1, ttl).unwrap();
+        keys.push((key, ttl));
+    }
+    shmap.insert(&rand_string(20), 2).unwrap();
+
+    keys.sort_by_key(|(_, ttl)| *ttl);
+    let by_expiry = shmap.keys_by_expiry().unwrap();
+    assert_eq!(
+        by_expiry.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+        keys.iter().map(|(key, _)| key).collect::<Vec<_>>()
+    );
+    assert!(by_expiry.is_sorted_by_key(|(_, expiration)| *expiration));
+}
+
+#[test]
+fn test_ttl_histogram() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let buckets = [
+        Duration::from_secs(10),
+        Duration::from_mins(1),
+        Duration::from_hours(1),
+    ];
+    assert_eq!(shmap.ttl_histogram(&buckets).unwrap(), [0, 0, 0, 0, 0]);
+
+    let ttls = [
+        Duration::from_secs(5),
+        Duration::from_secs(30),
+        Duration::from_secs(40),
+        Duration::from_mins(10),
+        Duration::from_hours(2),
+    ];
+    for ttl in ttls {
+        shmap.insert_with_ttl(&rand_string(20), 1, ttl).unwrap();
+    }
+    shmap.insert(&rand_string(20), 2).unwrap();
+    shmap.insert(&rand_string(20), 3).unwrap();
+    // Expired items are not counted
+    shmap
+        .insert_with_ttl(&rand_string(20), 4, Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    assert_eq!(shmap.ttl_histogram(&buckets).unwrap(), [1, 2, 1, 1, 2]);
+    assert_eq!(shmap.ttl_histogram(&[]).unwrap(), [5, 2]);
+}
+
+#[test]
+fn test_debug_dump() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_chunk_size(4)
+        .build()
+        .unwrap();
+    assert_eq!(shmap.debug_dump().unwrap().lines().count(), 1);
+
+    shmap
+        .insert_raw_with_ttl("alpha", &[1; 10], Duration::from_hours(1))
+        .unwrap();
+    shmap.insert_raw_named("untracked", &[2; 3]).unwrap();
+    shmap.insert("beta", 3u8).unwrap();
+    let metadata_size = |key: &str| {
+        std::fs::metadata(dir.join(shmap.shm_metadata_filename(key).unwrap()))
+            .unwrap()
+            .len()
+    };
+
+    let dump = shmap.debug_dump().unwrap();
+    let lines = dump.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("key"));
+    let alpha = lines[1].split_whitespace().collect::<Vec<_>>();
+    assert_eq!(
+        alpha[..4],
+        ["alpha", "10", &metadata_size("alpha").to_string(), "no"]
+    );
+    let ttl = alpha[4].trim_end_matches('s').parse::<f64>().unwrap();
+    assert!(ttl > 3500.0 && ttl <= 3600.0);
+    assert_eq!(
+        lines[2].split_whitespace().collect::<Vec<_>>(),
+        ["beta", "1", &metadata_size("beta").to_string(), "no", "-"]
+    );
+}
+
+#[test]
+fn test_insert_with_ttl_prev() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+
+    let ttl = Duration::from_mins(1);
+    assert_eq!(shmap.insert_with_ttl_prev(&key, 1, ttl).unwrap(), None);
+    let before = Utc::now();
+    assert_eq!(shmap.insert_with_ttl_prev(&key, 2, ttl).unwrap(), Some(1));
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), Some(2));
+    let expiration = shmap.metadata(&key).unwrap().unwrap().expiration.unwrap();
+    assert!(
+        expiration >= before + ttl - Duration::from_millis(1) && expiration <= Utc::now() + ttl
+    );
+
+    // An expired value is not returned
+    let ttl = Duration::from_millis(50);
+    assert_eq!(shmap.insert_with_ttl_prev(&key, 3, ttl).unwrap(), Some(2));
+    std::thread::sleep(ttl * 2);
+    assert_eq!(shmap.insert_with_ttl_prev(&key, 4, ttl).unwrap(), None);
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), Some(4));
+
+    // The item is kept if the previous value is not a T
+    shmap.insert(&key, 2u8).unwrap();
+    assert!(shmap.insert_with_ttl_prev(&key, true, ttl).is_err());
+    assert_eq!(shmap.get::<u8>(&key).unwrap(), Some(2));
+}
+
+#[test]
+fn test_get_set() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    assert_eq!(shmap.get_set(&key, 0usize).unwrap(), None);
+    assert!(shmap.metadata(&key).unwrap().unwrap().expiration.is_none());
+
+    // Each swap gets the value of another one, so every value is seen exactly once
+    let handles = (1..=8usize)
+        .map(|i| {
+            let shmap = shmap.clone();
+            let key = key.clone();
+            std::thread::spawn(move || {
+                (0..20)
+                    .map(|j| shmap.get_set(&key, i * 100 + j).unwrap().unwrap())
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut seen = Vec::new();
+    for handle in handles {
+        seen.extend(handle.join().unwrap());
+    }
+    seen.push(shmap.get::<usize>(&key).unwrap().unwrap());
+    seen.sort_unstable();
+    let mut expected = (1..=8)
+        .flat_map(|i| (0..20).map(move |j| i * 100 + j))
+        .collect::<Vec<_>>();
+    expected.push(0);
+    expected.sort_unstable();
+    assert_eq!(seen, expected);
+
+    // The TTL is kept
+    shmap
+        .insert_with_ttl(&key, 1usize, Duration::from_mins(1))
+        .unwrap();
+    let expiration = shmap.metadata(&key).unwrap().unwrap().expiration;
+    assert_eq!(shmap.get_set(&key, 2usize).unwrap(), Some(1));
+    assert_eq!(shmap.get::<usize>(&key).unwrap(), Some(2));
+    assert_eq!(
+        shmap.metadata(&key).unwrap().unwrap().expiration,
+        expiration
+    );
+}
+
+#[test]
+fn test_get_or_insert_with_ttl_concurrency() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    let computed = Arc::new(AtomicUsize::new(0));
+
+    let ttl = Duration::from_mins(1);
+    let before = Utc::now();
+    let handles = (0..8)
+        .map(|_| {
+            let shmap = shmap.clone();
+            let key = key.clone();
+            let computed = computed.clone();
+            std::thread::spawn(move || {
+                shmap
+                    .get_or_insert_with_ttl(&key, ttl, || {
+                        computed.fetch_add(1, Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(50));
+                        42
+                    })
+                    .unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), 42);
+    }
+    assert_eq!(computed.load(Ordering::SeqCst), 1);
+    let expiration = shmap.metadata(&key).unwrap().unwrap().expiration.unwrap();
+    assert!(expiration >= before + ttl - Duration::from_millis(1));
+    assert!(expiration <= Utc::now() + ttl);
+
+    // Computed again once expired
+    let ttl = Duration::from_millis(50);
+    let key = rand_string(20);
+    assert_eq!(shmap.get_or_insert_with_ttl(&key, ttl, || 1).unwrap(), 1);
+    assert_eq!(shmap.get_or_insert_with_ttl(&key, ttl, || 2).unwrap(), 1);
+    std::thread::sleep(ttl * 2);
+    assert_eq!(shmap.get_or_insert_with_ttl(&key, ttl, || 3).unwrap(), 3);
+}
+
+// best-effort: concurrent tests only insert small items
+#[test]
+fn test_available_bytes() {
+    init_logger();
+
+    let shmap = Shmap::new();
+    let key = rand_string(20);
+    let len: usize = 16 << 20;
+
+    let before = shmap.available_bytes().unwrap();
+    let len_bytes = u64::try_from(len).unwrap();
+    assert!(before > len_bytes);
+    shmap.insert_raw(&key, &vec![1; len]).unwrap();
+    let after = shmap.available_bytes().unwrap();
+    shmap.remove(&key).unwrap();
+    assert!(before - after >= len_bytes / 2);
+
+    assert!(matches!(
+        ShmapBuilder::new()
+            .with_dir("/nonexistent")
+            .build()
+            .unwrap()
+            .available_bytes(),
+        Err(ShmapError::SyscallError {
+            syscall: "statvfs",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_ephemeral() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_encryption(&[5u8; 32])
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let value = rand_string(50);
+    let sanitized_key = sanitize_key(None, &key);
+
+    // A previous item with metadata is replaced
+    shmap
+        .insert_with_ttl(&key, 1, Duration::from_mins(1))
+        .unwrap();
+    shmap.insert_ephemeral(&key, &value).unwrap();
+    assert!(!dir.join(&sanitized_key).exists());
+    assert!(!dir.join(format!("{sanitized_key}.metadata")).exists());
+    assert_eq!(shmap.get_ephemeral::<String>(&key).unwrap(), Some(value));
+    assert_eq!(shmap.get::<String>(&key).unwrap(), None);
+    assert!(shmap.keys().unwrap().is_empty());
+
+    // Items with metadata are read too
+    let other_key = rand_string(20);
+    shmap.insert(&other_key, 1).unwrap();
+    assert_eq!(shmap.get_ephemeral::<i32>(&other_key).unwrap(), Some(1));
+
+    // Kept while fresh, then removed once not written for the grace period, without being
+    // counted as an orphan
+    let path = dir.join(format!("{sanitized_key}.eph"));
+    shmap.clean().unwrap();
+    assert!(path.exists());
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - Duration::from_secs(10))
+        .unwrap();
+    let report = shmap.clean_limited(usize::MAX, Duration::MAX).unwrap();
+    assert_eq!(report.ephemeral, 1);
+    assert_eq!(report.orphan_items, 0);
+    assert!(!path.exists());
+    assert_eq!(shmap.get_ephemeral::<String>(&key).unwrap(), None);
+
+    // Removed with the item
+    shmap.insert_ephemeral(&key, &2).unwrap();
+    shmap.remove(&key).unwrap();
+    assert_eq!(shmap.get_ephemeral::<i32>(&key).unwrap(), None);
+}
+
+#[test]
+fn test_push_concurrency() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    assert_eq!(shmap.pop_back::<u32>(&key).unwrap(), None);
+    assert_eq!(shmap.get::<Vec<u32>>(&key).unwrap(), None);
+
+    let mut handles = Vec::new();
+    for thread in 0..8 {
+        let shmap = shmap.clone();
+        let key = key.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..50 {
+                shmap.push(&key, thread * 100 + i).unwrap();
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let mut list = shmap.get::<Vec<u32>>(&key).unwrap().unwrap();
+    assert_eq!(list.len(), 8 * 50);
+    let pushed_last = *list.last().unwrap();
+    assert_eq!(shmap.pop_back::<u32>(&key).unwrap(), Some(pushed_last));
+    assert_eq!(shmap.push(&key, 1000).unwrap(), 8 * 50);
+
+    list.sort_unstable();
+    list.dedup();
+    assert_eq!(list.len(), 8 * 50);
+}
+
+#[test]
+fn test_counter() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    let counter = shmap.counter(&key).unwrap();
+    assert_eq!(counter.key(), key);
+    assert_eq!(counter.get().unwrap(), 0);
+    assert_eq!(shmap.get::<i64>(&key).unwrap(), None);
+
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                let counter = shmap.counter(&key).unwrap();
+                for _ in 0..50 {
+                    counter.inc().unwrap();
+                }
+                for _ in 0..10 {
+                    counter.dec().unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(counter.get().unwrap(), 8 * 40);
+    assert_eq!(shmap.get::<i64>(&key).unwrap(), Some(8 * 40));
+
+    counter.set(i64::MAX).unwrap();
+    assert_eq!(counter.inc().unwrap(), i64::MIN);
+    assert_eq!(counter.add(5).unwrap(), i64::MIN + 5);
+    assert_eq!(counter.reset().unwrap(), i64::MIN + 5);
+    assert_eq!(counter.get().unwrap(), 0);
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_get_bytes_shared() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    let value = rand_string(50);
+    assert_eq!(shmap.get_bytes_shared(&key).unwrap(), None);
+
+    shmap.insert_raw(&key, value.as_bytes()).unwrap();
+    let bytes = shmap.get_bytes_shared(&key).unwrap().unwrap();
+    assert_eq!(bytes, value.as_bytes());
+    // Clones share the buffer
+    assert_eq!(bytes.clone().as_ptr(), bytes.as_ptr());
+
+    // The buffer does not change with the item
+    shmap.insert_raw(&key, b"other").unwrap();
+    assert_eq!(bytes, value.as_bytes());
+}
+
+#[test]
+fn test_time() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    assert_eq!(shmap.get_time(&key).unwrap(), None);
+
+    let millis = 1_700_000_000_123;
+    let time = std::time::UNIX_EPOCH + Duration::from_millis(millis);
+    shmap
+        .insert_time(&key, time + Duration::from_micros(456))
+        .unwrap();
+    assert_eq!(shmap.get_time(&key).unwrap(), Some(time));
+    let bytes = shmap.get_raw(&key).unwrap().unwrap();
+    let (stored, _): (i64, usize) =
+        bincode::serde::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    assert_eq!(stored, i64::try_from(millis).unwrap());
+
+    let before_epoch = std::time::UNIX_EPOCH - Duration::from_hours(24);
+    shmap.insert_time(&key, before_epoch).unwrap();
+    assert_eq!(shmap.get_time(&key).unwrap(), Some(before_epoch));
+    assert_eq!(shmap.get::<i64>(&key).unwrap(), Some(-86_400_000));
+}
+
+#[test]
+fn test_all_keys() {
+    init_logger();
+
+    let namespaces = [rand_string(10), rand_string(10)];
+    let key = rand_string(20);
+    let shmaps = namespaces.each_ref().map(|namespace| {
+        ShmapBuilder::new()
+            .with_namespace(namespace)
+            .build()
+            .unwrap()
+    });
+    for shmap in &shmaps {
+        shmap.insert(&key, 1).unwrap();
+    }
+    let unnamed_key = rand_string(20);
+    let unnamed = Shmap::new();
+    unnamed.insert(&unnamed_key, 1).unwrap();
+
+    let all_keys = Shmap::all_keys().unwrap();
+    for namespace in &namespaces {
+        assert!(all_keys.contains(&(namespace.clone(), key.clone())));
+    }
+    assert!(all_keys.contains(&(String::new(), unnamed_key.clone())));
+
+    for shmap in &shmaps {
+        shmap.remove(&key).unwrap();
+    }
+    unnamed.remove(&unnamed_key).unwrap();
+
+    // Expired items are skipped, but not cleaned, in a directory of their own not to be cleaned
+    // by other tests
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_namespace(&namespaces[0])
+        .build()
+        .unwrap();
+    shmap.insert(&key, 1).unwrap();
+    shmap
+        .insert_with_ttl(&key.repeat(2), 1, Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    assert_eq!(
+        all_namespaces_keys(&dir).unwrap(),
+        vec![(namespaces[0].clone(), key.clone())]
+    );
+    assert!(dir
+        .join(sanitize_key(Some(&namespaces[0]), &key.repeat(2)))
+        .exists());
+}
+
+#[test]
+fn test_empty_read_retries() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_empty_read_retries(5)
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let value = rand_string(50);
+    shmap.insert_raw(&key, value.as_bytes()).unwrap();
+
+    // Rewrite the file without taking the lock, leaving it empty for a while
+    let path = dir.join(sanitize_key(None, &key));
+    let (truncated_tx, truncated_rx) = std::sync::mpsc::channel();
+    let writer = std::thread::spawn({
+        let value = value.clone();
+        move || {
+            let mut file = std::fs::File::create(path).unwrap();
+            truncated_tx.send(()).unwrap();
+            std::thread::sleep(Duration::from_millis(3));
+            file.write_all(value.as_bytes()).unwrap();
+        }
+    });
+    truncated_rx.recv().unwrap();
+    assert_eq!(shmap.get_raw(&key).unwrap(), Some(value.into_bytes()));
+    writer.join().unwrap();
+
+    // Without retries, an empty item is removed right away
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_empty_read_retries(0)
+        .build()
+        .unwrap();
+    std::fs::File::create(dir.join(sanitize_key(None, &key))).unwrap();
+    assert_eq!(shmap.get_raw(&key).unwrap(), None);
+    assert!(!dir.join(sanitize_key(None, &key)).exists());
+}
+
+#[test]
+fn test_expire_prefix() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let user_keys: Vec<String> = (0..3).map(|i| format!("user:42:{i}")).collect();
+    for key in &user_keys {
+        shmap
+            .insert_with_ttl(key, rand_string(10), Duration::from_secs(10))
+            .unwrap();
+    }
+    shmap
+        .insert_with_ttl("user:43:0", 1, Duration::from_secs(10))
+        .unwrap();
+    shmap
+        .insert_with_ttl("user:42:expired", 1, Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    assert_eq!(
+        shmap
+            .expire_prefix("user:42:", Duration::from_hours(1))
+            .unwrap(),
+        3
+    );
+    for key in &user_keys {
+        let remaining = shmap.metadata(key).unwrap().unwrap().expiration.unwrap() - Utc::now();
+        assert!(remaining > chrono::Duration::minutes(59));
+        assert!(shmap.get::<String>(key).unwrap().is_some());
+    }
+    let remaining = shmap
+        .metadata("user:43:0")
+        .unwrap()
+        .unwrap()
+        .expiration
+        .unwrap()
+        - Utc::now();
+    assert!(remaining <= chrono::Duration::seconds(10));
+    assert_eq!(shmap.get::<i32>("user:42:expired").unwrap(), None);
+}
+
+#[test]
+fn test_pop() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    let value = rand_string(50);
+    assert_eq!(shmap.pop::<String>(&key).unwrap(), None);
+
+    shmap.insert(&key, value.clone()).unwrap();
+    let barrier = std::sync::Arc::new(std::sync::Barrier::new(8));
+    let mut handles = Vec::new();
+    for _ in 0..8 {
+        let shmap = shmap.clone();
+        let key = key.clone();
+        let barrier = barrier.clone();
+        handles.push(std::thread::spawn(move || {
+            barrier.wait();
+            shmap.pop::<String>(&key).unwrap()
+        }));
+    }
+    let mut popped = Vec::new();
+    for handle in handles {
+        popped.extend(handle.join().unwrap());
+    }
+    assert_eq!(popped, vec![value]);
+    assert_eq!(shmap.get::<String>(&key).unwrap(), None);
+    assert!(shmap.metadata(&key).unwrap().is_none());
+}
+
+#[test]
+fn test_expect_get() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    shmap.insert(&key, rand_string(10)).unwrap();
+    assert_eq!(
+        shmap.expect_get::<String>(&key),
+        shmap.get::<String>(&key).unwrap().unwrap()
+    );
+
+    let missing = rand_string(20);
+    let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        shmap.expect_get::<String>(&missing)
+    }))
+    .unwrap_err();
+    assert_eq!(
+        panic.downcast_ref::<String>().unwrap(),
+        &format!("shmap item <{missing}> not found")
+    );
+}
+
+#[test]
+fn test_get_full() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_encryption(&[6u8; 32])
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+    let value = (rand_string(50), 42u64);
+    assert!(shmap.get_full::<(String, u64)>(&key).unwrap().is_none());
+
+    shmap.insert(&key, &value).unwrap();
+    let (got, bytes) = shmap.get_full::<(String, u64)>(&key).unwrap().unwrap();
+    assert_eq!(got, value);
+    let (decoded, _): ((String, u64), usize) =
+        bincode::serde::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+    assert_eq!(decoded, value);
+}
+
+#[test]
+fn test_bytes_serde() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    let value: Vec<u8> = (0..=u8::MAX).cycle().take(1000).collect();
+    assert_eq!(shmap.get_bytes_serde(&key).unwrap(), None);
+
+    shmap.insert_bytes_serde(&key, &value).unwrap();
+    // A 3-byte varint length, then the bytes as is
+    let stored = std::fs::read(dir.join(sanitize_key(None, &key))).unwrap();
+    assert_eq!(stored.len(), 3 + value.len());
+    assert_eq!(&stored[3..], &value[..]);
+    assert_eq!(shmap.get_bytes_serde(&key).unwrap(), Some(value.clone()));
+    assert_eq!(shmap.get::<Vec<u8>>(&key).unwrap(), Some(value.clone()));
+
+    shmap.insert(&key, &value[..10]).unwrap();
+    assert_eq!(
+        shmap.get_bytes_serde(&key).unwrap(),
+        Some(value[..10].to_vec())
+    );
+}
+
+#[test]
+fn test_insert_if_absent() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new()
+        .with_dir(&dir)
+        .with_encryption(&[4u8; 32])
+        .build()
+        .unwrap();
+    let key = rand_string(20);
+
+    assert!(shmap.insert_if_absent(&key, 1).unwrap());
+    assert!(!shmap.insert_if_absent(&key, 2).unwrap());
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), Some(1));
+
+    // Expired items are replaced
+    shmap
+        .insert_with_ttl(&key, 3, Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    assert!(shmap.insert_if_absent(&key, 4).unwrap());
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), Some(4));
+
+    // Values without metadata are not
+    let raw_key = rand_string(20);
+    shmap.insert_raw(&raw_key, b"raw").unwrap();
+    assert!(!shmap.insert_if_absent(&raw_key, 5).unwrap());
+    assert_eq!(shmap.get_raw(&raw_key).unwrap(), Some(b"raw".to_vec()));
+}
+
+// Processes racing to insert the same key, only one of them must succeed
+#[test]
+fn test_insert_if_absent_processes() {
+    let Some(dir) = std::env::var_os("SHMAP_TEST_INSERT_IF_ABSENT") else {
+        let dir = tmp_dir();
+        let children = (0..8)
+            .map(|_| {
+                std::process::Command::new(std::env::current_exe().unwrap())
+                    .args(["--exact", "tests::map::test_insert_if_absent_processes"])
+                    .env("SHMAP_TEST_INSERT_IF_ABSENT", &*dir)
+                    .stdout(std::process::Stdio::null())
+                    .spawn()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        for mut child in children {
+            assert!(child.wait().unwrap().success());
+        }
+        let winners = std::fs::read_dir(&dir)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter_map(|filename| filename.strip_prefix("winner.")?.parse::<u32>().ok())
+            .collect::<Vec<_>>();
+        assert_eq!(winners.len(), 1);
+        let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+        assert_eq!(shmap.get::<u32>("key").unwrap(), Some(winners[0]));
+        return;
+    };
+    let dir = PathBuf::from(dir);
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    if shmap.insert_if_absent("key", std::process::id()).unwrap() {
+        std::fs::write(dir.join(format!("winner.{}", std::process::id())), b"").unwrap();
+    }
+}
+
+#[test]
+fn test_remove_if() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    let owner = rand_string(10);
+    assert!(!shmap.remove_if(&key, &owner).unwrap());
+
+    shmap.insert(&key, owner.clone()).unwrap();
+    assert!(!shmap.remove_if(&key, &rand_string(10)).unwrap());
+    assert_eq!(shmap.get::<String>(&key).unwrap(), Some(owner.clone()));
+
+    assert!(shmap.remove_if(&key, &owner).unwrap());
+    assert_eq!(shmap.get::<String>(&key).unwrap(), None);
+    assert!(shmap.metadata(&key).unwrap().is_none());
+    assert!(!shmap.remove_if(&key, &owner).unwrap());
+}
+
+#[test]
+fn test_bytes_key() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = [0xff, 0xfe, 0x00, 0x01];
+
+    shmap.insert_bytes_key(&key, 42).unwrap();
+    assert_eq!(shmap.get_bytes_key::<i32>(&key).unwrap(), Some(42));
+    assert_eq!(shmap.keys().unwrap(), vec!["fffe0001".to_string()]);
+    // The hex encoding is only listed, it is not the same key
+    assert_eq!(shmap.get::<i32>("fffe0001").unwrap(), None);
+
+    shmap.remove_bytes_key(&key).unwrap();
+    assert_eq!(shmap.get_bytes_key::<i32>(&key).unwrap(), None);
+
+    // UTF-8 keys are the same as string ones
+    shmap.insert("utf8", 1).unwrap();
+    assert_eq!(shmap.get_bytes_key::<i32>(b"utf8").unwrap(), Some(1));
+}
+
+#[test]
+fn test_structured_logs() {
+    init_logger();
+
+    let dir = tmp_dir();
+    let shmap = ShmapBuilder::new().with_dir(&dir).build().unwrap();
+    let key = rand_string(20);
+    shmap
+        .insert_with_ttl(&key, 1, Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+
+    let logs = capture_logs(|| {
+        shmap.clean().unwrap();
+    });
+    let sanitized_key = sanitize_key(None, &key);
+    let log = logs
+        .iter()
+        .find(|log| log.message.contains("expired"))
+        .unwrap();
+    assert_eq!(log.target, "shmap");
+    assert!(log
+        .fields
+        .contains(&("key".to_string(), sanitized_key.clone())));
+    assert!(log
+        .fields
+        .contains(&("op".to_string(), "clean".to_string())));
+    assert!(!log.message.contains(&sanitized_key));
+}
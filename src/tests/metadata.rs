@@ -0,0 +1,107 @@
+use crate::{
+    errors::SerializationError,
+    map::{encode_metadata, sanitize_key},
+    metadata::Metadata,
+    shm::{self, SHM_DIR},
+    tests::map::{init_logger, rand_string},
+    Shmap, ShmapError, SystemClock,
+};
+use chrono::{DateTime, Utc};
+use memmap2::Mmap;
+use std::{path::Path, time::Duration};
+
+#[test]
+fn test_metadata_ttl_out_of_range() {
+    // Fits in a chrono duration, but not once added to now
+    let ttl = Duration::from_secs(i64::MAX.unsigned_abs() / 1000);
+    assert!(matches!(
+        Metadata::new("key", None, Some(ttl), false, &SystemClock),
+        Err(ShmapError::DurationOutOfRangeError)
+    ));
+    assert!(matches!(
+        Metadata::new("key", None, Some(Duration::MAX), false, &SystemClock),
+        Err(ShmapError::DurationOutOfRangeError)
+    ));
+}
+
+// The check is only made in debug builds
+#[cfg(debug_assertions)]
+#[test]
+fn test_metadata_round_trip_check() {
+    let mut metadata = Metadata::new("key", None, None, false, &SystemClock).unwrap();
+    assert!(encode_metadata("key.metadata", &metadata).is_ok());
+
+    // Only milliseconds are stored
+    metadata.expiration = DateTime::from_timestamp(0, 1);
+    assert!(matches!(
+        encode_metadata("key.metadata", &metadata),
+        Err(ShmapError::Serialization {
+            source: SerializationError::InvalidMetadata(_),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_metadata_round_trip() {
+    let metadata = Metadata::new("key", None, None, false, &SystemClock).unwrap();
+    assert_eq!(
+        Metadata::decode(&metadata.encode().unwrap()).unwrap(),
+        metadata
+    );
+
+    let metadata = Metadata::new(
+        "key",
+        Some("namespace"),
+        Some(Duration::from_secs(10)),
+        true,
+        &SystemClock,
+    )
+    .unwrap();
+    assert_eq!(
+        Metadata::decode(&metadata.encode().unwrap()).unwrap(),
+        metadata
+    );
+
+    let mut metadata = metadata;
+    metadata.record_access(Utc::now());
+    metadata.record_access(Utc::now());
+    metadata.parts = 3;
+    metadata.binary_key = true;
+    metadata.len = 1 << 33;
+    metadata.type_name = Some("alloc::string::String".to_owned());
+    metadata.creator_pid = Some(42);
+    metadata.owned = true;
+    assert_eq!(
+        Metadata::decode(&metadata.encode().unwrap()).unwrap(),
+        metadata
+    );
+
+    let bytes = metadata.encode().unwrap();
+    assert!(Metadata::decode(&bytes[..bytes.len() - 1]).is_err());
+}
+
+// test the size of the metadata file of a typical entry
+#[test]
+fn test_metadata_size() {
+    init_logger();
+
+    let shmap = Shmap::new();
+    let key = rand_string(20);
+
+    shmap
+        .insert_with_ttl(&key, rand_string(10), Duration::from_secs(10))
+        .unwrap();
+    let metadata_name = format!("{}.metadata", sanitize_key(None, &key));
+    let fd = shm::open_read(Path::new(SHM_DIR), &metadata_name).unwrap();
+    // SAFETY: Mmap call is unsafe
+    let mmap = unsafe { Mmap::map(fd) }.unwrap();
+    // Version, flags, expiration, key length, key, then boot id and monotonic expiration
+    assert_eq!(mmap.len(), 1 + 1 + 8 + 4 + 20 + 16 + 8);
+
+    let (_, _, metadata) = shmap.dump_entries(&[&key]).unwrap().remove(0);
+    assert_eq!(metadata.key, key);
+    assert!(metadata.expiration.is_some());
+
+    shmap.remove(&key).unwrap();
+}
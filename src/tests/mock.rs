@@ -0,0 +1,94 @@
+use crate::tests::map::rand_string;
+use crate::{MemoryShmap, Shmap, ShmapBuilder, ShmapError};
+use std::time::Duration;
+
+#[test]
+fn test_mock_insert_get_remove() {
+    let shmap = MemoryShmap::new();
+    let key = rand_string(20);
+    let value = rand_string(50);
+
+    assert_eq!(shmap.get::<String>(&key).unwrap(), None);
+    shmap.insert(&key, value.clone()).unwrap();
+    assert_eq!(shmap.get::<String>(&key).unwrap(), Some(value));
+    // Shared by all the instances of the process, but not with shm files
+    assert!(MemoryShmap::new().keys().unwrap().contains(&key));
+    assert_eq!(Shmap::new().get::<String>(&key).unwrap(), None);
+
+    shmap.insert_raw(&key, b"raw").unwrap();
+    assert_eq!(shmap.get_raw(&key).unwrap(), Some(b"raw".to_vec()));
+
+    shmap.remove(&key).unwrap();
+    assert_eq!(shmap.get_raw(&key).unwrap(), None);
+    assert!(!shmap.keys().unwrap().contains(&key));
+}
+
+#[test]
+fn test_mock_ttl() {
+    let shmap = ShmapBuilder::new()
+        .with_default_ttl(Duration::from_mins(1))
+        .build_in_memory()
+        .unwrap();
+    let key = rand_string(20);
+    let key_no_expiry = rand_string(20);
+    let key_ttl = rand_string(20);
+
+    shmap.insert(&key, 1).unwrap();
+    shmap.insert_no_expiry(&key_no_expiry, 2).unwrap();
+    shmap
+        .insert_with_ttl(&key_ttl, 3, Duration::from_millis(1))
+        .unwrap();
+    assert!(shmap.metadata(&key).unwrap().unwrap().expiration.is_some());
+    assert!(shmap
+        .metadata(&key_no_expiry)
+        .unwrap()
+        .unwrap()
+        .expiration
+        .is_none());
+
+    std::thread::sleep(Duration::from_millis(10));
+    let keys = shmap.clean().unwrap();
+    assert!(keys.contains(&key));
+    assert!(keys.contains(&key_no_expiry));
+    assert!(!keys.contains(&key_ttl));
+    assert_eq!(shmap.metadata(&key_ttl).unwrap(), None);
+
+    shmap
+        .insert_with_ttl(&key_ttl, 3, Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    assert_eq!(shmap.get::<i32>(&key_ttl).unwrap(), None);
+}
+
+#[test]
+fn test_mock_encryption() {
+    let shmap = MemoryShmap::new_with_encryption(&[1u8; 32]);
+    let key = rand_string(20);
+    let value = rand_string(50);
+
+    shmap.insert(&key, value.clone()).unwrap();
+    assert!(shmap.metadata(&key).unwrap().unwrap().encrypted);
+    assert_eq!(shmap.get::<String>(&key).unwrap(), Some(value));
+    assert_ne!(shmap.get_raw(&key).unwrap(), None);
+
+    assert!(matches!(
+        MemoryShmap::new_with_encryption(&[2u8; 32]).get::<String>(&key),
+        Err(ShmapError::AesGcmError(_))
+    ));
+    assert!(MemoryShmap::new().get::<String>(&key).is_err());
+}
+
+#[test]
+fn test_mock_namespace() {
+    let shmap = ShmapBuilder::new()
+        .with_namespace("mock")
+        .build_in_memory()
+        .unwrap();
+    let key = rand_string(20);
+
+    shmap.insert(&key, 1).unwrap();
+    assert_eq!(shmap.get::<i32>(&key).unwrap(), Some(1));
+    assert_eq!(MemoryShmap::new().get::<i32>(&key).unwrap(), None);
+    assert!(shmap.keys().unwrap().contains(&key));
+    assert!(!MemoryShmap::new().keys().unwrap().contains(&key));
+}
@@ -1 +1,8 @@
+pub mod builder;
+pub mod core;
+pub mod lock;
 pub mod map;
+pub mod metadata;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod shm;
@@ -0,0 +1,157 @@
+use crate::{
+    shm::{self, SHM_DIR},
+    tests::map::{rand_string, tmp_dir},
+    ShmapError,
+};
+use std::{ffi::CString, os::unix::ffi::OsStrExt, path::Path, process::Command};
+
+#[test]
+fn test_open_write_too_large() {
+    let name = format!("shmap.{}", rand_string(20));
+
+    let ret = shm::open_write(Path::new(SHM_DIR), &name, usize::MAX, false);
+    assert!(matches!(ret, Err(ShmapError::ValueTooLarge)));
+
+    // Nothing must have been created
+    assert!(matches!(
+        shm::open_read(Path::new(SHM_DIR), &name),
+        Err(ShmapError::ShmFileNotFound)
+    ));
+}
+
+// best-effort: /dev/shm is assumed to be smaller than 1 PiB
+#[test]
+fn test_open_write_out_of_space() {
+    let name = format!("shmap.{}", rand_string(20));
+
+    let ret = shm::open_write(Path::new(SHM_DIR), &name, 1 << 50, true);
+    assert!(matches!(ret, Err(ShmapError::OutOfSpace)));
+    shm::unlink(Path::new(SHM_DIR), &name).unwrap();
+
+    // Reasonable sizes are still allocated
+    let fd = shm::open_write(Path::new(SHM_DIR), &name, 4096, true).unwrap();
+    drop(fd);
+    shm::unlink(Path::new(SHM_DIR), &name).unwrap();
+}
+
+#[test]
+fn test_open_create_exclusive() {
+    let dir = tmp_dir();
+    let name = format!("shmap.{}", rand_string(20));
+
+    drop(shm::open_create_exclusive(&dir, &name, 16, false).unwrap());
+    assert_eq!(std::fs::metadata(dir.join(&name)).unwrap().len(), 16);
+    assert!(matches!(
+        shm::open_create_exclusive(&dir, &name, 16, false),
+        Err(ShmapError::ShmFileExists)
+    ));
+
+    // The created file is unlinked if it can not be sized, best-effort like
+    // `test_open_write_out_of_space`
+    let name = format!("shmap.{}", rand_string(20));
+    assert!(shm::open_create_exclusive(&dir, &name, 1 << 50, true).is_err());
+    assert!(!dir.join(&name).exists());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_syscall_error() {
+    let dir = tmp_dir();
+    let name = format!("shmap.{}", rand_string(20));
+
+    // A FIFO can be opened for writing, but not truncated
+    let path = CString::new(dir.join(&name).as_os_str().as_bytes()).unwrap();
+    // SAFETY: libc call is unsafe
+    let ret = unsafe { libc::mkfifo(path.as_ptr(), 0o600) };
+    assert_eq!(ret, 0);
+
+    let err = shm::open_write(&dir, &name, 1, false).unwrap_err();
+    assert!(matches!(
+        err,
+        ShmapError::SyscallError {
+            syscall: "ftruncate",
+            ..
+        }
+    ));
+    assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+    assert!(err.to_string().starts_with("ftruncate failed: EINVAL: "));
+
+    // The failing syscall of an open is reported too
+    let err = shm::open_write(&dir.join("missing"), &name, 1, false).unwrap_err();
+    assert!(err.to_string().starts_with("open failed: ENOENT: "));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+fn getrlimit() -> libc::rlimit {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: libc call is unsafe
+    let ret = unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &raw mut rlim) };
+    assert_eq!(ret, 0);
+    rlim
+}
+
+// The fd limit is process-wide: it is lowered in child processes, running only this test
+#[test]
+fn test_open_emfile() {
+    let Some(lower_hard) = std::env::var_os("SHMAP_TEST_EMFILE") else {
+        for lower_hard in ["0", "1"] {
+            let status = Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "tests::shm::test_open_emfile"])
+                .env("SHMAP_TEST_EMFILE", lower_hard)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+        return;
+    };
+    let lower_hard = lower_hard == "1";
+    let name = format!("shmap.{}", rand_string(20));
+
+    // The hard limit may already be low, inherited from a process where another test lowered it
+    let rlim = getrlimit();
+    let limit = rlim.rlim_max.min(64);
+    let lowered = if lower_hard {
+        libc::rlimit {
+            rlim_cur: limit,
+            rlim_max: limit,
+        }
+    } else {
+        libc::rlimit {
+            rlim_cur: limit - 1,
+            rlim_max: rlim.rlim_max,
+        }
+    };
+    // SAFETY: libc call is unsafe
+    let ret = unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &raw const lowered) };
+    assert_eq!(ret, 0);
+
+    // Exhaust the descriptors
+    let mut fds = Vec::new();
+    loop {
+        // SAFETY: libc call is unsafe
+        let fd = unsafe { libc::dup(0) };
+        if fd < 0 {
+            break;
+        }
+        fds.push(fd);
+    }
+    let ret = shm::open_write(Path::new(SHM_DIR), &name, 1, false);
+    for fd in fds {
+        // SAFETY: libc call is unsafe
+        unsafe { libc::close(fd) };
+    }
+
+    if lower_hard {
+        assert!(matches!(ret, Err(ShmapError::TooManyOpenFiles)));
+    } else {
+        // Retried once the limit was raised back
+        drop(ret.unwrap());
+        shm::unlink(Path::new(SHM_DIR), &name).unwrap();
+        assert_eq!(getrlimit().rlim_cur, rlim.rlim_max);
+    }
+}